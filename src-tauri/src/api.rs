@@ -0,0 +1,20 @@
+//! the compute pipeline as a library, for running jobs without the tauri app
+//!
+//! implement `JobContext` to receive progress and signal cancellation, point `set_binary_paths`
+//! at ffmpeg, then build a `ProcessClipsJob` from a directory of clips and run its stages on it
+//!
+//! build with `default-features = false` to leave out the app and its tauri dependencies
+
+pub use crate::{
+    compute::{
+        check_first_clip, check_timestamps, BatchExtraction, ChapterMode, CoverageOptions,
+        CoveragePeriod, Crop, DateRange, EmptyTimeline, ExportFormat, GapPolicy, GlyphField,
+        LatLng, Overlays, ProcessClipsJob, ScrapedFields, SpriteSheetOptions, TimelapseSettings,
+        TimelapseType, TimelineOptions, TimestampReport,
+    },
+    ffmpeg::{
+        set_binary_paths, set_hwaccel, AnimatedFormat, Container, HwAccel, Intermediate,
+        PixelFormat, RateControl,
+    },
+    Cancelled, JobContext, SetProgressInfo, Severity,
+};
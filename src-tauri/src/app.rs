@@ -0,0 +1,1099 @@
+//! the tauri app: its commands, and the `JobInfo` that runs compute jobs for them
+
+#[cfg(feature = "progress-server")]
+mod progress_server;
+
+use std::{
+    collections::{HashMap, VecDeque},
+    fs,
+    io::Write,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use tauri::{path::BaseDirectory, AppHandle, Emitter, Manager, State};
+
+use crate::{compute, ffmpeg, Cancelled, JobContext, SetProgressInfo};
+
+/// the max number of detail messages kept in memory per job for `job_log`
+const JOB_LOG_CAPACITY: usize = 1000;
+/// ring buffer of the most recent detail messages of a job
+type JobLog = Arc<Mutex<VecDeque<String>>>;
+
+struct JobInfo {
+    id: usize,
+    is_cancelled: AtomicBool,
+    app: AppHandle,
+    /// where detail messages are logged, if the job has an output directory
+    logfile_path: Option<PathBuf>,
+    /// shared with `Jobs::finished`, so it can be read after the job ends
+    log_tail: JobLog,
+    started: Instant,
+    /// total size of every output the job has written so far
+    bytes_written: AtomicU64,
+}
+impl JobInfo {
+    /// emit a job-specific event, named `{event}:{id}`
+    pub(crate) fn emit<S: serde::Serialize + Clone>(&self, event: &str, payload: S) {
+        #[cfg(feature = "progress-server")]
+        progress_server::broadcast(event, self.id, &payload);
+        self.app
+            .emit(&format!("{event}:{}", self.id), payload)
+            .unwrap_or_else(|_| panic!("emit {event}"));
+    }
+}
+impl JobContext for JobInfo {
+    fn set_progress(&self, info: SetProgressInfo) {
+        if let Some(detail) = &info.detail {
+            let detail = format!("{}{detail}", info.severity.log_prefix());
+            let line = format!(
+                "[{}] {detail}\n",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f")
+            );
+            if let Some(logfile_path) = &self.logfile_path {
+                let mut file = fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(logfile_path)
+                    .expect("open logfile");
+                file.write_all(line.as_bytes()).expect("write to logfile");
+            }
+
+            let mut log_tail = self.log_tail.lock().unwrap();
+            if log_tail.len() >= JOB_LOG_CAPACITY {
+                log_tail.pop_front();
+            }
+            log_tail.push_back(detail);
+        }
+
+        self.emit("progress", info);
+    }
+    fn cancelled(&self) -> bool {
+        self.is_cancelled.load(std::sync::atomic::Ordering::Relaxed)
+    }
+    fn resolve_resource(&self, path: &str) -> PathBuf {
+        self.app
+            .path()
+            .resolve(path, BaseDirectory::Resource)
+            .expect("resolve resource path")
+    }
+    fn add_bytes_written(&self, bytes: u64) {
+        self.bytes_written
+            .fetch_add(bytes, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+/// how a job ended
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+enum FinalState {
+    Completed,
+    Cancelled,
+    Failed,
+}
+
+/// the terminal state of a job, emitted as a `final:{id}` event and kept for `replay_final`
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FinalEvent {
+    state: FinalState,
+    /// the error chain, if the job failed
+    error: Option<String>,
+    /// files in the job's output directory once it ended
+    outputs: Vec<PathBuf>,
+    /// wall-clock time the job ran for
+    elapsed_secs: f64,
+    /// total size of the outputs the job wrote
+    bytes_written: u64,
+}
+impl FinalEvent {
+    /// `cancelled` is whether the job was cancelled before it returned `result`
+    fn new(info: &JobInfo, result: &anyhow::Result<()>, cancelled: bool) -> Self {
+        let (state, error) = match result {
+            Ok(()) => (FinalState::Completed, None),
+            Err(e) if cancelled || Cancelled::caused(e) => (FinalState::Cancelled, None),
+            Err(e) => (FinalState::Failed, Some(format!("{:#}", e))),
+        };
+        let outputs = info
+            .logfile_path
+            .as_deref()
+            .and_then(Path::parent)
+            .and_then(|dir| fs::read_dir(dir).ok())
+            .map(|entries| entries.flatten().map(|entry| entry.path()).collect())
+            .unwrap_or_default();
+        Self {
+            state,
+            error,
+            outputs,
+            elapsed_secs: info.started.elapsed().as_secs_f64(),
+            bytes_written: info
+                .bytes_written
+                .load(std::sync::atomic::Ordering::Relaxed),
+        }
+    }
+    /// like "done in 4m12s, wrote 340.0MB"
+    fn summary(&self) -> String {
+        let secs = self.elapsed_secs.round() as u64;
+        let elapsed = match secs {
+            0..60 => format!("{secs}s"),
+            60..3600 => format!("{}m{:02}s", secs / 60, secs % 60),
+            _ => format!("{}h{:02}m{:02}s", secs / 3600, secs / 60 % 60, secs % 60),
+        };
+        format!(
+            "done in {elapsed}, wrote {:.1}MB",
+            self.bytes_written as f64 / 1024.0 / 1024.0
+        )
+    }
+}
+/// the max number of finished jobs whose `FinalEvent` is kept for `replay_final`
+const FINISHED_JOBS_CAPACITY: usize = 32;
+
+struct Jobs {
+    id_inc: AtomicUsize,
+    active: Mutex<HashMap<usize, Arc<JobInfo>>>,
+    /// the most recently finished jobs, oldest first
+    finished: Mutex<VecDeque<FinishedJob>>,
+}
+/// what is kept of a job once it has finished
+struct FinishedJob {
+    id: usize,
+    event: FinalEvent,
+    /// the job's log, so it can still be read back after a reload or a failure
+    log_tail: JobLog,
+}
+/// what `cancel_job` did
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+enum CancelResult {
+    /// the job is running and will stop, which is also returned for repeated calls
+    Cancelled,
+    /// the job already ended on its own
+    AlreadyFinished,
+    /// there is no job with that id, or it finished too long ago to be remembered
+    Unknown,
+}
+
+impl Jobs {
+    /// flag job `id` as cancelled, which is safe to call any number of times
+    fn cancel(&self, id: usize) -> CancelResult {
+        // the job stays registered so its log can still be read while it winds down
+        if let Some(info) = self.active.lock().unwrap().get(&id) {
+            info.is_cancelled
+                .store(true, std::sync::atomic::Ordering::Relaxed);
+            return CancelResult::Cancelled;
+        }
+        // active first, since a job is only unregistered once it's already in `finished`
+        match self.finished.lock().unwrap().iter().any(|f| f.id == id) {
+            true => CancelResult::AlreadyFinished,
+            false => CancelResult::Unknown,
+        }
+    }
+    /// keep the final event and log of job `id` and unregister it, which only happens once the
+    /// event is sent
+    fn record_finished(&self, id: usize, event: FinalEvent, log_tail: JobLog) {
+        {
+            let mut finished = self.finished.lock().unwrap();
+            if finished.len() >= FINISHED_JOBS_CAPACITY {
+                finished.pop_front();
+            }
+            finished.push_back(FinishedJob {
+                id,
+                event,
+                log_tail,
+            });
+        }
+        // finished first, so a cancel in between still sees the job
+        self.active.lock().unwrap().remove(&id);
+    }
+    /// the most recent detail messages of job `id`, whether it is running or recently finished
+    fn log(&self, id: usize) -> Vec<String> {
+        let log_tail = match self.active.lock().unwrap().get(&id) {
+            Some(info) => Some(Arc::clone(&info.log_tail)),
+            None => self
+                .finished
+                .lock()
+                .unwrap()
+                .iter()
+                .find(|f| f.id == id)
+                .map(|f| Arc::clone(&f.log_tail)),
+        };
+        log_tail
+            .map(|log_tail| log_tail.lock().unwrap().iter().cloned().collect())
+            .unwrap_or_default()
+    }
+}
+
+// job options //
+
+#[derive(Debug, serde::Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum TimelapseType {
+    None,
+    Jpg,
+    Mp4,
+    Webp,
+    Avif,
+    /// extract every frame but write nothing, for measuring extraction speed
+    ///
+    /// this isn't offered by `supported_formats`, since it only matters for performance work
+    Null,
+}
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TimelapseOptions {
+    /// one output type, or a list of them to create from the same frames
+    #[serde(deserialize_with = "deserialize_timelapse_types")]
+    typ: Vec<TimelapseType>,
+    /// seconds of output, ignored when `sample_rate` is set
+    length: u64,
+    /// frames taken per second of footage, so `fps` only sets the playback speed
+    #[serde(default)]
+    sample_rate: Option<f64>,
+    /// skip the gaps between clips, or space frames over real-world time and hold the frame
+    /// before each gap or fill it with black
+    #[serde(default)]
+    gaps: compute::GapPolicy,
+    fps: u32,
+    skip: Option<u32>,
+    /// container for the video output
+    #[serde(default)]
+    container: ffmpeg::Container,
+    /// constant quality, or two passes to hit a target size
+    #[serde(default)]
+    rate_control: ffmpeg::RateControl,
+    /// encode the video in segments of this many frames, so running an interrupted job again
+    /// resumes from its last finished segment (constant quality only)
+    #[serde(default)]
+    segment_frames: Option<usize>,
+    /// ffmpeg options for the video encoder that aren't otherwise exposed, like
+    /// `["-preset", "slow", "-tune", "film"]`, which override the encoder's own where they repeat
+    #[serde(default)]
+    extra_ffmpeg_args: Vec<String>,
+    /// the format frames are handed to the video encoder in, trading speed for generational loss
+    #[serde(default)]
+    intermediate: ffmpeg::Intermediate,
+    /// tune how many frames are extracted at once based on how fast they finish, up to `threads`
+    #[serde(default)]
+    adaptive_concurrency: bool,
+    /// extract the frames of clips contributing at least `minFrames` with one ffmpeg per
+    /// `framesPerRun` frames, instead of seeking to every frame on its own
+    #[serde(default)]
+    batch_extraction: Option<compute::BatchExtraction>,
+    /// embed the first scraped location as file-level metadata (mp4 only, requires scraping)
+    #[serde(default)]
+    embed_location: bool,
+    /// keep only this region of each frame
+    #[serde(default)]
+    crop: Option<compute::Crop>,
+    #[serde(default)]
+    overlays: compute::Overlays,
+    /// split jpg frames into subdirectories of this many frames, instead of one flat directory
+    #[serde(default)]
+    frames_per_dir: Option<usize>,
+    /// pipe decoded frames to the video encoder as raw pixels instead of re-encoded jpgs
+    #[serde(default)]
+    raw_frames: bool,
+    /// keep any one long clip from dominating the timelapse
+    #[serde(default)]
+    max_frames_per_clip: Option<usize>,
+    /// write each jpg frame's real-world time and scraped location into its exif data
+    #[serde(default)]
+    exif: bool,
+    /// seconds to hold the first frame for, like a title card
+    #[serde(default)]
+    hold_start_secs: f64,
+    /// seconds to hold the last frame for, like an end card
+    #[serde(default)]
+    hold_end_secs: f64,
+    /// seconds that the end crossfades into the start for, so the timelapse loops seamlessly when
+    /// repeated, which can't be combined with holding the first or last frame
+    #[serde(default)]
+    loop_crossfade_secs: f64,
+    /// even out brightness flicker between frames with this strength from 0 to 1 (cpu intensive)
+    #[serde(default)]
+    deflicker_strength: Option<f32>,
+    /// denoise each frame with this strength from 0 to 10 (cpu intensive), which also applies to
+    /// the frames glyphs are scraped from
+    #[serde(default)]
+    denoise_strength: Option<f32>,
+    /// extract frames in grayscale for computer vision pipelines, which also writes jpg frames
+    /// with a single channel
+    #[serde(default)]
+    pixel_format: ffmpeg::PixelFormat,
+    /// write a csv of every output frame's real-world time, for adding markers in video editors
+    #[serde(default)]
+    frames_csv: bool,
+    /// pause the job while the output volume has fewer than this many megabytes free, instead of
+    /// running out of space partway through
+    #[serde(default)]
+    min_free_space_mb: Option<u64>,
+    /// mark a chapter in the video for each source clip or each day
+    #[serde(default)]
+    chapters: Option<compute::ChapterMode>,
+}
+
+fn deserialize_timelapse_types<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<TimelapseType>, D::Error> {
+    #[derive(serde::Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(TimelapseType),
+        Many(Vec<TimelapseType>),
+    }
+    Ok(match serde::Deserialize::deserialize(deserializer)? {
+        OneOrMany::One(typ) => vec![typ],
+        OneOrMany::Many(types) => types,
+    })
+}
+
+impl TimelapseOptions {
+    /// the free space to pause at, in bytes
+    fn min_free_space(&self) -> Option<u64> {
+        self.min_free_space_mb
+            .map(|mb| mb.saturating_mul(1024 * 1024))
+    }
+    /// the denoise strength, if denoising is enabled
+    fn denoise(&self) -> Option<f32> {
+        self.denoise_strength.filter(|&strength| strength > 0.0)
+    }
+
+    /// the settings for creating this timelapse, or `None` if no timelapse should be created
+    ///
+    /// `locations` are the scraped locations of each clip in the timeline, if they were scraped
+    fn settings(
+        self,
+        locations: Option<&[compute::LatLng]>,
+    ) -> Option<compute::TimelapseSettings<'_>> {
+        let mut types = Vec::new();
+        for typ in &self.typ {
+            let typ = match typ {
+                TimelapseType::None => continue,
+                TimelapseType::Jpg => compute::TimelapseType::Jpg,
+                TimelapseType::Mp4 => compute::TimelapseType::Mp4,
+                TimelapseType::Webp => {
+                    compute::TimelapseType::Animated(ffmpeg::AnimatedFormat::Webp)
+                }
+                TimelapseType::Avif => {
+                    compute::TimelapseType::Animated(ffmpeg::AnimatedFormat::Avif)
+                }
+                TimelapseType::Null => compute::TimelapseType::Null,
+            };
+            if !types.contains(&typ) {
+                types.push(typ);
+            }
+        }
+        if types.is_empty() {
+            return None;
+        }
+        Some(compute::TimelapseSettings {
+            types,
+            length: Duration::from_secs(self.length),
+            sample_rate: self
+                .sample_rate
+                .filter(|rate| rate.is_finite() && *rate > 0.0),
+            gaps: self.gaps,
+            hold_start: Duration::try_from_secs_f64(self.hold_start_secs).unwrap_or_default(),
+            hold_end: Duration::try_from_secs_f64(self.hold_end_secs).unwrap_or_default(),
+            loop_crossfade: Duration::try_from_secs_f64(self.loop_crossfade_secs)
+                .unwrap_or_default(),
+            fps: self.fps,
+            container: self.container,
+            rate_control: self.rate_control,
+            segment_frames: self.segment_frames,
+            intermediate: self.intermediate,
+            adaptive_concurrency: self.adaptive_concurrency,
+            batch_extraction: self.batch_extraction,
+            skip: self.skip,
+            location: locations
+                .filter(|_| self.embed_location)
+                .and_then(|locs| locs.iter().find(|loc| loc.is_valid())),
+            crop: self.crop,
+            denoise: self.denoise(),
+            pixel_format: self.pixel_format,
+            min_free_space: self.min_free_space(),
+            overlays: self.overlays,
+            extra_ffmpeg_args: self.extra_ffmpeg_args,
+            frames_per_dir: self.frames_per_dir,
+            raw_frames: self.raw_frames,
+            max_frames_per_clip: self.max_frames_per_clip,
+            exif: self.exif,
+            deflicker: self.deflicker_strength.filter(|&strength| strength > 0.0),
+            frames_csv: self.frames_csv,
+            chapters: self.chapters,
+            clip_locations: locations,
+        })
+    }
+}
+
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportOptions {
+    enabled: bool,
+    location: bool,
+    /// additional glyph fields to scrape into the export
+    #[serde(default)]
+    glyphs: Vec<compute::GlyphField>,
+    /// only scrape every nth clip, filling in the clips between from the one before
+    #[serde(default)]
+    scrape_every: Option<usize>,
+    /// the filename and formatting of the exported json
+    #[serde(default, flatten)]
+    format: compute::ExportFormat,
+}
+
+// job commands //
+
+/// create a new job and add it to the list of active jobs
+fn register_job(app: AppHandle, jobs: &Jobs, logfile_path: Option<PathBuf>) -> Arc<JobInfo> {
+    let id = jobs
+        .id_inc
+        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+    let info = Arc::new(JobInfo {
+        id,
+        is_cancelled: AtomicBool::new(false),
+        app,
+        logfile_path,
+        log_tail: Arc::new(Mutex::new(VecDeque::with_capacity(JOB_LOG_CAPACITY))),
+        started: Instant::now(),
+        bytes_written: AtomicU64::new(0),
+    });
+    let mut job_map = jobs.active.lock().unwrap();
+    job_map.insert(info.id, info.clone());
+    info
+}
+
+/// run `run_job` for a registered job on a blocking thread, returning the job id
+fn spawn_job<F>(info: Arc<JobInfo>, run_job: F) -> usize
+where
+    F: FnOnce(Arc<JobInfo>) -> anyhow::Result<()> + Send + 'static,
+{
+    let id = info.id;
+    tauri::async_runtime::spawn_blocking(move || {
+        let result = run_job(Arc::clone(&info));
+        let cancelled = info.cancelled();
+        if result.as_ref().is_err_and(Cancelled::caused) {
+            info.set_progress(SetProgressInfo::detail("----- Cancelled -----"));
+        } else if let Err(e) = &result {
+            let panic_msg = format!("----- PANIC -----\n{:?}\n", e);
+            info.set_progress(SetProgressInfo::error(panic_msg.clone()));
+            eprintln!("{}", panic_msg);
+        }
+        info.is_cancelled
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+
+        let event = FinalEvent::new(&info, &result, cancelled);
+        if event.state == FinalState::Completed {
+            info.set_progress(SetProgressInfo::detail(format!(
+                "----- {} -----",
+                event.summary()
+            )));
+        }
+        info.emit("final", event.clone());
+        info.app
+            .state::<Jobs>()
+            .record_finished(info.id, event, Arc::clone(&info.log_tail));
+    });
+    id
+}
+
+/// register and spawn a job that writes into `output_path`
+fn spawn_output_job<F>(app: AppHandle, jobs: &Jobs, output_path: &str, run_job: F) -> usize
+where
+    F: FnOnce(Arc<JobInfo>) -> anyhow::Result<()> + Send + 'static,
+{
+    // create the output directory
+    std::fs::create_dir_all(output_path).expect("create output directory");
+
+    let logfile_path = Path::new(output_path).join("output.log");
+    spawn_job(register_job(app, jobs, Some(logfile_path)), run_job)
+}
+
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn start_job(
+    app: AppHandle,
+    jobs: State<Jobs>,
+    threads: usize,
+    input_path: String,
+    output_path: String,
+    timeline: Option<compute::TimelineOptions>,
+    timelapse: TimelapseOptions,
+    export: ExportOptions,
+    skip_preflight: Option<bool>,
+) -> usize {
+    let job_output_path = output_path.clone();
+    spawn_output_job(app, &jobs, &output_path, move |info| {
+        // a job with neither output would only build the timeline and throw it away. an export on
+        // its own is fine, and is how just a gps track of the footage is made
+        if timelapse.typ.iter().all(|typ| *typ == TimelapseType::None) && !export.enabled {
+            anyhow::bail!("neither a timelapse nor an export was chosen");
+        }
+        let timeline = timeline.unwrap_or_default();
+        let mut fields = Vec::new();
+        if export.enabled {
+            fields = export.glyphs;
+            if export.location {
+                fields.insert(0, compute::GlyphField::location());
+            }
+        }
+        if !skip_preflight.unwrap_or(false) {
+            compute::check_first_clip(&*info, &input_path, &timeline, &fields, timelapse.denoise())
+                .context("preflight check failed, pass skipPreflight to start anyway")?;
+        }
+        let job = compute::ProcessClipsJob::new(threads, info.clone(), &input_path, &timeline)?;
+        // glyphs are scraped up-front so the timelapse can embed the location
+        let scraped = if !fields.is_empty() {
+            Some(job.scrape_fields(
+                info.clone(),
+                fields,
+                export.scrape_every.unwrap_or(1),
+                timelapse.denoise(),
+                &job_output_path,
+            )?)
+        } else {
+            None
+        };
+        let locations = scraped.as_ref().and_then(compute::ScrapedFields::locations);
+        let min_free_space = timelapse.min_free_space();
+        if let Some(settings) = timelapse.settings(locations.as_deref()) {
+            job.create_timelapse(info.clone(), settings, &job_output_path)?;
+        }
+        if export.enabled {
+            job.export_data(
+                info,
+                scraped.as_ref(),
+                &job_output_path,
+                &export.format,
+                min_free_space,
+            )?;
+        }
+        Ok(())
+    })
+}
+
+/// timelapse the single clip at `clip_path`, like one long recording, without globbing a folder
+#[tauri::command]
+fn start_clip_job(
+    app: AppHandle,
+    jobs: State<Jobs>,
+    threads: usize,
+    clip_path: String,
+    output_path: String,
+    timeline: Option<compute::TimelineOptions>,
+    timelapse: TimelapseOptions,
+) -> usize {
+    let job_output_path = output_path.clone();
+    spawn_output_job(app, &jobs, &output_path, move |info| {
+        let Some(settings) = timelapse.settings(None) else {
+            anyhow::bail!("no timelapse output was chosen for the clip");
+        };
+        let job = compute::ProcessClipsJob::new_single_clip(
+            threads,
+            info.clone(),
+            &clip_path,
+            &timeline.unwrap_or_default(),
+        )?;
+        job.create_timelapse(info, settings, &job_output_path)
+    })
+}
+
+/// build the timeline and export it without scraping or timelapsing
+#[tauri::command]
+fn export_timeline_only(
+    app: AppHandle,
+    jobs: State<Jobs>,
+    input_path: String,
+    output_path: String,
+    timeline: Option<compute::TimelineOptions>,
+    format: Option<compute::ExportFormat>,
+) -> usize {
+    let job_output_path = output_path.clone();
+    spawn_output_job(app, &jobs, &output_path, move |info| {
+        let job = compute::ProcessClipsJob::new(
+            get_parallelism(),
+            info.clone(),
+            &input_path,
+            &timeline.unwrap_or_default(),
+        )?;
+        job.export_data(
+            info,
+            None,
+            &job_output_path,
+            &format.unwrap_or_default(),
+            None,
+        )
+    })
+}
+
+/// build the timeline and total how much was recorded in each hour or day, without any ffmpeg work
+/// beyond probing the clips
+#[tauri::command]
+fn export_coverage(
+    app: AppHandle,
+    jobs: State<Jobs>,
+    input_path: String,
+    output_path: String,
+    timeline: Option<compute::TimelineOptions>,
+    coverage: Option<compute::CoverageOptions>,
+) -> usize {
+    let job_output_path = output_path.clone();
+    spawn_output_job(app, &jobs, &output_path, move |info| {
+        let job = compute::ProcessClipsJob::new(
+            get_parallelism(),
+            info.clone(),
+            &input_path,
+            &timeline.unwrap_or_default(),
+        )?;
+        job.export_coverage(info, &coverage.unwrap_or_default(), &job_output_path)
+    })
+}
+
+/// build the timeline and tile a thumbnail of each clip into sprite sheets, for scrubbing through it
+#[tauri::command]
+fn export_sprite_sheet(
+    app: AppHandle,
+    jobs: State<Jobs>,
+    input_path: String,
+    output_path: String,
+    timeline: Option<compute::TimelineOptions>,
+    sprites: Option<compute::SpriteSheetOptions>,
+) -> usize {
+    let job_output_path = output_path.clone();
+    spawn_output_job(app, &jobs, &output_path, move |info| {
+        let job = compute::ProcessClipsJob::new(
+            get_parallelism(),
+            info.clone(),
+            &input_path,
+            &timeline.unwrap_or_default(),
+        )?;
+        job.export_sprite_sheet(info, &sprites.unwrap_or_default(), &job_output_path)
+    })
+}
+
+/// combine earlier exports into one sorted timeline in `output_path`, without reprocessing clips
+///
+/// returns the number of entries in the merged export
+#[tauri::command]
+fn merge_exports(
+    input_paths: Vec<PathBuf>,
+    output_path: String,
+    format: Option<compute::ExportFormat>,
+) -> Result<usize, String> {
+    compute::merge_exports(
+        &input_paths,
+        Path::new(&output_path),
+        &format.unwrap_or_default(),
+    )
+    .map_err(|e| format!("{:#}", e))
+}
+
+/// measure frame extraction throughput at each thread count
+///
+/// the results are emitted as a `benchmark:{id}` event once finished
+#[tauri::command]
+fn benchmark(
+    app: AppHandle,
+    jobs: State<Jobs>,
+    input_path: String,
+    timeline: Option<compute::TimelineOptions>,
+    thread_counts: Vec<usize>,
+    sample_size: Option<usize>,
+) -> usize {
+    let info = register_job(app, &jobs, None);
+    spawn_job(info, move |info| {
+        let job = compute::ProcessClipsJob::new(
+            get_parallelism(),
+            info.clone(),
+            &input_path,
+            &timeline.unwrap_or_default(),
+        )?;
+        let results = job.benchmark(
+            info.clone(),
+            &thread_counts,
+            sample_size.unwrap_or(compute::MAX_BENCHMARK_FRAMES),
+        )?;
+        info.emit("benchmark", results);
+        Ok(())
+    })
+}
+
+/// estimate the output size of a timelapse by encoding a sample of its frames
+///
+/// the estimate is emitted as an `estimate:{id}` event once finished
+#[tauri::command]
+fn estimate_output_size(
+    app: AppHandle,
+    jobs: State<Jobs>,
+    input_path: String,
+    timeline: Option<compute::TimelineOptions>,
+    timelapse: TimelapseOptions,
+    sample_size: Option<usize>,
+) -> usize {
+    let info = register_job(app, &jobs, None);
+    spawn_job(info, move |info| {
+        let Some(settings) = timelapse.settings(None) else {
+            anyhow::bail!("no timelapse type to estimate the size of");
+        };
+        let job = compute::ProcessClipsJob::new(
+            get_parallelism(),
+            info.clone(),
+            &input_path,
+            &timeline.unwrap_or_default(),
+        )?;
+        let estimate = job.estimate_size(
+            info.clone(),
+            &settings,
+            sample_size.unwrap_or(compute::MAX_ESTIMATE_FRAMES),
+        )?;
+        info.emit("estimate", estimate);
+        Ok(())
+    })
+}
+
+/// encode the first few seconds of a timelapse with its video settings, to check its quality and
+/// overlays before a full run
+///
+/// the mp4 is emitted as a base64 data url in a `preview:{id}` event once finished
+#[tauri::command]
+fn encode_preview(
+    app: AppHandle,
+    jobs: State<Jobs>,
+    input_path: String,
+    timeline: Option<compute::TimelineOptions>,
+    timelapse: TimelapseOptions,
+    secs: Option<u32>,
+) -> usize {
+    let info = register_job(app, &jobs, None);
+    spawn_job(info, move |info| {
+        let Some(settings) = timelapse.settings(None) else {
+            anyhow::bail!("no timelapse type to preview");
+        };
+        let job = compute::ProcessClipsJob::new(
+            get_parallelism(),
+            info.clone(),
+            &input_path,
+            &timeline.unwrap_or_default(),
+        )?;
+        let preview = job.encode_preview(
+            info.clone(),
+            &settings,
+            secs.unwrap_or(compute::DEFAULT_PREVIEW_SECS),
+        )?;
+        info.emit("preview", preview);
+        Ok(())
+    })
+}
+
+/// build the timeline to find when its footage starts and stops, for the bounds of a date picker
+///
+/// the range is emitted as a `dateRange:{id}` event once finished, which is `null` when there
+/// are no clips to timeline
+#[tauri::command]
+fn timeline_date_range(
+    app: AppHandle,
+    jobs: State<Jobs>,
+    input_path: String,
+    timeline: Option<compute::TimelineOptions>,
+) -> usize {
+    let info = register_job(app, &jobs, None);
+    spawn_job(info, move |info| {
+        let range = match compute::ProcessClipsJob::new(
+            get_parallelism(),
+            info.clone(),
+            &input_path,
+            &timeline.unwrap_or_default(),
+        ) {
+            Ok(job) => job.date_range(),
+            Err(e) if compute::EmptyTimeline::caused(&e) => None,
+            Err(e) => return Err(e),
+        };
+        info.emit("dateRange", range);
+        Ok(())
+    })
+}
+
+/// count the clips whose filenames have a timestamp that parses, without probing any of them
+#[tauri::command]
+fn check_timestamps(
+    input_path: String,
+    timeline: Option<compute::TimelineOptions>,
+) -> Result<compute::TimestampReport, String> {
+    compute::check_timestamps(Path::new(&input_path), &timeline.unwrap_or_default())
+        .map_err(|e| format!("{:#}", e))
+}
+
+/// stop a job, reporting whether it was running, already finished, or never existed
+#[tauri::command]
+fn cancel_job(job_id: usize, jobs: State<Jobs>) -> CancelResult {
+    jobs.cancel(job_id)
+}
+
+/// the terminal state of a recently finished job, for when the `final:{id}` event was missed
+#[tauri::command]
+fn replay_final(job_id: usize, jobs: State<Jobs>) -> Option<FinalEvent> {
+    let finished = jobs.finished.lock().unwrap();
+    finished
+        .iter()
+        .rev()
+        .find(|f| f.id == job_id)
+        .map(|f| f.event.clone())
+}
+
+#[tauri::command]
+fn job_log(job_id: usize, jobs: State<Jobs>) -> Vec<String> {
+    jobs.log(job_id)
+}
+
+// other commands //
+
+#[tauri::command]
+fn get_parallelism() -> usize {
+    std::thread::available_parallelism()
+        .map(|x| x.get())
+        .unwrap_or(1)
+}
+
+/// check a glyph config for problems before running a job with it, defaulting to the bundled config
+///
+/// returns one message per problem found, or an error if the config could not be parsed at all
+#[tauri::command]
+fn validate_glyph_config(app: AppHandle, path: Option<String>) -> Result<Vec<String>, String> {
+    let resolve = |path: &str| {
+        app.path()
+            .resolve(path, BaseDirectory::Resource)
+            .unwrap_or_else(|_| PathBuf::from(path))
+    };
+    let config_path = path.map_or_else(|| resolve(compute::GLYPH_CONFIG_RESOURCE), PathBuf::from);
+    compute::validate_glyph_config(config_path, resolve).map_err(|e| format!("{:#}", e))
+}
+
+/// run the whole pipeline on a tiny synthetic clip, reporting which stages work on this install
+///
+/// the report is emitted as a `selfTest:{id}` event once finished
+#[tauri::command]
+fn self_test(app: AppHandle, jobs: State<Jobs>) -> usize {
+    let info = register_job(app, &jobs, None);
+    spawn_job(info, move |info| {
+        let report = compute::self_test(info.clone(), get_parallelism())?;
+        info.emit("selfTest", report);
+        Ok(())
+    })
+}
+
+/// pick the most detailed of a few frames sampled across a clip, returned as jpg bytes
+#[tauri::command(async)]
+fn clip_thumbnail(path: String, candidates: Option<usize>) -> Result<Vec<u8>, String> {
+    let thumbnail = compute::representative_frame(
+        Path::new(&path),
+        candidates.unwrap_or(compute::DEFAULT_THUMBNAIL_CANDIDATES),
+    );
+    print_ffmpeg_diagnostics(&path);
+    thumbnail.map_err(|e| format!("{:#}", e))
+}
+
+/// print anything ffmpeg printed in verbose mode for a command that isn't a job, which has no log
+/// to put it in, so it doesn't pile up on the command's thread
+fn print_ffmpeg_diagnostics(subject: &str) {
+    for output in ffmpeg::take_diagnostics() {
+        eprintln!("ffmpeg output for {:?}:\n{}", subject, output);
+    }
+}
+
+/// the options that will work with the bundled ffmpeg, so the ui only offers those
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SupportedFormats {
+    /// values accepted for the timelapse `typ`
+    timelapse_types: Vec<&'static str>,
+    #[serde(flatten)]
+    ffmpeg: ffmpeg::Capabilities,
+}
+
+#[tauri::command(async)]
+fn supported_formats() -> Result<SupportedFormats, String> {
+    let ffmpeg = ffmpeg::capabilities().map_err(|e| format!("{:#}", e))?;
+    let mut timelapse_types = vec!["none"];
+    if ffmpeg.mjpeg {
+        timelapse_types.push("jpg");
+        if ffmpeg.h264 && !ffmpeg.containers.is_empty() {
+            timelapse_types.push("mp4");
+        }
+        timelapse_types.extend(ffmpeg.animated.iter().map(|format| format.extension()));
+    }
+    Ok(SupportedFormats {
+        timelapse_types,
+        ffmpeg,
+    })
+}
+
+/// toggle running ffmpeg with `-v warning`, logging what it prints into the job log
+#[tauri::command]
+fn set_ffmpeg_verbose(verbose: bool) {
+    ffmpeg::set_verbose(verbose);
+}
+
+/// decode clips with a hardware decoder when extracting frames, or in software with `None`
+///
+/// if the decoder fails on a clip that decodes fine in software, it stays off until this is
+/// called again
+#[tauri::command]
+fn set_hwaccel(hwaccel: Option<ffmpeg::HwAccel>) {
+    ffmpeg::set_hwaccel(hwaccel);
+}
+
+/// how many seconds before the end of a clip to look for its last frame, when a timestamp runs
+/// past the frames in it
+#[tauri::command]
+fn set_last_frame_seek(secs: f64) -> Result<(), String> {
+    let seek = Duration::try_from_secs_f64(secs).map_err(|e| format!("{:#}", e))?;
+    ffmpeg::set_last_frame_seek(seek);
+    Ok(())
+}
+
+/// bytes available on the volume containing `path`, checking its nearest existing ancestor if
+/// the path has not been created yet
+#[tauri::command]
+fn disk_free(path: String) -> Result<u64, String> {
+    let path = Path::new(&path);
+    let existing = path
+        .ancestors()
+        .find(|ancestor| ancestor.exists())
+        .ok_or_else(|| format!("no part of {:?} exists", path))?;
+    fs4::available_space(existing)
+        .map_err(|e| format!("read available space for {:?}: {}", existing, e))
+}
+
+/// run ffprobe on a file and return its raw json output, for fields that are not parsed anywhere else
+#[tauri::command]
+fn ffprobe_raw(path: String, entries: Option<String>) -> Result<serde_json::Value, String> {
+    let probed = ffmpeg::probe_raw(Path::new(&path), entries.as_deref());
+    print_ffmpeg_diagnostics(&path);
+    probed.map_err(|e| format!("{:#}", e))
+}
+
+#[tauri::command]
+fn read_file(filepath: &Path) -> String {
+    std::fs::read_to_string(filepath).expect("read file from filepath")
+}
+
+// init //
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    let jobs_state = Jobs {
+        id_inc: AtomicUsize::new(1),
+        active: Mutex::new(HashMap::new()),
+        finished: Mutex::new(VecDeque::with_capacity(FINISHED_JOBS_CAPACITY)),
+    };
+
+    tauri::Builder::default()
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_opener::init())
+        .setup(|app| {
+            ffmpeg::set_paths(app.handle())?;
+            // the app still works without the server, like when its port is taken
+            #[cfg(feature = "progress-server")]
+            match progress_server::start(app.handle().clone()) {
+                Ok(addr) => eprintln!("serving job progress on http://{addr}"),
+                Err(e) => eprintln!("could not start the progress server: {:#}", e),
+            }
+            Ok(())
+        })
+        .manage(jobs_state)
+        .invoke_handler(tauri::generate_handler![
+            start_job,
+            start_clip_job,
+            export_timeline_only,
+            export_coverage,
+            export_sprite_sheet,
+            merge_exports,
+            benchmark,
+            estimate_output_size,
+            encode_preview,
+            timeline_date_range,
+            check_timestamps,
+            cancel_job,
+            job_log,
+            replay_final,
+            get_parallelism,
+            ffprobe_raw,
+            clip_thumbnail,
+            supported_formats,
+            set_ffmpeg_verbose,
+            set_hwaccel,
+            set_last_frame_seek,
+            disk_free,
+            self_test,
+            validate_glyph_config,
+            read_file,
+        ])
+        .run(tauri::generate_context!())
+        .expect("error while running tauri application");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CancelResult, FinalEvent, FinalState, Jobs};
+    use std::{
+        collections::{HashMap, VecDeque},
+        sync::{atomic::AtomicUsize, Arc, Mutex},
+    };
+
+    fn completed() -> FinalEvent {
+        FinalEvent {
+            state: FinalState::Completed,
+            error: None,
+            outputs: Vec::new(),
+            elapsed_secs: 0.0,
+            bytes_written: 0,
+        }
+    }
+    fn no_jobs() -> Jobs {
+        Jobs {
+            id_inc: AtomicUsize::new(1),
+            active: Mutex::new(HashMap::new()),
+            finished: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    #[test]
+    fn cancelling_finished_and_unknown_jobs_is_reported() {
+        let jobs = no_jobs();
+        jobs.record_finished(3, completed(), Arc::default());
+        for _ in 0..2 {
+            assert_eq!(jobs.cancel(3), CancelResult::AlreadyFinished);
+            assert_eq!(jobs.cancel(4), CancelResult::Unknown);
+        }
+    }
+
+    #[test]
+    fn finished_jobs_keep_their_log() {
+        let jobs = no_jobs();
+        let log_tail = Arc::new(Mutex::new(VecDeque::from([
+            "extracting".to_string(),
+            "[error] clip is corrupt".to_string(),
+        ])));
+        jobs.record_finished(3, completed(), log_tail);
+        assert_eq!(jobs.log(3), ["extracting", "[error] clip is corrupt"]);
+        assert!(jobs.log(4).is_empty());
+    }
+
+    #[test]
+    fn summary_reports_elapsed_time_and_size() {
+        let mut event = FinalEvent {
+            state: FinalState::Completed,
+            error: None,
+            outputs: Vec::new(),
+            elapsed_secs: 252.4,
+            bytes_written: 340 * 1024 * 1024,
+        };
+        assert_eq!(event.summary(), "done in 4m12s, wrote 340.0MB");
+        event.elapsed_secs = 3725.0;
+        assert_eq!(event.summary(), "done in 1h02m05s, wrote 340.0MB");
+    }
+}
@@ -0,0 +1,327 @@
+//! a local http server that mirrors job events as server-sent events, for watching jobs on a
+//! headless machine from a dashboard
+//!
+//! - `GET /jobs` lists the active and recently finished jobs as json
+//! - `GET /events` streams every job event, with the event name as the sse event and
+//!   `{"id": job id, "payload": ...}` as its data
+//!
+//! responses don't allow cross-origin reads, so web pages open in a browser can't read the paths
+//! and logs of jobs from it. requests for any host but localhost or the server's own ip are
+//! rejected, so a page can't get around that by rebinding its own domain to this address
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Mutex, OnceLock,
+    },
+    time::Duration,
+};
+
+use anyhow::Context;
+use tauri::{AppHandle, Manager};
+
+use super::Jobs;
+use crate::JobContext;
+
+/// address the server binds to, unless overridden with `CRIMELAPSE_PROGRESS_ADDR`
+pub const DEFAULT_ADDR: &str = "127.0.0.1:7878";
+/// how often an idle event stream is written to, to notice when the client has gone away
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+/// how long a client has to send its request before the connection is dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// how long a write can stall before the client is given up on
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// the most frames queued for an event stream, past which its client is too slow and is dropped
+const SUBSCRIBER_QUEUE: usize = 1024;
+/// the most connections served at once, each of which takes a thread
+const MAX_CONNECTIONS: usize = 32;
+
+static CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+/// a slot out of `MAX_CONNECTIONS`, which is given back when dropped
+struct ConnectionSlot;
+impl ConnectionSlot {
+    fn take() -> Option<Self> {
+        CONNECTIONS
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |open| {
+                (open < MAX_CONNECTIONS).then_some(open + 1)
+            })
+            .ok()
+            .map(|_| Self)
+    }
+}
+impl Drop for ConnectionSlot {
+    fn drop(&mut self) {
+        CONNECTIONS.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+static SUBSCRIBERS: OnceLock<Subscribers> = OnceLock::new();
+
+/// the open event streams, which each get a copy of every frame
+#[derive(Default)]
+struct Subscribers(Mutex<Vec<mpsc::SyncSender<String>>>);
+impl Subscribers {
+    fn subscribe(&self) -> mpsc::Receiver<String> {
+        let (tx, rx) = mpsc::sync_channel(SUBSCRIBER_QUEUE);
+        self.0.lock().unwrap().push(tx);
+        rx
+    }
+    /// send `frame` to every stream, dropping the ones that have closed or fallen too far behind
+    fn send(&self, frame: &str) {
+        self.0
+            .lock()
+            .unwrap()
+            .retain(|tx| tx.try_send(frame.to_string()).is_ok());
+    }
+}
+
+/// whether `host`, the value of a request's host header, names this server at `local_ip`
+fn allowed_host(host: &str, local_ip: IpAddr) -> bool {
+    let ip = host
+        .parse::<SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| host.trim_matches(['[', ']']).parse::<IpAddr>());
+    if let Ok(ip) = ip {
+        return ip.is_loopback() || ip == local_ip;
+    }
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if port.parse::<u16>().is_ok() => name,
+        _ => host,
+    };
+    name.eq_ignore_ascii_case("localhost")
+}
+
+/// the sse frame for job `id`'s `event`
+fn sse_frame<S: serde::Serialize>(
+    event: &str,
+    id: usize,
+    payload: S,
+) -> serde_json::Result<String> {
+    let data = serde_json::to_string(&serde_json::json!({ "id": id, "payload": payload }))?;
+    Ok(format!("event: {event}\ndata: {data}\n\n"))
+}
+
+/// mirror a job event to every open event stream, if the server is running
+pub fn broadcast<S: serde::Serialize>(event: &str, id: usize, payload: S) {
+    let Some(subscribers) = SUBSCRIBERS.get() else {
+        return;
+    };
+    match sse_frame(event, id, payload) {
+        Ok(frame) => subscribers.send(&frame),
+        Err(e) => eprintln!("serialize {event}:{id} for the progress server: {e}"),
+    }
+}
+
+/// bind the server and serve it on a background thread
+pub fn start(app: AppHandle) -> anyhow::Result<SocketAddr> {
+    let addr = std::env::var("CRIMELAPSE_PROGRESS_ADDR").unwrap_or_else(|_| DEFAULT_ADDR.into());
+    let listener =
+        TcpListener::bind(&addr).with_context(|| format!("bind progress server to {addr}"))?;
+    let local_addr = listener.local_addr().context("progress server address")?;
+    SUBSCRIBERS
+        .set(Subscribers::default())
+        .map_err(|_| anyhow::anyhow!("progress server is already running"))?;
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let Some(slot) = ConnectionSlot::take() else {
+                let _ = respond(
+                    stream,
+                    "503 Service Unavailable",
+                    "text/plain",
+                    "too many connections",
+                );
+                continue;
+            };
+            let app = app.clone();
+            std::thread::spawn(move || {
+                let _slot = slot;
+                if let Err(e) = handle(&app, stream) {
+                    eprintln!("progress server connection: {:#}", e);
+                }
+            });
+        }
+    });
+    Ok(local_addr)
+}
+
+fn handle(app: &AppHandle, stream: TcpStream) -> anyhow::Result<()> {
+    stream
+        .set_read_timeout(Some(REQUEST_TIMEOUT))
+        .context("set request timeout")?;
+    // a client that stops reading would otherwise block its thread and slot forever
+    stream
+        .set_write_timeout(Some(WRITE_TIMEOUT))
+        .context("set write timeout")?;
+    let mut reader = BufReader::new(stream.try_clone().context("clone stream")?);
+    let mut request_line = String::new();
+    reader
+        .read_line(&mut request_line)
+        .context("read request")?;
+    // only the host is needed, but every header has to be read before responding
+    let mut host = None;
+    let mut header = String::new();
+    while reader.read_line(&mut header).context("read headers")? > 2 {
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("host") {
+                host = Some(value.trim().to_string());
+            }
+        }
+        header.clear();
+    }
+    let local_ip = stream.local_addr().context("read local address")?.ip();
+    if !host.is_some_and(|host| allowed_host(&host, local_ip)) {
+        return respond(stream, "403 Forbidden", "text/plain", "unknown host");
+    }
+
+    let mut parts = request_line.split_whitespace();
+    match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/jobs")) => {
+            let body = serde_json::to_string(&jobs_snapshot(&app.state::<Jobs>()))?;
+            respond(stream, "200 OK", "application/json", &body)
+        }
+        (Some("GET"), Some("/events")) => stream_events(stream),
+        (Some("GET"), _) => respond(stream, "404 Not Found", "text/plain", "not found"),
+        _ => respond(
+            stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed",
+        ),
+    }
+}
+
+fn jobs_snapshot(jobs: &Jobs) -> serde_json::Value {
+    let mut active = jobs
+        .active
+        .lock()
+        .unwrap()
+        .values()
+        .map(|info| serde_json::json!({ "id": info.id, "cancelled": info.cancelled() }))
+        .collect::<Vec<_>>();
+    active.sort_by_key(|job| job["id"].as_u64());
+    let finished = jobs
+        .finished
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|job| serde_json::json!({ "id": job.id, "final": job.event }))
+        .collect::<Vec<_>>();
+    serde_json::json!({ "active": active, "finished": finished })
+}
+
+fn respond(
+    mut stream: TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> anyhow::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .context("write response")
+}
+
+fn stream_events(mut stream: TcpStream) -> anyhow::Result<()> {
+    let events = SUBSCRIBERS
+        .get()
+        .expect("subscribers set before serving")
+        .subscribe();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\n\
+         Connection: keep-alive\r\n\r\n"
+    )
+    .context("write event stream headers")?;
+    stream.flush()?;
+
+    // a failed write means the client disconnected, which ends the stream
+    loop {
+        let frame = match events.recv_timeout(KEEPALIVE_INTERVAL) {
+            Ok(frame) => frame,
+            Err(mpsc::RecvTimeoutError::Timeout) => ": keepalive\n\n".to_string(),
+            Err(mpsc::RecvTimeoutError::Disconnected) => return Ok(()),
+        };
+        if stream
+            .write_all(frame.as_bytes())
+            .and_then(|_| stream.flush())
+            .is_err()
+        {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        allowed_host, sse_frame, ConnectionSlot, Subscribers, MAX_CONNECTIONS, SUBSCRIBER_QUEUE,
+    };
+    use std::net::{IpAddr, Ipv4Addr};
+
+    #[test]
+    fn connections_are_capped_until_one_closes() {
+        let slots = (0..MAX_CONNECTIONS)
+            .map(|_| ConnectionSlot::take().unwrap())
+            .collect::<Vec<_>>();
+        assert!(ConnectionSlot::take().is_none());
+        drop(slots);
+        assert!(ConnectionSlot::take().is_some());
+    }
+
+    #[test]
+    fn frames_reach_open_streams_and_closed_ones_are_dropped() {
+        let subscribers = Subscribers::default();
+        let open = subscribers.subscribe();
+        drop(subscribers.subscribe());
+
+        let frame = sse_frame("progress", 3, serde_json::json!({ "progressInc": 1 })).unwrap();
+        assert_eq!(
+            frame,
+            "event: progress\ndata: {\"id\":3,\"payload\":{\"progressInc\":1}}\n\n"
+        );
+        subscribers.send(&frame);
+        assert_eq!(open.try_recv().unwrap(), frame);
+        assert_eq!(subscribers.0.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn streams_that_fall_too_far_behind_are_dropped() {
+        let subscribers = Subscribers::default();
+        let _stalled = subscribers.subscribe();
+        for _ in 0..SUBSCRIBER_QUEUE {
+            subscribers.send("frame");
+        }
+        assert_eq!(subscribers.0.lock().unwrap().len(), 1);
+        subscribers.send("frame");
+        assert!(subscribers.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn only_local_hosts_are_allowed() {
+        let lan = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20));
+        for host in [
+            "localhost",
+            "LOCALHOST:7878",
+            "127.0.0.1",
+            "127.0.0.1:7878",
+            "[::1]:7878",
+        ] {
+            assert!(allowed_host(host, lan), "{host}");
+        }
+        assert!(allowed_host("192.168.1.20:7878", lan));
+        for host in [
+            "attacker.example",
+            "attacker.example:7878",
+            "localhost.attacker.example",
+        ] {
+            assert!(!allowed_host(host, lan), "{host}");
+        }
+        assert!(!allowed_host("192.168.1.21:7878", lan));
+    }
+}
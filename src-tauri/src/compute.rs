@@ -1,34 +1,228 @@
+mod archive;
+mod atomic;
+mod benchmark;
+mod coverage;
+mod deflicker;
+mod estimate;
 mod export;
+mod font;
 mod glyph;
+mod metadata;
+mod overlay;
+mod preview;
+#[cfg(feature = "app")]
+mod self_test;
+mod sprites;
+mod sun;
+#[cfg(test)]
+mod test_util;
+mod thumbnail;
 mod timelapse;
 mod timeline;
 mod workers;
 
 use std::{path::Path, sync::Arc, time::Duration};
 
-use crate::{compute::timelapse::TimelapseEncoder, JobInfo, SetProgressInfo};
+use crate::{compute::timelapse::TimelapseEncoder, ffmpeg, JobContext, SetProgressInfo};
 use anyhow::Context;
 use timeline::Timeline;
 
+pub use benchmark::BenchmarkResult;
+pub use coverage::{CoverageOptions, CoveragePeriod};
+pub use estimate::SizeEstimate;
+pub use export::ExportFormat;
+pub use glyph::{GlyphField, LatLng, ScrapedFields};
+pub use overlay::Overlays;
+pub use sprites::SpriteSheetOptions;
+pub use timelapse::{BatchExtraction, ChapterMode, Crop, GapPolicy};
+pub use timeline::{check_timestamps, DateRange, EmptyTimeline, TimelineOptions, TimestampReport};
+// only the app's commands use these
+#[cfg(feature = "app")]
+pub use {
+    benchmark::MAX_BENCHMARK_FRAMES,
+    estimate::MAX_ESTIMATE_FRAMES,
+    export::merge_exports,
+    glyph::{validate_glyph_config, GLYPH_CONFIG_RESOURCE},
+    preview::DEFAULT_PREVIEW_SECS,
+    self_test::self_test,
+    thumbnail::{representative_frame, DEFAULT_THUMBNAIL_CANDIDATES},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TimelapseType {
     Jpg,
     Mp4,
+    /// an animated image, for short timelapses that are embedded on the web
+    Animated(ffmpeg::AnimatedFormat),
+    /// no output, which only extracts frames to measure how fast that is
+    Null,
+}
+pub struct TimelapseSettings<'a> {
+    /// every output to create, all from the same extracted frames
+    pub types: Vec<TimelapseType>,
+    /// runtime of the output timelapse, unless it follows from `sample_rate`
+    pub length: Duration,
+    /// frames taken per second of footage, which sets the frame count instead of `length`
+    pub sample_rate: Option<f64>,
+    /// whether frames skip over the gaps between clips, or are spaced over real-world time
+    pub gaps: GapPolicy,
+    /// how long to hold the first frame for, on top of `length`
+    pub hold_start: Duration,
+    /// how long to hold the last frame for, on top of `length`
+    pub hold_end: Duration,
+    /// how long the end of the timelapse crossfades into its start for, so it loops seamlessly
+    pub loop_crossfade: Duration,
+    /// playback rate of the video output
+    pub fps: u32,
+    /// container for the video output
+    pub container: ffmpeg::Container,
+    /// how the video output's bitrate is picked
+    pub rate_control: ffmpeg::RateControl,
+    /// encode the video output in segments of this many frames, so an interrupted encode resumes
+    /// from the last finished segment when it is run again
+    pub segment_frames: Option<usize>,
+    /// options passed through to the video encoder after its own, like `-preset slow`
+    pub extra_ffmpeg_args: Vec<String>,
+    /// format frames are extracted as to feed the video encoder
+    pub intermediate: ffmpeg::Intermediate,
+    /// tune how many frames are extracted at once, instead of always using every thread
+    pub adaptive_concurrency: bool,
+    /// decode the frames of clips that contribute many in runs, instead of seeking to each
+    pub batch_extraction: Option<BatchExtraction>,
+    /// number of frames to skip from the start of the timelapse
+    pub skip: Option<u32>,
+    /// location to embed as file-level metadata
+    pub location: Option<&'a LatLng>,
+    /// the region of each frame to keep, before overlays are drawn
+    pub crop: Option<Crop>,
+    /// overlays drawn onto each frame
+    pub overlays: Overlays,
+    /// split jpg output into subdirectories of this many frames
+    pub frames_per_dir: Option<usize>,
+    /// pipe video frames to ffmpeg as raw pixels, skipping the jpg round-trip
+    pub raw_frames: bool,
+    /// the most frames that any one clip can contribute to the timelapse
+    pub max_frames_per_clip: Option<usize>,
+    /// write the real-world time and location of jpg frames into their exif data
+    pub exif: bool,
+    /// how strongly to even out brightness between frames, from 0 to 1, or `None` to leave it
+    pub deflicker: Option<f32>,
+    /// strength of the denoise filter run on each extracted frame, or `None` to leave the noise in
+    pub denoise: Option<f32>,
+    /// extract frames in color or grayscale, which also decides the channels of jpg frames
+    pub pixel_format: ffmpeg::PixelFormat,
+    /// write `frames.csv` next to the output, mapping each video frame to its real-world time
+    pub frames_csv: bool,
+    /// pause while the output volume has fewer than this many bytes free
+    pub min_free_space: Option<u64>,
+    /// split the video output into chapters
+    pub chapters: Option<ChapterMode>,
+    /// scraped location of each clip in the timeline
+    pub clip_locations: Option<&'a [LatLng]>,
+}
+impl TimelapseSettings<'_> {
+    /// how frames are extracted, which is only as the configured intermediate when there is a
+    /// video output to feed
+    fn extract_options(&self) -> ffmpeg::ExtractOptions {
+        ffmpeg::ExtractOptions {
+            intermediate: match self.types.contains(&TimelapseType::Mp4) {
+                true => self.intermediate,
+                false => ffmpeg::Intermediate::default(),
+            },
+            denoise: self.denoise,
+            pixel_format: self.pixel_format,
+        }
+    }
+    /// how the video output is encoded, into `container`
+    fn video_options(&self, container: ffmpeg::Container) -> ffmpeg::VideoOptions {
+        ffmpeg::VideoOptions {
+            fps: self.fps,
+            container,
+            rate: self.rate_control,
+            extra_args: self.extra_ffmpeg_args.clone(),
+        }
+    }
 }
 enum DynTimelapseEnc {
     Jpg(timelapse::JpgTimelapseEnc),
-    Mp4(timelapse::Mp4TimelapseEnc),
+    Mp4(Box<timelapse::Mp4TimelapseEnc>),
+    Animated(Box<timelapse::AnimatedTimelapseEnc>),
+    Null(timelapse::NullTimelapseEnc),
+    /// fans every frame out to each of the encoders
+    Multi(Vec<DynTimelapseEnc>),
 }
 impl TimelapseEncoder for DynTimelapseEnc {
-    fn encode_frame(&mut self, jpg_data: Vec<u8>) -> anyhow::Result<()> {
+    fn encode_frame(
+        &mut self,
+        frame: timelapse::Frame,
+        meta: &metadata::FrameMeta,
+    ) -> anyhow::Result<()> {
         match self {
-            Self::Jpg(e) => e.encode_frame(jpg_data),
-            Self::Mp4(e) => e.encode_frame(jpg_data),
+            Self::Jpg(e) => e.encode_frame(frame, meta),
+            Self::Mp4(e) => e.encode_frame(frame, meta),
+            Self::Animated(e) => e.encode_frame(frame, meta),
+            Self::Null(e) => e.encode_frame(frame, meta),
+            Self::Multi(encs) => {
+                let Some((last, rest)) = encs.split_last_mut() else {
+                    return Ok(());
+                };
+                for e in rest {
+                    e.encode_frame(frame.clone(), meta)?;
+                }
+                last.encode_frame(frame, meta)
+            }
         }
     }
-    fn finish(self) -> anyhow::Result<()> {
+    fn finish(self) -> anyhow::Result<u64> {
         match self {
             Self::Jpg(e) => e.finish(),
-            Self::Mp4(e) => e.finish(),
+            Self::Mp4(e) => (*e).finish(),
+            Self::Animated(e) => (*e).finish(),
+            Self::Null(e) => e.finish(),
+            Self::Multi(encs) => {
+                // finish every encoder even if one fails, so none are left half-written
+                let mut result = Ok(0);
+                for e in encs {
+                    let finished = e.finish();
+                    result = match (result, finished) {
+                        (Ok(total), Ok(bytes)) => Ok(total + bytes),
+                        (Err(e), _) | (Ok(_), Err(e)) => Err(e),
+                    };
+                }
+                result
+            }
+        }
+    }
+    fn fixed_geometry(&self) -> bool {
+        match self {
+            Self::Jpg(e) => e.fixed_geometry(),
+            Self::Mp4(e) => e.fixed_geometry(),
+            Self::Animated(e) => e.fixed_geometry(),
+            Self::Null(e) => e.fixed_geometry(),
+            Self::Multi(encs) => encs.iter().any(TimelapseEncoder::fixed_geometry),
+        }
+    }
+    fn resumed_frames(&self) -> usize {
+        match self {
+            Self::Jpg(e) => e.resumed_frames(),
+            Self::Mp4(e) => e.resumed_frames(),
+            Self::Animated(e) => e.resumed_frames(),
+            Self::Null(e) => e.resumed_frames(),
+            // a frame can only go unextracted if no encoder still needs it
+            Self::Multi(encs) => encs
+                .iter()
+                .map(TimelapseEncoder::resumed_frames)
+                .min()
+                .unwrap_or(0),
+        }
+    }
+    fn skip_frame(&mut self) {
+        match self {
+            Self::Jpg(e) => e.skip_frame(),
+            Self::Mp4(e) => e.skip_frame(),
+            Self::Animated(e) => e.skip_frame(),
+            Self::Null(e) => e.skip_frame(),
+            Self::Multi(encs) => encs.iter_mut().for_each(TimelapseEncoder::skip_frame),
         }
     }
 }
@@ -36,82 +230,276 @@ impl TimelapseEncoder for DynTimelapseEnc {
 pub struct ProcessClipsJob {
     pool: workers::WorkerPool,
     timeline: Arc<timeline::Timeline>,
+    /// the clips of the input when it is a zip, which are removed with the job
+    extracted: Option<archive::ExtractedZip>,
+}
+/// fail fast on a bad config by processing only the first clip: probing it, parsing its
+/// timestamp, and scraping `fields` from it
+pub fn check_first_clip(
+    info: &dyn JobContext,
+    input_path: &str,
+    timeline_opts: &TimelineOptions,
+    fields: &[GlyphField],
+    denoise: Option<f32>,
+) -> anyhow::Result<()> {
+    let input_path = Path::new(input_path);
+    // clips in a zip aren't extracted until the timeline is built, which is too late to be quick
+    if archive::is_zip(input_path) {
+        return Ok(());
+    }
+    let Some(clip_path) = timeline::check_first_clip(info, input_path, timeline_opts)? else {
+        return Ok(());
+    };
+    if !fields.is_empty() {
+        glyph::check_scrape(info, &clip_path, fields, denoise)?;
+    }
+    info.set_progress(SetProgressInfo::detail(format!(
+        "first clip {:?} passed the preflight check",
+        clip_path
+    )));
+    Ok(())
 }
+
 impl ProcessClipsJob {
-    pub fn new(threads: usize, info: Arc<JobInfo>, input_path: &str) -> anyhow::Result<Self> {
+    pub fn new(
+        threads: usize,
+        info: Arc<dyn JobContext>,
+        input_path: &str,
+        timeline_opts: &TimelineOptions,
+    ) -> anyhow::Result<Self> {
         let pool = workers::WorkerPool::new(threads);
-        let timeline = Timeline::new_from_path(info, &pool, input_path)
+        let input_path = Path::new(input_path);
+        let extracted = match archive::is_zip(input_path) {
+            true => Some(archive::ExtractedZip::new(&*info, input_path)?),
+            false => None,
+        };
+        let clips_path = extracted.as_ref().map_or(input_path, |zip| zip.dir());
+        let timeline = Timeline::new_from_path(info, &pool, clips_path, timeline_opts)
             .context("create Timeline from path")?;
 
         Ok(Self {
             pool,
             timeline: Arc::new(timeline),
+            extracted,
         })
     }
 
+    /// a job over just the clip at `clip_path`, for timelapsing one long recording
+    pub fn new_single_clip(
+        threads: usize,
+        info: Arc<dyn JobContext>,
+        clip_path: &str,
+        timeline_opts: &TimelineOptions,
+    ) -> anyhow::Result<Self> {
+        let timeline = Timeline::new_from_clip(&*info, Path::new(clip_path), timeline_opts)
+            .context("create Timeline from clip")?;
+        Ok(Self {
+            pool: workers::WorkerPool::new(threads),
+            timeline: Arc::new(timeline),
+            extracted: None,
+        })
+    }
+
+    /// measure extraction throughput at each of `thread_counts`, using a sample of the timeline
+    pub fn benchmark(
+        &self,
+        info: Arc<dyn JobContext>,
+        thread_counts: &[usize],
+        sample_size: usize,
+    ) -> anyhow::Result<Vec<BenchmarkResult>> {
+        info.set_progress(SetProgressInfo::detail("--- Begin benchmark ---"));
+        let results = benchmark::benchmark(
+            Arc::clone(&info),
+            Arc::clone(&self.timeline),
+            thread_counts,
+            sample_size,
+        )
+        .context("run benchmark")?;
+        info.set_progress(SetProgressInfo::detail("--- Finished benchmark ---"));
+        Ok(results)
+    }
+
+    /// estimate the output size of a timelapse, without creating it
+    pub fn estimate_size(
+        &self,
+        info: Arc<dyn JobContext>,
+        settings: &TimelapseSettings,
+        sample_size: usize,
+    ) -> anyhow::Result<SizeEstimate> {
+        info.set_progress(SetProgressInfo::detail("--- Begin size estimate ---"));
+        let estimate = estimate::estimate_size(
+            Arc::clone(&info),
+            Arc::clone(&self.timeline),
+            &self.pool,
+            settings,
+            sample_size,
+        )
+        .context("estimate timelapse size")?;
+        info.set_progress(SetProgressInfo::detail("--- Finished size estimate ---"));
+        Ok(estimate)
+    }
+
+    /// encode the first `secs` seconds of the timelapse into an mp4 data url, to check its
+    /// quality before a full run
+    pub fn encode_preview(
+        &self,
+        info: Arc<dyn JobContext>,
+        settings: &TimelapseSettings,
+        secs: u32,
+    ) -> anyhow::Result<String> {
+        info.set_progress(SetProgressInfo::detail("--- Begin preview encode ---"));
+        let preview = preview::encode_preview(
+            Arc::clone(&info),
+            Arc::clone(&self.timeline),
+            &self.pool,
+            settings,
+            secs,
+        )
+        .context("encode timelapse preview")?;
+        info.set_progress(SetProgressInfo::detail("--- Finished preview encode ---"));
+        Ok(preview)
+    }
+
     pub fn create_timelapse<P: AsRef<Path>>(
         &self,
-        info: Arc<JobInfo>,
-        typ: TimelapseType,
-        length: Duration,
-        fps: u32,
-        skip: Option<u32>,
+        info: Arc<dyn JobContext>,
+        settings: TimelapseSettings,
         output_dir: P,
     ) -> anyhow::Result<()> {
         info.set_progress(SetProgressInfo::detail("--- Begin timelapsing ---"));
-        let enc = match typ {
-            TimelapseType::Jpg => {
-                DynTimelapseEnc::Jpg(timelapse::JpgTimelapseEnc::new(output_dir.as_ref()))
-            }
-            TimelapseType::Mp4 => DynTimelapseEnc::Mp4(
-                timelapse::Mp4TimelapseEnc::new(output_dir.as_ref().join("output.mp4"), fps)
+        let (timestamps, black) = timelapse::timelapse_frames(&*info, &self.timeline, &settings);
+        let chapters = settings
+            .chapters
+            .map(|mode| timelapse::chapters(&self.timeline, &timestamps, &settings, mode))
+            .unwrap_or_default();
+        let mut encs = Vec::with_capacity(settings.types.len());
+        for typ in &settings.types {
+            encs.push(match typ {
+                TimelapseType::Jpg => DynTimelapseEnc::Jpg(timelapse::JpgTimelapseEnc::new(
+                    output_dir.as_ref(),
+                    settings.frames_per_dir,
+                    settings.exif,
+                    settings.pixel_format == ffmpeg::PixelFormat::Gray,
+                )),
+                TimelapseType::Mp4 => DynTimelapseEnc::Mp4(Box::new(
+                    timelapse::Mp4TimelapseEnc::new(
+                        Arc::clone(&info),
+                        output_dir
+                            .as_ref()
+                            .join("output")
+                            .with_extension(settings.container.extension()),
+                        settings.location,
+                        timelapse::Mp4Options {
+                            video: settings.video_options(settings.container),
+                            raw: settings.raw_frames,
+                            intermediate: settings.intermediate,
+                            chapters: chapters.clone(),
+                            segments: settings.segment_frames.map(|frames_per_segment| {
+                                timelapse::SegmentPlan {
+                                    frames_per_segment,
+                                    total_frames: timelapse::output_frame_count(
+                                        timestamps.len(),
+                                        &settings,
+                                    ),
+                                    frames: timelapse::frames_fingerprint(
+                                        &self.timeline,
+                                        &timestamps,
+                                        &settings,
+                                    ),
+                                }
+                            }),
+                        },
+                    )
                     .context("create mp4 timelapse encoder")?,
-            ),
+                )),
+                TimelapseType::Animated(format) => DynTimelapseEnc::Animated(Box::new(
+                    timelapse::AnimatedTimelapseEnc::new(
+                        output_dir
+                            .as_ref()
+                            .join("output")
+                            .with_extension(format.extension()),
+                        settings.fps,
+                        *format,
+                        timelapse::output_frame_count(timestamps.len(), &settings),
+                    )
+                    .with_context(|| format!("create {} timelapse encoder", format.extension()))?,
+                )),
+                TimelapseType::Null => {
+                    DynTimelapseEnc::Null(timelapse::NullTimelapseEnc::new(Arc::clone(&info)))
+                }
+            });
+        }
+        let enc = match encs.len() {
+            1 => encs.pop().expect("one encoder"),
+            _ => DynTimelapseEnc::Multi(encs),
         };
         timelapse::timelapse(
             Arc::clone(&info),
             Arc::clone(&self.timeline),
             &self.pool,
             enc,
-            length,
-            fps,
-            skip,
+            &settings,
+            timestamps,
+            black,
+            output_dir.as_ref(),
         )
         .context("create timelapse")?;
+        info.set_progress(SetProgressInfo::detail(format!(
+            "decoded clips in {}",
+            ffmpeg::decode_path()
+        )));
         info.set_progress(SetProgressInfo::detail("--- Finished timelapsing ---"));
         Ok(())
     }
 
-    pub fn export_data<P: AsRef<Path>>(
+    /// scrape `fields` from every `every`th clip, filling in the rest from the clip before them
+    ///
+    /// frames are run through a denoise filter of strength `denoise` first, if it is set
+    pub fn scrape_fields<P: AsRef<Path>>(
         &self,
-        info: Arc<JobInfo>,
-        location: bool,
+        info: Arc<dyn JobContext>,
+        fields: Vec<GlyphField>,
+        every: usize,
+        denoise: Option<f32>,
         output_dir: P,
-    ) -> anyhow::Result<()> {
+    ) -> anyhow::Result<ScrapedFields> {
         info.set_progress(SetProgressInfo {
             total: Some(0),
             progress: Some(0),
-            detail: Some("--- Begin exporting timeline ---".into()),
+            detail: Some("--- Begin scraping glyphs ---".into()),
             ..Default::default()
         });
-        let locations = if location {
-            Some(
-                glyph::scrape_locations(
-                    Arc::clone(&info),
-                    Arc::clone(&self.timeline),
-                    &self.pool,
-                    output_dir.as_ref(),
-                )
-                .context("scrape locations")?,
-            )
-        } else {
-            None
-        };
+        let scraped = glyph::scrape_fields(
+            Arc::clone(&info),
+            Arc::clone(&self.timeline),
+            &self.pool,
+            fields,
+            every,
+            denoise,
+            output_dir.as_ref(),
+        )
+        .context("scrape glyph fields")?;
+        info.set_progress(SetProgressInfo::detail("--- Finished scraping glyphs ---"));
+        Ok(scraped)
+    }
+
+    pub fn export_data<P: AsRef<Path>>(
+        &self,
+        info: Arc<dyn JobContext>,
+        scraped: Option<&ScrapedFields>,
+        output_dir: P,
+        format: &ExportFormat,
+        min_free_space: Option<u64>,
+    ) -> anyhow::Result<()> {
+        info.set_progress(SetProgressInfo::detail("--- Begin exporting timeline ---"));
         export::export_timeline(
-            &info,
+            &*info,
             &self.timeline,
-            locations.as_deref(),
+            self.extracted.as_ref(),
+            scraped,
             output_dir.as_ref(),
+            format,
+            min_free_space,
         )
         .context("export timeline")?;
         info.set_progress(SetProgressInfo::detail(
@@ -119,4 +507,44 @@ impl ProcessClipsJob {
         ));
         Ok(())
     }
+
+    /// when the timeline's footage starts and stops, for picking a sub-range of it
+    pub fn date_range(&self) -> Option<DateRange> {
+        self.timeline.date_range()
+    }
+
+    /// total how much footage was recorded in each hour or day, to show gaps in the archive
+    pub fn export_coverage<P: AsRef<Path>>(
+        &self,
+        info: Arc<dyn JobContext>,
+        opts: &CoverageOptions,
+        output_dir: P,
+    ) -> anyhow::Result<()> {
+        info.set_progress(SetProgressInfo::detail("--- Begin coverage export ---"));
+        coverage::export_coverage(&*info, &self.timeline, opts, output_dir.as_ref())
+            .context("export coverage")?;
+        info.set_progress(SetProgressInfo::detail("--- Finished coverage export ---"));
+        Ok(())
+    }
+
+    /// tile a thumbnail of every clip into sprite sheets, with a json index of where each is
+    pub fn export_sprite_sheet<P: AsRef<Path>>(
+        &self,
+        info: Arc<dyn JobContext>,
+        opts: &SpriteSheetOptions,
+        output_dir: P,
+    ) -> anyhow::Result<()> {
+        info.set_progress(SetProgressInfo::detail("--- Begin sprite sheet ---"));
+        sprites::export_sprite_sheet(
+            Arc::clone(&info),
+            &self.timeline,
+            self.extracted.as_ref(),
+            &self.pool,
+            opts,
+            output_dir.as_ref(),
+        )
+        .context("export sprite sheet")?;
+        info.set_progress(SetProgressInfo::detail("--- Finished sprite sheet ---"));
+        Ok(())
+    }
 }
@@ -0,0 +1,134 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+use crate::{JobContext, SetProgressInfo};
+
+/// whether `path` is a zip archive to read clips out of, rather than a directory of clips
+pub fn is_zip(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("zip"))
+}
+
+/// the clips of a zip, extracted into a temp dir that is removed when this is dropped
+pub struct ExtractedZip {
+    dir: tempfile::TempDir,
+    zip_path: PathBuf,
+}
+impl ExtractedZip {
+    /// extract every .mp4 clip in the zip at `zip_path`
+    pub fn new(info: &dyn JobContext, zip_path: &Path) -> anyhow::Result<Self> {
+        let dir = tempfile::tempdir().context("create temp dir for zip clips")?;
+        let clips =
+            extract_zip_clips(info, zip_path, dir.path()).context("extract clips from zip")?;
+        if clips == 0 {
+            anyhow::bail!("{:?} does not contain any .mp4 clips", zip_path);
+        }
+        Ok(Self {
+            dir,
+            zip_path: zip_path.to_path_buf(),
+        })
+    }
+    /// where the clips were extracted to
+    pub fn dir(&self) -> &Path {
+        self.dir.path()
+    }
+    /// the zip's path joined with where `clip_path` was in it, for outputs that outlive the
+    /// extracted copy
+    pub fn source_path(&self, clip_path: &Path) -> PathBuf {
+        match clip_path.strip_prefix(self.dir()) {
+            Ok(name) => self.zip_path.join(name),
+            Err(_) => clip_path.to_path_buf(),
+        }
+    }
+}
+
+/// extract every .mp4 clip in the zip at `zip_path` into `dir`, keeping the directories they were in
+/// so that `max_depth` still means the same thing
+///
+/// returns the number of clips extracted
+fn extract_zip_clips(info: &dyn JobContext, zip_path: &Path, dir: &Path) -> anyhow::Result<usize> {
+    let file = fs::File::open(zip_path).with_context(|| format!("open {:?}", zip_path))?;
+    let mut archive = zip::ZipArchive::new(io::BufReader::new(file)).context("read zip archive")?;
+    info.set_progress(SetProgressInfo::detail(format!(
+        "extracting clips from {:?}",
+        zip_path
+    )));
+
+    let mut extracted = 0;
+    for i in 0..archive.len() {
+        info.cancel_result()?;
+        let mut entry = archive.by_index(i).context("read zip entry")?;
+        // entries that would escape `dir` have no enclosed name, and are skipped with everything else
+        let Some(name) = entry.enclosed_name() else {
+            continue;
+        };
+        let is_clip = name
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("mp4"));
+        if entry.is_dir() || !is_clip {
+            continue;
+        }
+
+        let out_path = dir.join(&name);
+        if let Some(parent) = out_path.parent() {
+            fs::create_dir_all(parent).with_context(|| format!("create dir {:?}", parent))?;
+        }
+        let mut out =
+            fs::File::create(&out_path).with_context(|| format!("create {:?}", out_path))?;
+        io::copy(&mut entry, &mut out).with_context(|| format!("extract {:?}", name))?;
+        extracted += 1;
+    }
+
+    info.set_progress(SetProgressInfo::detail(format!(
+        "extracted {} clips from {:?}",
+        extracted, zip_path
+    )));
+    Ok(extracted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract_zip_clips, ExtractedZip};
+    use crate::compute::test_util::NoProgress;
+    use std::{io::Write, path::Path};
+
+    #[test]
+    fn clips_are_extracted_without_escaping_the_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let zip_path = dir.path().join("clips.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        for name in ["day1/a.mp4", "notes.txt", "../escaped.mp4", "b.MP4"] {
+            zip.start_file(name, zip::write::SimpleFileOptions::default())
+                .unwrap();
+            zip.write_all(name.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let out = dir.path().join("out");
+        std::fs::create_dir(&out).unwrap();
+        assert_eq!(extract_zip_clips(&NoProgress, &zip_path, &out).unwrap(), 2);
+        assert_eq!(
+            std::fs::read_to_string(out.join("day1/a.mp4")).unwrap(),
+            "day1/a.mp4"
+        );
+        assert!(out.join("b.MP4").is_file());
+        assert!(!out.join("notes.txt").exists());
+        assert!(!dir.path().join("escaped.mp4").exists());
+
+        let extracted = ExtractedZip::new(&NoProgress, &zip_path).unwrap();
+        assert_eq!(
+            extracted.source_path(&extracted.dir().join("day1/a.mp4")),
+            zip_path.join("day1/a.mp4")
+        );
+        assert_eq!(
+            extracted.source_path(Path::new("elsewhere.mp4")),
+            Path::new("elsewhere.mp4")
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use std::{
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+/// a file written next to its final path and only renamed over it once it is complete, so a crash
+/// mid-write leaves the previous version instead of a truncated one
+pub(super) struct AtomicFile {
+    file: tempfile::NamedTempFile,
+    path: PathBuf,
+}
+impl AtomicFile {
+    pub(super) fn create(path: &Path) -> anyhow::Result<Self> {
+        // the temp file has to be on the same volume for the rename to be atomic
+        let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+        let mut builder = tempfile::Builder::new();
+        builder.prefix(".").suffix(".part");
+        // temp files are only readable by their owner, so ask for what a plain file gets instead,
+        // which the umask is still applied to
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            builder.permissions(std::fs::Permissions::from_mode(0o666));
+        }
+        let file = builder
+            .tempfile_in(dir.unwrap_or(Path::new(".")))
+            .with_context(|| format!("create temp file for {:?}", path))?;
+        // a replaced file keeps the permissions it had
+        #[cfg(unix)]
+        if let Ok(meta) = std::fs::metadata(path) {
+            file.as_file()
+                .set_permissions(meta.permissions())
+                .with_context(|| format!("copy permissions of {:?}", path))?;
+        }
+        Ok(Self {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+    /// replace the file at the final path with everything written, which is removed instead if
+    /// this is dropped first
+    pub(super) fn commit(mut self) -> anyhow::Result<()> {
+        self.file
+            .as_file_mut()
+            .sync_all()
+            .with_context(|| format!("sync {:?}", self.path))?;
+        self.file
+            .persist(&self.path)
+            .with_context(|| format!("move finished file to {:?}", self.path))?;
+        Ok(())
+    }
+}
+impl Write for AtomicFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// like `std::fs::write`, but through an `AtomicFile`
+pub(super) fn write_atomically(path: &Path, contents: impl AsRef<[u8]>) -> anyhow::Result<()> {
+    let mut file = AtomicFile::create(path)?;
+    file.write_all(contents.as_ref())
+        .with_context(|| format!("write {:?}", path))?;
+    file.commit()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_atomically, AtomicFile};
+    use std::io::Write;
+
+    #[test]
+    fn unfinished_writes_leave_the_previous_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output.json");
+        write_atomically(&path, "old").unwrap();
+
+        let mut file = AtomicFile::create(&path).unwrap();
+        file.write_all(b"half of the new").unwrap();
+        drop(file);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "old");
+
+        write_atomically(&path, "new").unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "new");
+        // neither write leaves its temp file behind
+        assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn written_files_have_the_permissions_of_plain_files() {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = |path: &std::path::Path| std::fs::metadata(path).unwrap().permissions().mode();
+        let dir = tempfile::tempdir().unwrap();
+        let plain = dir.path().join("plain.json");
+        std::fs::write(&plain, "plain").unwrap();
+        let path = dir.path().join("output.json");
+        write_atomically(&path, "new").unwrap();
+        assert_eq!(mode(&path), mode(&plain));
+
+        // an existing file keeps its permissions when it's replaced
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o640)).unwrap();
+        write_atomically(&path, "newer").unwrap();
+        assert_eq!(mode(&path) & 0o777, 0o640);
+    }
+}
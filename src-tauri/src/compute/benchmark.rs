@@ -0,0 +1,165 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    compute::{
+        timelapse::{extraction_runs, BatchExtraction, MAX_FRAMES_PER_RUN},
+        timeline::Timeline,
+        workers::WorkerPool,
+    },
+    ffmpeg, JobContext, SetProgressInfo,
+};
+
+/// upper bound on the number of frames extracted per thread count, to keep the benchmark quick
+pub const MAX_BENCHMARK_FRAMES: usize = 100;
+/// frames sampled from each clip, so batched runs have several frames of a clip to decode together
+/// like they would in a timelapse
+const FRAMES_PER_CLIP: usize = 8;
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BenchmarkResult {
+    pub threads: usize,
+    /// whether the frames of each clip were decoded together in runs, instead of each on its own
+    pub batched: bool,
+    /// number of frames successfully extracted
+    pub frames: usize,
+    pub elapsed_secs: f64,
+    pub frames_per_sec: f64,
+}
+
+/// `sample_size` timestamps, `FRAMES_PER_CLIP` evenly spaced in each of the clips spread evenly
+/// over the timeline
+fn sample_timestamps(timeline: &Timeline, sample_size: usize) -> Vec<Duration> {
+    let clips = timeline
+        .iter()
+        .scan(Duration::ZERO, |offset, clip| {
+            let start = *offset;
+            *offset += clip.length;
+            Some((start, clip.length))
+        })
+        .filter(|&(_, len)| !len.is_zero())
+        .collect::<Vec<_>>();
+    if clips.is_empty() || sample_size == 0 {
+        return Vec::new();
+    }
+    let sampled_clips = sample_size.div_ceil(FRAMES_PER_CLIP).min(clips.len());
+    let per_clip = sample_size.div_ceil(sampled_clips);
+    (0..sampled_clips)
+        .flat_map(|i| {
+            let (start, len) = clips[i * clips.len() / sampled_clips];
+            (0..per_clip as u32).map(move |k| start + len * k / per_clip as u32)
+        })
+        .take(sample_size)
+        .collect()
+}
+
+/// time extracting `sample_size` frames from the timeline at each thread count, both seeking to
+/// every frame and decoding the frames of each clip in runs spread over the threads
+pub fn benchmark(
+    info: Arc<dyn JobContext>,
+    timeline: Arc<Timeline>,
+    thread_counts: &[usize],
+    sample_size: usize,
+) -> anyhow::Result<Vec<BenchmarkResult>> {
+    let timestamps = sample_timestamps(&timeline, sample_size.clamp(1, MAX_BENCHMARK_FRAMES));
+    let sample_size = timestamps.len();
+
+    let clips = timestamps
+        .iter()
+        .map(|&ts| Some(timeline.clip_index_at(ts)))
+        .collect::<Vec<_>>();
+
+    info.set_progress(SetProgressInfo {
+        progress: Some(0),
+        total: Some(thread_counts.len() * sample_size * 2),
+        ..Default::default()
+    });
+
+    let mut results = Vec::with_capacity(thread_counts.len() * 2);
+    for &threads in thread_counts {
+        let threads = threads.max(1);
+        // the longest runs that the timelapse would split a clip into
+        let batch = BatchExtraction {
+            min_frames: 2,
+            frames_per_run: MAX_FRAMES_PER_RUN,
+        };
+        for batch in [None, Some(batch)] {
+            info.cancel_result()?;
+
+            let pool = WorkerPool::new(threads);
+            let start = Instant::now();
+            let jobs = pool.run_channel(extraction_runs(&clips, batch).into_iter().map(|run| {
+                let info = Arc::clone(&info);
+                let timeline = Arc::clone(&timeline);
+                let run = timestamps[run].to_vec();
+                move || {
+                    info.cancel_result()?;
+                    let (clip_ts, clip) = timeline.get_at(run[0]);
+                    let res = match run.as_slice() {
+                        &[ts] => ffmpeg::extract_frame(&clip.path, ts - clip_ts).map(|_| 1),
+                        run => {
+                            let in_clip = run.iter().map(|&ts| ts - clip_ts).collect::<Vec<_>>();
+                            ffmpeg::extract_frames(&clip.path, &in_clip).map(|frames| frames.len())
+                        }
+                    };
+                    info.log_ffmpeg_diagnostics(&clip.path);
+                    info.set_progress(SetProgressInfo {
+                        progress_inc: Some(run.len()),
+                        ..Default::default()
+                    });
+                    res
+                }
+            }));
+            let frames = jobs.filter_map(Result::ok).sum::<usize>();
+            let elapsed = start.elapsed();
+            info.cancel_result()?;
+
+            let result = BenchmarkResult {
+                threads,
+                batched: batch.is_some(),
+                frames,
+                elapsed_secs: elapsed.as_secs_f64(),
+                frames_per_sec: frames as f64 / elapsed.as_secs_f64(),
+            };
+            info.set_progress(SetProgressInfo::detail(format!(
+                "{} thread(s){}: extracted {} frames in {:.02}s ({:.02} frames/sec)",
+                result.threads,
+                if result.batched { ", batched" } else { "" },
+                result.frames,
+                result.elapsed_secs,
+                result.frames_per_sec
+            )));
+            results.push(result);
+        }
+    }
+    Ok(results)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{sample_timestamps, FRAMES_PER_CLIP};
+    use crate::compute::timeline::{Timeline, TimelineClip};
+    use std::time::Duration;
+
+    #[test]
+    fn samples_are_grouped_into_clips() {
+        let clip = |len| TimelineClip {
+            creation_time: Default::default(),
+            length: Duration::from_secs(len),
+            path: Default::default(),
+            unreliable_timestamps: false,
+        };
+        let timeline = Timeline::from_sorted_clips((0..10).map(|_| clip(80)).collect());
+        let timestamps = sample_timestamps(&timeline, 2 * FRAMES_PER_CLIP);
+        let clips = timestamps
+            .iter()
+            .map(|&ts| timeline.clip_index_at(ts))
+            .collect::<Vec<_>>();
+        assert_eq!(clips[..FRAMES_PER_CLIP], [0; FRAMES_PER_CLIP]);
+        assert_eq!(clips[FRAMES_PER_CLIP..], [5; FRAMES_PER_CLIP]);
+        assert_eq!(timestamps[1], Duration::from_secs(10));
+    }
+}
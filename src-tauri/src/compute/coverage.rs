@@ -0,0 +1,192 @@
+use std::{collections::BTreeMap, io::Cursor, path::Path};
+
+use anyhow::Context;
+use chrono::{NaiveDateTime, NaiveTime, TimeDelta, Timelike};
+use image::{Rgb, RgbImage};
+
+use crate::{
+    compute::{
+        atomic::write_atomically,
+        timeline::{Timeline, CLIP_TIMEZONE},
+    },
+    JobContext, SetProgressInfo,
+};
+
+/// height of the coverage chart in pixels
+const CHART_HEIGHT: u32 = 200;
+/// roughly how wide the coverage chart is, unless there are more periods than pixels
+const CHART_WIDTH: u32 = 1200;
+
+/// the length of time that recorded footage is totalled over
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CoveragePeriod {
+    Hour,
+    #[default]
+    Day,
+}
+impl CoveragePeriod {
+    fn length(self) -> TimeDelta {
+        match self {
+            Self::Hour => TimeDelta::hours(1),
+            Self::Day => TimeDelta::days(1),
+        }
+    }
+    /// the start of the period that `time` falls within
+    fn start_of(self, time: NaiveDateTime) -> NaiveDateTime {
+        match self {
+            Self::Hour => {
+                time.date().and_time(NaiveTime::MIN) + TimeDelta::hours(time.hour().into())
+            }
+            Self::Day => time.date().and_time(NaiveTime::MIN),
+        }
+    }
+}
+
+/// how the coverage of the timeline is totalled and whether it is charted
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct CoverageOptions {
+    pub period: CoveragePeriod,
+    /// also render the coverage as a bar chart png
+    pub chart: bool,
+}
+
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CoverageEntry {
+    /// local start time of the period, in the timezone the clips were recorded in
+    start: String,
+    seconds: f64,
+}
+
+/// total the seconds recorded in every period from the first clip to the last, splitting clips
+/// that cross into the next period, and keeping periods with nothing recorded as gaps
+fn bucket_coverage(timeline: &Timeline, period: CoveragePeriod) -> Vec<CoverageEntry> {
+    let mut buckets = BTreeMap::<NaiveDateTime, f64>::new();
+    for clip in timeline.iter() {
+        let mut cursor = clip
+            .creation_time
+            .with_timezone(&CLIP_TIMEZONE)
+            .naive_local();
+        let end = cursor + TimeDelta::from_std(clip.length).unwrap_or(TimeDelta::MAX);
+        while cursor < end {
+            let start = period.start_of(cursor);
+            let next = (start + period.length()).min(end);
+            *buckets.entry(start).or_default() += (next - cursor).as_seconds_f64();
+            cursor = next;
+        }
+    }
+
+    let (Some(&first), Some(&last)) = (buckets.keys().next(), buckets.keys().next_back()) else {
+        return Vec::new();
+    };
+    let mut entries = Vec::new();
+    let mut start = first;
+    while start <= last {
+        entries.push(CoverageEntry {
+            start: start.format("%Y-%m-%dT%H:%M:%S").to_string(),
+            seconds: buckets.get(&start).copied().unwrap_or_default(),
+        });
+        start += period.length();
+    }
+    entries
+}
+
+/// a bar per period, filled to how much of the period was recorded
+fn render_chart(entries: &[CoverageEntry], period: CoveragePeriod) -> RgbImage {
+    let bar_width = (CHART_WIDTH / entries.len().max(1) as u32).max(1);
+    let period_secs = period.length().as_seconds_f64();
+    let mut chart = RgbImage::from_pixel(
+        bar_width * entries.len() as u32,
+        CHART_HEIGHT,
+        Rgb([32, 32, 32]),
+    );
+    for (i, entry) in entries.iter().enumerate() {
+        let filled = (entry.seconds / period_secs).clamp(0.0, 1.0);
+        let height = (filled * CHART_HEIGHT as f64).round() as u32;
+        for x in i as u32 * bar_width..(i as u32 + 1) * bar_width {
+            for y in CHART_HEIGHT - height..CHART_HEIGHT {
+                chart.put_pixel(x, y, Rgb([90, 180, 250]));
+            }
+        }
+    }
+    chart
+}
+
+/// write how many seconds were recorded in each period to `coverage.json` and `coverage.csv`,
+/// and chart them into `coverage.png` if asked to
+pub fn export_coverage(
+    info: &dyn JobContext,
+    timeline: &Timeline,
+    opts: &CoverageOptions,
+    output_dir: &Path,
+) -> anyhow::Result<()> {
+    let entries = bucket_coverage(timeline, opts.period);
+
+    let json = serde_json::to_string_pretty(&entries)?;
+    write_atomically(&output_dir.join("coverage.json"), &json).context("write coverage json")?;
+    info.add_bytes_written(json.len() as u64);
+
+    let mut csv = String::from("period_start,seconds_recorded\n");
+    for entry in &entries {
+        csv.push_str(&format!("{},{:.3}\n", entry.start, entry.seconds));
+    }
+    write_atomically(&output_dir.join("coverage.csv"), &csv).context("write coverage csv")?;
+    info.add_bytes_written(csv.len() as u64);
+
+    if opts.chart && !entries.is_empty() {
+        let mut png = Vec::new();
+        render_chart(&entries, opts.period)
+            .write_to(&mut Cursor::new(&mut png), image::ImageFormat::Png)
+            .context("encode coverage chart")?;
+        write_atomically(&output_dir.join("coverage.png"), &png).context("write coverage chart")?;
+        info.add_bytes_written(png.len() as u64);
+    }
+
+    let gaps = entries.iter().filter(|entry| entry.seconds == 0.0).count();
+    info.set_progress(SetProgressInfo::detail(format!(
+        "totalled coverage over {} periods, {} of which have nothing recorded",
+        entries.len(),
+        gaps
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bucket_coverage, CoveragePeriod};
+    use crate::compute::timeline::{Timeline, TimelineClip, CLIP_TIMEZONE};
+    use chrono::TimeZone;
+    use std::{path::PathBuf, time::Duration};
+
+    #[test]
+    fn clips_are_split_across_periods_and_gaps_kept() {
+        let at = |d, h, m| {
+            CLIP_TIMEZONE
+                .with_ymd_and_hms(2024, 5, d, h, m, 0)
+                .unwrap()
+                .to_utc()
+        };
+        let clip = |creation_time, mins: u64| TimelineClip {
+            creation_time,
+            length: Duration::from_secs(mins * 60),
+            path: PathBuf::new(),
+            unreliable_timestamps: false,
+        };
+        let timeline = Timeline::from_sorted_clips(vec![
+            clip(at(1, 10, 30), 60),
+            clip(at(1, 12, 0), 15),
+            clip(at(3, 23, 50), 20),
+        ]);
+
+        let hours = bucket_coverage(&timeline, CoveragePeriod::Hour);
+        assert_eq!(hours[0].start, "2024-05-01T10:00:00");
+        let secs: Vec<_> = hours[..3].iter().map(|entry| entry.seconds).collect();
+        assert_eq!(secs, [1800.0, 1800.0, 900.0]);
+
+        let days = bucket_coverage(&timeline, CoveragePeriod::Day);
+        let secs: Vec<_> = days.iter().map(|entry| entry.seconds).collect();
+        assert_eq!(secs, [4500.0, 0.0, 600.0, 600.0]);
+    }
+}
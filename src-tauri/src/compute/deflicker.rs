@@ -0,0 +1,85 @@
+use std::collections::VecDeque;
+
+use image::RgbImage;
+
+/// number of recent frames whose brightness makes up the target
+const WINDOW_FRAMES: usize = 15;
+/// bounds on how much one frame can be brightened or darkened, so a black frame isn't blown out
+const MIN_GAIN: f64 = 0.5;
+const MAX_GAIN: f64 = 2.0;
+
+/// evens out the brightness of consecutive frames toward a rolling average, to stop exposure
+/// changes from flickering in the timelapse
+pub struct Deflicker {
+    /// 0 leaves frames untouched, 1 moves them all the way to the target
+    strength: f64,
+    recent: VecDeque<f64>,
+}
+impl Deflicker {
+    pub fn new(strength: f32) -> Self {
+        Self {
+            strength: (strength as f64).clamp(0.0, 1.0),
+            recent: VecDeque::with_capacity(WINDOW_FRAMES),
+        }
+    }
+
+    /// adjust `img` toward the mean brightness of the frames before it
+    pub fn apply(&mut self, img: &mut RgbImage) {
+        let luma = mean_luma(img);
+        if self.recent.len() >= WINDOW_FRAMES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(luma);
+        let target = self.recent.iter().sum::<f64>() / self.recent.len() as f64;
+        if luma < 1.0 {
+            return;
+        }
+
+        let gain = 1.0 + self.strength * ((target / luma).clamp(MIN_GAIN, MAX_GAIN) - 1.0);
+        if (gain - 1.0).abs() < 0.005 {
+            return;
+        }
+        for channel in img.iter_mut() {
+            *channel = (*channel as f64 * gain).round().min(255.0) as u8;
+        }
+    }
+}
+
+fn mean_luma(img: &RgbImage) -> f64 {
+    let gray = image::imageops::grayscale(img);
+    let n = gray.pixels().len() as f64;
+    if n == 0.0 {
+        return 0.0;
+    }
+    gray.pixels().map(|p| p.0[0] as f64).sum::<f64>() / n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{mean_luma, Deflicker};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn brightness_spikes_are_pulled_toward_recent_frames() {
+        let mut deflicker = Deflicker::new(1.0);
+        for _ in 0..10 {
+            let mut img = RgbImage::from_pixel(4, 4, Rgb([100, 100, 100]));
+            deflicker.apply(&mut img);
+            assert_eq!(img.get_pixel(0, 0), &Rgb([100, 100, 100]));
+        }
+
+        let mut spike = RgbImage::from_pixel(4, 4, Rgb([200, 200, 200]));
+        deflicker.apply(&mut spike);
+        let luma = mean_luma(&spike);
+        assert!(luma < 120.0, "spike was only brought down to {}", luma);
+    }
+
+    #[test]
+    fn zero_strength_leaves_frames_untouched() {
+        let mut deflicker = Deflicker::new(0.0);
+        deflicker.apply(&mut RgbImage::from_pixel(4, 4, Rgb([50, 50, 50])));
+        let mut img = RgbImage::from_pixel(4, 4, Rgb([200, 200, 200]));
+        deflicker.apply(&mut img);
+        assert_eq!(img.get_pixel(0, 0), &Rgb([200, 200, 200]));
+    }
+}
@@ -0,0 +1,189 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+
+use crate::{
+    compute::{
+        timelapse, timeline::Timeline, workers::WorkerPool, TimelapseSettings, TimelapseType,
+    },
+    ffmpeg, JobContext, SetProgressInfo,
+};
+
+/// upper bound on the number of frames encoded for an estimate, to keep it quick
+pub const MAX_ESTIMATE_FRAMES: usize = 30;
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SizeEstimate {
+    /// number of frames in the full timelapse
+    pub frames: usize,
+    /// runtime of the full timelapse
+    pub duration_secs: f64,
+    /// number of frames actually encoded to make the estimate
+    pub sample_frames: usize,
+    pub estimated_mb: f64,
+}
+
+/// estimate the output size by encoding a sample of evenly spaced frames and extrapolating
+pub fn estimate_size(
+    info: Arc<dyn JobContext>,
+    timeline: Arc<Timeline>,
+    pool: &WorkerPool,
+    settings: &TimelapseSettings,
+    sample_size: usize,
+) -> anyhow::Result<SizeEstimate> {
+    let timestamps = timelapse::timelapse_timestamps(&*info, &timeline, settings);
+    let frames = timestamps.len();
+    if frames == 0 {
+        anyhow::bail!("timelapse would not have any frames");
+    }
+    let sample_size = sample_size.clamp(1, MAX_ESTIMATE_FRAMES).min(frames);
+    let sample = (0..sample_size)
+        .map(|i| timestamps[i * frames / sample_size])
+        .collect::<Vec<_>>();
+
+    info.set_progress(SetProgressInfo {
+        progress: Some(0),
+        total: Some(sample_size),
+        ..Default::default()
+    });
+
+    let overlays = Arc::new(settings.overlays.clone());
+    let extract = settings.extract_options();
+    let intermediate = extract.intermediate;
+    let crop = settings.crop;
+    let jobs = pool.run_ordered_channel(sample.into_iter().map(|ts| {
+        let info = Arc::clone(&info);
+        let timeline = Arc::clone(&timeline);
+        let overlays = Arc::clone(&overlays);
+        move || {
+            info.cancel_result()?;
+            // the picture-in-picture is sampled at the frame itself, which is close enough in size
+            let frame = timelapse::extract_timelapse_frame(
+                &timeline,
+                &overlays,
+                ts,
+                false,
+                extract,
+                crop,
+                Some(ts),
+            );
+            info.log_ffmpeg_diagnostics(&timeline.get_at(ts).1.path);
+            frame
+        }
+    }));
+
+    // jpg output is just the frames themselves, but video and animated outputs have to actually
+    // be encoded to be measured
+    let temp_dir = tempfile::tempdir().context("create temp dir for sample encode")?;
+    let sample_path = temp_dir
+        .path()
+        .join("sample")
+        .with_extension(settings.container.extension());
+    // a target size is what the video output comes out at, so there's nothing to measure
+    let (mut enc, target_mb) = match (
+        settings.types.contains(&TimelapseType::Mp4),
+        settings.rate_control,
+    ) {
+        (false, _) => (None, 0.0),
+        (true, ffmpeg::RateControl::TargetSize { target_mb }) => (None, target_mb),
+        (true, ffmpeg::RateControl::Crf) => (
+            Some(
+                ffmpeg::Mp4FrameEncoder::new(
+                    &sample_path,
+                    intermediate.frame_input(),
+                    &[],
+                    &[],
+                    &settings.video_options(settings.container),
+                )
+                .context("create sample encoder")?,
+            ),
+            0.0,
+        ),
+    };
+    let mut animated_encs = settings
+        .types
+        .iter()
+        .filter_map(|ty| match ty {
+            TimelapseType::Animated(format) => Some(*format),
+            _ => None,
+        })
+        .map(|format| {
+            let path = temp_dir
+                .path()
+                .join("sample")
+                .with_extension(format.extension());
+            ffmpeg::Mp4FrameEncoder::animated(
+                &path,
+                settings.fps,
+                format,
+                ffmpeg::FrameInput::Mjpeg,
+            )
+            .map(|enc| (path, enc))
+            .with_context(|| format!("create sample {} encoder", format.extension()))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let counts_jpg = settings.types.contains(&TimelapseType::Jpg);
+    let mut encoded_frames = 0;
+    let mut jpg_bytes = 0;
+    for (i, job) in jobs.into_iter().enumerate() {
+        info.cancel_result()?;
+        match job.with_context(|| format!("extract sample frame {}", i)) {
+            Ok(frame) => {
+                if let Some(enc) = &mut enc {
+                    let data = frame.clone().into_intermediate(intermediate)?;
+                    enc.encode_frame(&data)
+                        .with_context(|| format!("encode sample frame {}", i))?;
+                }
+                if counts_jpg || !animated_encs.is_empty() {
+                    let jpg = frame.into_jpg()?;
+                    for (_, enc) in &mut animated_encs {
+                        enc.encode_frame(&jpg)
+                            .with_context(|| format!("encode animated sample frame {}", i))?;
+                    }
+                    if counts_jpg {
+                        jpg_bytes += jpg.len();
+                    }
+                }
+                encoded_frames += 1;
+            }
+            Err(e) => info.set_progress(SetProgressInfo::warn(format!(
+                "could not extract sample frame {i}/{sample_size}\n{e}\n\n"
+            ))),
+        }
+        info.set_progress(SetProgressInfo {
+            progress_inc: Some(1),
+            ..Default::default()
+        });
+    }
+
+    let mut sample_bytes = 0;
+    let encoded = enc.map(|enc| (sample_path, enc));
+    for (path, mut enc) in encoded.into_iter().chain(animated_encs) {
+        enc.finish().context("finish sample encode")?;
+        info.log_ffmpeg_diagnostics(&path);
+        sample_bytes += std::fs::metadata(&path)
+            .context("read sample encode size")?
+            .len() as usize;
+    }
+    sample_bytes += jpg_bytes;
+    let sampled_bytes = if encoded_frames == 0 {
+        0.0
+    } else {
+        sample_bytes as f64 * frames as f64 / encoded_frames as f64
+    };
+    let estimated_bytes = sampled_bytes + target_mb * 1024.0 * 1024.0;
+
+    let estimate = SizeEstimate {
+        frames,
+        duration_secs: frames as f64 / settings.fps.max(1) as f64,
+        sample_frames: encoded_frames,
+        estimated_mb: estimated_bytes / 1024.0 / 1024.0,
+    };
+    info.set_progress(SetProgressInfo::detail(format!(
+        "estimated {:.01}MB for {} frames ({:.02}s) from {} sample frames",
+        estimate.estimated_mb, estimate.frames, estimate.duration_secs, estimate.sample_frames
+    )));
+    Ok(estimate)
+}
@@ -1,16 +1,39 @@
-use std::path::Path;
+use std::{
+    collections::BTreeMap,
+    ffi::OsStr,
+    path::{Path, PathBuf},
+};
 
-use crate::{JobInfo, SetProgressInfo};
+use anyhow::Context;
 
-use super::timeline::Timeline;
+use crate::{JobContext, SetProgressInfo};
+
+use super::{
+    archive::ExtractedZip,
+    atomic::write_atomically,
+    glyph::{GlyphField, GlyphValue, LatLng, ScrapedFields},
+    timeline::Timeline,
+};
 
 #[derive(Debug, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
-struct TimelineExportEntry {
+struct TimelineExportEntry<'a> {
     file_path: String,
     timestamp: String,
     duration: f64,
     location: Option<TimelineExportEntryLocation>,
+    /// scraped speed, in the units of the overlay
+    #[serde(skip_serializing_if = "Option::is_none")]
+    speed: Option<f64>,
+    /// scraped compass heading in degrees
+    #[serde(skip_serializing_if = "Option::is_none")]
+    heading: Option<f64>,
+    /// scraped glyph fields other than the location, speed, and heading, by field name
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    glyphs: BTreeMap<String, Option<&'a GlyphValue>>,
+    /// the glyphs were filled in from an earlier clip instead of being scraped from this one
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    interpolated: bool,
 }
 #[derive(Debug, serde::Serialize)]
 struct TimelineExportEntryLocation {
@@ -18,30 +41,330 @@ struct TimelineExportEntryLocation {
     lng: f64,
 }
 
+/// where and how the exported json is written
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ExportFormat {
+    /// name of the file written into the output directory
+    pub filename: String,
+    /// indent the json for reading, instead of writing it on a single line
+    pub pretty: bool,
+    /// also write the scraped locations as a gpx track, named like the json (not when merging)
+    pub gpx: bool,
+}
+impl Default for ExportFormat {
+    fn default() -> Self {
+        Self {
+            filename: "output.json".into(),
+            pretty: true,
+            gpx: false,
+        }
+    }
+}
+
+impl ExportFormat {
+    /// where the export is written in `output_dir`
+    fn output_path(&self, output_dir: &Path) -> anyhow::Result<PathBuf> {
+        if Path::new(&self.filename).file_name() != Some(OsStr::new(&self.filename)) {
+            anyhow::bail!(
+                "export filename {:?} must be a plain file name",
+                self.filename
+            );
+        }
+        Ok(output_dir.join(&self.filename))
+    }
+    fn to_json<T: serde::Serialize>(&self, value: &T) -> serde_json::Result<String> {
+        if self.pretty {
+            serde_json::to_string_pretty(value)
+        } else {
+            serde_json::to_string(value)
+        }
+    }
+}
+
+/// `extracted` is the zip the timeline's clips came out of, if they did. every file waits for
+/// `min_free_space` bytes to be free before it is written
 pub fn export_timeline(
-    info: &JobInfo,
+    info: &dyn JobContext,
     timeline: &Timeline,
-    locs: Option<&[super::glyph::LatLng]>,
+    extracted: Option<&ExtractedZip>,
+    scraped: Option<&ScrapedFields>,
     output_dir: &Path,
+    format: &ExportFormat,
+    min_free_space: Option<u64>,
 ) -> anyhow::Result<()> {
+    let output_path = format.output_path(output_dir)?;
+    let wait_for_space = || match min_free_space {
+        Some(min_free) => info.wait_for_disk_space(output_dir, min_free),
+        None => Ok(()),
+    };
+
+    let locs = scraped.and_then(ScrapedFields::locations);
+    let speeds = scraped.and_then(ScrapedFields::speeds);
+    let headings = scraped.and_then(ScrapedFields::headings);
     let entries = timeline
         .iter()
         .enumerate()
         .map(|(i, clip)| TimelineExportEntry {
-            file_path: clip.path.to_string_lossy().into(),
+            file_path: extracted
+                .map_or_else(|| clip.path.clone(), |zip| zip.source_path(&clip.path))
+                .to_string_lossy()
+                .into(),
             timestamp: clip.creation_time.to_rfc3339(),
             duration: clip.length.as_secs_f64(),
-            location: locs.map(|locs| TimelineExportEntryLocation {
+            location: locs.as_ref().map(|locs| TimelineExportEntryLocation {
                 lat: locs[i].lat,
                 lng: locs[i].lng,
             }),
+            speed: speeds.as_ref().and_then(|speeds| speeds[i]),
+            heading: headings.as_ref().and_then(|headings| headings[i]),
+            glyphs: scraped
+                .map(|scraped| {
+                    scraped
+                        .fields
+                        .iter()
+                        .zip(&scraped.values[i])
+                        .filter(|(field, _)| {
+                            !matches!(
+                                field,
+                                GlyphField::LatLng { .. }
+                                    | GlyphField::Speed { .. }
+                                    | GlyphField::Heading { .. }
+                            )
+                        })
+                        .map(|(field, value)| (field.name(), value.as_ref()))
+                        .collect()
+                })
+                .unwrap_or_default(),
+            interpolated: scraped.is_some_and(|scraped| !scraped.scraped[i]),
         })
         .collect::<Vec<_>>();
-    let output_path = output_dir.join("output.json");
-    std::fs::write(&output_path, serde_json::to_string_pretty(&entries)?)?;
+    let json = format.to_json(&entries)?;
+    wait_for_space()?;
+    write_atomically(&output_path, &json)?;
+    info.add_bytes_written(json.len() as u64);
     info.set_progress(SetProgressInfo::detail(format!(
         "exported data to file {:?}",
         output_path
     )));
+
+    if format.gpx {
+        wait_for_space()?;
+        export_gpx(info, timeline, scraped, &output_path.with_extension("gpx"))?;
+    }
+    Ok(())
+}
+
+/// write the location of every directly scraped clip as a gpx track, leaving out the clips that
+/// were filled in from an earlier one or whose location could not be parsed
+fn export_gpx(
+    info: &dyn JobContext,
+    timeline: &Timeline,
+    scraped: Option<&ScrapedFields>,
+    output_path: &Path,
+) -> anyhow::Result<()> {
+    let (Some(scraped), Some(locs)) = (scraped, scraped.and_then(ScrapedFields::parsed_locations))
+    else {
+        info.set_progress(SetProgressInfo::warn(
+            "no locations were scraped, so no gpx track was written",
+        ));
+        return Ok(());
+    };
+    let points = timeline
+        .iter()
+        .zip(locs.iter().zip(&scraped.scraped))
+        .filter_map(|(clip, (loc, &was_scraped))| {
+            Some((clip.creation_time, loc.as_ref().filter(|_| was_scraped)?))
+        })
+        .collect::<Vec<_>>();
+    let gpx = render_gpx(&points);
+    write_atomically(output_path, &gpx)
+        .with_context(|| format!("write gpx track {:?}", output_path))?;
+    info.add_bytes_written(gpx.len() as u64);
+    info.set_progress(SetProgressInfo::detail(format!(
+        "exported a track of {} points to file {:?}",
+        points.len(),
+        output_path
+    )));
     Ok(())
 }
+
+/// a gpx track with a point for each time and place
+fn render_gpx(points: &[(chrono::DateTime<chrono::Utc>, &LatLng)]) -> String {
+    let mut gpx = String::from(concat!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+        "<gpx version=\"1.1\" creator=\"crimelapse\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n",
+        "  <trk>\n",
+        "    <trkseg>\n",
+    ));
+    for (time, loc) in points {
+        gpx.push_str(&format!(
+            "      <trkpt lat=\"{:.6}\" lon=\"{:.6}\"><time>{}</time></trkpt>\n",
+            loc.lat,
+            loc.lng,
+            time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+        ));
+    }
+    gpx.push_str("    </trkseg>\n  </trk>\n</gpx>\n");
+    gpx
+}
+
+/// an entry of an earlier export, keeping the fields that merging doesn't look at as they were
+#[cfg(feature = "app")]
+#[derive(Debug, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ExportedEntry {
+    file_path: String,
+    timestamp: String,
+    #[serde(flatten)]
+    rest: serde_json::Map<String, serde_json::Value>,
+}
+
+/// combine the exports at `inputs` into one sorted by timestamp, written into `output_dir`
+///
+/// a clip exported more than once keeps the entry from the last input it appears in, so a re-run
+/// batch replaces the older one. returns the number of entries written
+#[cfg(feature = "app")]
+pub fn merge_exports(
+    inputs: &[PathBuf],
+    output_dir: &Path,
+    format: &ExportFormat,
+) -> anyhow::Result<usize> {
+    let output_path = format.output_path(output_dir)?;
+
+    let mut by_path = std::collections::HashMap::new();
+    for input in inputs {
+        let json = std::fs::read(input).with_context(|| format!("read export {:?}", input))?;
+        let entries = serde_json::from_slice::<Vec<ExportedEntry>>(&json)
+            .with_context(|| format!("parse export {:?}", input))?;
+        for entry in entries {
+            let timestamp =
+                chrono::DateTime::parse_from_rfc3339(&entry.timestamp).with_context(|| {
+                    format!("parse timestamp of {:?} in {:?}", entry.file_path, input)
+                })?;
+            by_path.insert(entry.file_path.clone(), (timestamp, entry));
+        }
+    }
+
+    let mut entries = by_path.into_values().collect::<Vec<_>>();
+    entries
+        .sort_by(|(a_ts, a), (b_ts, b)| a_ts.cmp(b_ts).then_with(|| a.file_path.cmp(&b.file_path)));
+    let entries = entries
+        .into_iter()
+        .map(|(_, entry)| entry)
+        .collect::<Vec<_>>();
+    std::fs::create_dir_all(output_dir)
+        .with_context(|| format!("create output directory {:?}", output_dir))?;
+    write_atomically(&output_path, format.to_json(&entries)?)
+        .with_context(|| format!("write merged export {:?}", output_path))?;
+    Ok(entries.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{export_gpx, render_gpx};
+    #[cfg(feature = "app")]
+    use super::{merge_exports, ExportFormat};
+    use crate::compute::{
+        glyph::{GlyphField, GlyphValue, LatLng, ScrapedFields},
+        test_util::NoProgress,
+        timeline::{Timeline, TimelineClip},
+    };
+    use chrono::TimeZone;
+    use std::time::Duration;
+
+    #[test]
+    fn gpx_points_are_in_utc() {
+        let time = chrono::Utc.with_ymd_and_hms(2024, 5, 1, 10, 30, 0).unwrap();
+        let loc = LatLng {
+            lat: 40.7128,
+            lng: -74.006,
+        };
+        let gpx = render_gpx(&[(time, &loc)]);
+        assert!(gpx.contains(
+            r#"<trkpt lat="40.712800" lon="-74.006000"><time>2024-05-01T10:30:00Z</time></trkpt>"#
+        ));
+        assert!(gpx.ends_with("</gpx>\n"));
+    }
+
+    #[test]
+    fn gpx_track_leaves_out_filled_in_and_unparsed_locations() {
+        let clips = (0..4)
+            .map(|i| TimelineClip {
+                creation_time: chrono::Utc.with_ymd_and_hms(2024, 5, 1, 10, i, 0).unwrap(),
+                length: Duration::from_secs(60),
+                path: format!("{i}.mp4").into(),
+                unreliable_timestamps: false,
+            })
+            .collect();
+        let timeline = Timeline::from_sorted_clips(clips);
+        let loc = |lat| Some(GlyphValue::LatLng(LatLng { lat, lng: -74.0 }));
+        // clip 1 was filled in from clip 0, and clip 2 was scraped but couldn't be parsed
+        let scraped = ScrapedFields {
+            fields: vec![GlyphField::location()],
+            values: vec![
+                vec![loc(40.0)],
+                vec![loc(40.0)],
+                vec![None],
+                vec![loc(41.0)],
+            ],
+            scraped: vec![true, false, true, true],
+        };
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("output.gpx");
+        export_gpx(&NoProgress, &timeline, Some(&scraped), &path).unwrap();
+        let gpx = std::fs::read_to_string(&path).unwrap();
+        let points = gpx
+            .lines()
+            .filter(|line| line.contains("<trkpt"))
+            .collect::<Vec<_>>();
+        assert_eq!(points.len(), 2, "{gpx}");
+        assert!(points[0].contains(r#"lat="40.000000""#));
+        assert!(points[0].contains("10:00:00Z"));
+        assert!(points[1].contains(r#"lat="41.000000""#));
+        assert!(points[1].contains("10:03:00Z"));
+    }
+
+    #[cfg(feature = "app")]
+    #[test]
+    fn merged_exports_are_sorted_and_deduplicated() {
+        let dir = tempfile::tempdir().unwrap();
+        let first = dir.path().join("first.json");
+        let second = dir.path().join("second.json");
+        std::fs::write(
+            &first,
+            r#"[
+                {"filePath":"b.mp4","timestamp":"2024-01-02T00:00:00+00:00","duration":60.0,"location":null},
+                {"filePath":"c.mp4","timestamp":"2024-01-03T00:00:00+00:00","duration":60.0,"location":null}
+            ]"#,
+        )
+        .unwrap();
+        std::fs::write(
+            &second,
+            r#"[
+                {"filePath":"a.mp4","timestamp":"2024-01-01T00:00:00+00:00","duration":60.0,"location":null},
+                {"filePath":"c.mp4","timestamp":"2024-01-03T00:00:00+00:00","duration":30.0,"location":null}
+            ]"#,
+        )
+        .unwrap();
+
+        let out_dir = dir.path().join("merged");
+        let format = ExportFormat::default();
+        let count = merge_exports(&[first, second], &out_dir, &format).unwrap();
+        assert_eq!(count, 3);
+
+        let merged: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(out_dir.join(&format.filename)).unwrap())
+                .unwrap();
+        let paths = merged
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| entry["filePath"].as_str().unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(paths, ["a.mp4", "b.mp4", "c.mp4"]);
+        // the later input wins
+        assert_eq!(merged[2]["duration"], 30.0);
+    }
+}
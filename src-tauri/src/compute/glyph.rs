@@ -5,12 +5,17 @@ mod organize;
 
 use crate::{
     compute::{timeline::Timeline, workers::WorkerPool},
-    ffmpeg, JobInfo, SetProgressInfo,
+    ffmpeg, JobContext, SetProgressInfo,
 };
 use anyhow::Context;
 use image::{GenericImageView, GrayImage, Luma, Rgb, RgbImage, SubImage};
 use regex::Regex;
-use std::{path::Path, sync::Arc, time::Duration};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
 
 #[derive(Debug, Clone)]
 struct GlyphMask {
@@ -20,6 +25,18 @@ impl GlyphMask {
     fn new(bmp: GrayImage) -> Self {
         Self { bmp }
     }
+    fn from_image<T: GenericImageView<Pixel = Rgb<u8>>>(img: &T, ink: GlyphInk) -> Self {
+        let (width, height) = img.dimensions();
+        let bmp = GrayImage::from_fn(width, height, |x, y| {
+            if ink.matches(img.get_pixel(x, y).0) {
+                Luma([255])
+            } else {
+                Luma([0])
+            }
+        });
+
+        Self { bmp }
+    }
     fn score_similarity(&self, other: &Self) -> f64 {
         debug_assert_eq!(self.bmp.dimensions(), other.bmp.dimensions());
 
@@ -41,38 +58,99 @@ impl GlyphMask {
         match_score as f64 / total_score as f64
     }
 }
-impl<T: GenericImageView<Pixel = Rgb<u8>>> From<&T> for GlyphMask {
-    fn from(value: &T) -> Self {
-        const WHITE_AVG_MIN: u8 = 220;
-        const WHITE_MAX_CHROMA: u8 = 30;
-
-        let (width, height) = value.dimensions();
-        let bmp = GrayImage::from_fn(width, height, |x, y| {
-            let [r, g, b] = value.get_pixel(x, y).0;
-            let avg = ((r as u16 + g as u16 + b as u16) / 3) as u8;
-            let max_channel = r.max(g).max(b);
-            let min_channel = r.min(g).min(b);
-            let chroma = max_channel - min_channel;
-            if avg >= WHITE_AVG_MIN && chroma <= WHITE_MAX_CHROMA {
-                Luma([255])
-            } else {
-                Luma([0])
-            }
-        });
-
-        Self { bmp }
+/// thresholds for deciding which pixels of a glyph are "overlay white"
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct WhiteThresholds {
+    /// minimum average of the r, g, and b channels
+    avg_min: u8,
+    /// maximum difference between the largest and smallest channel
+    max_chroma: u8,
+}
+impl Default for WhiteThresholds {
+    fn default() -> Self {
+        Self {
+            avg_min: 220,
+            max_chroma: 30,
+        }
+    }
+}
+impl WhiteThresholds {
+    fn matches(&self, [r, g, b]: [u8; 3]) -> bool {
+        let avg = ((r as u16 + g as u16 + b as u16) / 3) as u8;
+        let chroma = r.max(g).max(b) - r.min(g).min(b);
+        avg >= self.avg_min && chroma <= self.max_chroma
+    }
+}
+/// the color of a row's text, for overlays that aren't white
+#[derive(Debug, Clone, Copy, serde::Deserialize)]
+struct GlyphColor {
+    /// the r, g, and b of the text
+    rgb: [u8; 3],
+    /// how far each channel can be from `rgb` for a pixel to still be part of the text
+    tolerance: u8,
+}
+impl GlyphColor {
+    fn matches(&self, px: [u8; 3]) -> bool {
+        px.iter()
+            .zip(self.rgb)
+            .all(|(&channel, target)| channel.abs_diff(target) <= self.tolerance)
+    }
+}
+/// which pixels of a row count as its text when converting glyphs to masks
+#[derive(Debug, Clone, Copy)]
+enum GlyphInk {
+    White(WhiteThresholds),
+    Color(GlyphColor),
+}
+impl GlyphInk {
+    fn matches(&self, px: [u8; 3]) -> bool {
+        match self {
+            Self::White(thresholds) => thresholds.matches(px),
+            Self::Color(color) => color.matches(px),
+        }
     }
 }
 
 #[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
 struct GlyphRow {
+    /// name that `GlyphField`s use to refer to this row, which older configs don't have
+    #[serde(default)]
+    name: String,
     top: u32,
     right: u32,
     width: u32,
     height: u32,
     columns: u32,
+    /// the color of this row's text, which takes priority over any white thresholds
+    #[serde(default)]
+    color: Option<GlyphColor>,
+    /// this row's thresholds for white text, instead of the config-wide ones
+    #[serde(default)]
+    white_thresholds: Option<WhiteThresholds>,
 }
 impl GlyphRow {
+    /// which pixels are this row's text, falling back to the config-wide `thresholds`
+    fn ink(&self, thresholds: &WhiteThresholds) -> GlyphInk {
+        match self.color {
+            Some(color) => GlyphInk::Color(color),
+            None => GlyphInk::White(self.white_thresholds.unwrap_or(*thresholds)),
+        }
+    }
+    /// a row without any area would scrape an empty string, which only fails much later when parsed
+    fn check_geometry(&self) -> anyhow::Result<()> {
+        if self.width == 0 || self.height == 0 || self.columns == 0 {
+            anyhow::bail!(
+                "row {:?} has a degenerate geometry of {} columns of {}x{}, but all of them must be above 0",
+                self.name,
+                self.columns,
+                self.width,
+                self.height
+            );
+        }
+        Ok(())
+    }
     fn crops<'a>(
         &self,
         img: &'a RgbImage,
@@ -83,19 +161,29 @@ impl GlyphRow {
             image::imageops::crop_imm(img, x, y, self.width, self.height)
         })
     }
-    fn glyphs<'a>(&self, img: &'a RgbImage) -> impl Iterator<Item = GlyphMask> + use<'a, '_> {
+    fn glyphs<'a>(
+        &self,
+        img: &'a RgbImage,
+        thresholds: &WhiteThresholds,
+    ) -> impl Iterator<Item = GlyphMask> + use<'a, '_> {
+        let ink = self.ink(thresholds);
         self.crops(img)
-            .map(|crop| GlyphMask::from(&crop.to_image()))
+            .map(move |crop| GlyphMask::from_image(&crop.to_image(), ink))
     }
-    fn scrape_string(&self, img: &RgbImage, chars: &[(String, GlyphMask)]) -> String {
+    fn scrape_string(
+        &self,
+        img: &RgbImage,
+        thresholds: &WhiteThresholds,
+        chars: &[(String, GlyphMask)],
+    ) -> String {
         let mut s = String::with_capacity(self.columns as usize);
-        for glyph in self.glyphs(&img) {
+        for glyph in self.glyphs(img, thresholds) {
             let mut best_c = "";
             let mut best_score = 0.0;
             for (ref_c, ref_glyph) in chars {
                 let score = glyph.score_similarity(ref_glyph);
                 if score > best_score {
-                    best_c = &ref_c;
+                    best_c = ref_c;
                     best_score = score;
                 }
             }
@@ -117,131 +205,837 @@ struct GlyphChar {
 struct GlyphConfig {
     glyph_rows: Vec<GlyphRow>,
     glyph_chars: Vec<GlyphChar>,
+    #[serde(default)]
+    white_thresholds: WhiteThresholds,
+    /// where the config was loaded from
+    #[serde(skip)]
+    path: PathBuf,
+    #[cfg(feature = "annotated-glyph-frames")]
+    #[serde(default)]
+    annotate: annotate::AnnotateStyle,
 }
 impl GlyphConfig {
-    fn from_resources(info: &JobInfo) -> anyhow::Result<Self> {
-        let path = info.resolve_resource("resources/glyphconfig.json");
-        let file = std::fs::File::open(path)?;
+    fn from_resources(info: &dyn JobContext) -> anyhow::Result<Self> {
+        Self::from_path(info.resolve_resource(GLYPH_CONFIG_RESOURCE))
+    }
+    fn from_path(path: PathBuf) -> anyhow::Result<Self> {
+        let gcfg = Self::parse(path)?;
+        for row in &gcfg.glyph_rows {
+            row.check_geometry()
+                .with_context(|| format!("invalid glyph config {:?}", gcfg.path))?;
+        }
+        Ok(gcfg)
+    }
+    /// read the config without checking it, so that validation can report every problem at once
+    fn parse(path: PathBuf) -> anyhow::Result<Self> {
+        let file = std::fs::File::open(&path).with_context(|| format!("open {:?}", path))?;
         let reader = std::io::BufReader::new(file);
-        Ok(serde_json::from_reader(reader)?)
+        let mut gcfg: Self = serde_json::from_reader(reader).context("parse glyph config")?;
+        gcfg.path = path;
+        // configs from before rows had names only had the latitude and longitude rows, in order
+        for (i, row) in gcfg.glyph_rows.iter_mut().enumerate() {
+            if row.name.is_empty() {
+                row.name = match i {
+                    0 => LAT_ROW.into(),
+                    1 => LNG_ROW.into(),
+                    i => i.to_string(),
+                };
+            }
+        }
+        Ok(gcfg)
     }
-    fn load_glyph_masks(&self, info: &JobInfo) -> anyhow::Result<Vec<(String, GlyphMask)>> {
-        let mut char_masks = Vec::new();
-        for gc in &self.glyph_chars {
-            let path = info.resolve_resource(&gc.filepath);
-            let img = image::open(path)?;
-            char_masks.push((gc.char.clone(), GlyphMask::new(img.to_luma8())))
+    fn row(&self, name: &str) -> anyhow::Result<&GlyphRow> {
+        self.glyph_rows
+            .iter()
+            .find(|row| row.name == name)
+            .ok_or_else(|| anyhow::anyhow!("glyph config has no row named {:?}", name))
+    }
+    /// load the reference glyph masks, reusing the ones decoded by a previous job if none of
+    /// the config or bitmap files have been modified since
+    fn load_glyph_masks(&self, info: &dyn JobContext) -> anyhow::Result<Arc<GlyphMasks>> {
+        static CACHE: Mutex<Option<GlyphMaskCache>> = Mutex::new(None);
+
+        let char_paths = self
+            .glyph_chars
+            .iter()
+            .map(|gc| info.resolve_resource(&gc.filepath))
+            .collect::<Vec<_>>();
+        let key = std::iter::once(&self.path)
+            .chain(&char_paths)
+            .map(|path| {
+                let mtime = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+                (path.clone(), mtime)
+            })
+            .collect::<Vec<_>>();
+
+        let mut cache = CACHE.lock().unwrap();
+        let cached = match cache.take().filter(|c| c.key == key) {
+            Some(cached) => cached,
+            None => {
+                let chars = self.glyph_chars.iter().map(|gc| gc.char.as_str());
+                let (masks, skipped) = decode_glyph_masks(chars.zip(&char_paths))?;
+                GlyphMaskCache {
+                    key,
+                    masks: Arc::new(masks),
+                    skipped,
+                }
+            }
+        };
+        // reported for every job, since each job has its own log
+        if !cached.skipped.is_empty() {
+            info.set_progress(SetProgressInfo::warn(format!(
+                "skipped {} glyph chars that could not be loaded, so they won't be recognized:\n{}",
+                cached.skipped.len(),
+                cached.skipped.join("\n")
+            )));
+        }
+        let masks = Arc::clone(&cached.masks);
+        *cache = Some(cached);
+        Ok(masks)
+    }
+}
+
+/// where the glyph config is bundled, relative to the resource directory
+pub const GLYPH_CONFIG_RESOURCE: &str = "resources/glyphconfig.json";
+
+/// check the glyph config at `path` for problems that would otherwise only show up partway through a
+/// job, with `resolve_resource` resolving the bitmap paths like a job would
+///
+/// returns one message per problem found, which is empty if the config is valid
+#[cfg(feature = "app")]
+pub fn validate_glyph_config(
+    path: PathBuf,
+    resolve_resource: impl Fn(&str) -> PathBuf,
+) -> anyhow::Result<Vec<String>> {
+    let gcfg = GlyphConfig::parse(path)?;
+    let mut problems = Vec::new();
+
+    for (i, row) in gcfg.glyph_rows.iter().enumerate() {
+        if let Err(e) = row.check_geometry() {
+            problems.push(e.to_string());
+        }
+        if gcfg.glyph_rows[..i]
+            .iter()
+            .any(|other| other.name == row.name)
+        {
+            problems.push(format!("row {:?} is defined more than once", row.name));
+        }
+    }
+
+    for (i, gc) in gcfg.glyph_chars.iter().enumerate() {
+        if gcfg.glyph_chars[..i]
+            .iter()
+            .any(|other| other.char == gc.char)
+        {
+            problems.push(format!("char {:?} is defined more than once", gc.char));
+        }
+        let path = resolve_resource(&gc.filepath);
+        let img = match image::open(&path) {
+            Ok(img) => img,
+            Err(e) => {
+                problems.push(format!(
+                    "char {:?} bitmap {:?} could not be loaded: {}",
+                    gc.char, path, e
+                ));
+                continue;
+            }
+        };
+        // glyphs are compared pixel for pixel, so every bitmap has to be the size of a row's glyphs
+        for row in &gcfg.glyph_rows {
+            if img.dimensions() != (row.width, row.height) {
+                problems.push(format!(
+                    "char {:?} bitmap is {}x{}, but row {:?} glyphs are {}x{}",
+                    gc.char,
+                    img.width(),
+                    img.height(),
+                    row.name,
+                    row.width,
+                    row.height
+                ));
+            }
+        }
+    }
+    Ok(problems)
+}
+
+/// each char of the alphabet with its decoded bitmap
+type GlyphMasks = Vec<(String, GlyphMask)>;
+
+/// decoded glyph masks, keyed by the paths and modified times of the files they came from
+struct GlyphMaskCache {
+    key: Vec<(PathBuf, Option<SystemTime>)>,
+    masks: Arc<GlyphMasks>,
+    /// the chars whose bitmaps could not be loaded, and why
+    skipped: Vec<String>,
+}
+
+/// decode the bitmap of each char, leaving out the ones that are missing or corrupt
+///
+/// a partial alphabet still recognizes most glyphs, so this only fails when none of them load
+fn decode_glyph_masks<'a>(
+    chars: impl Iterator<Item = (&'a str, &'a PathBuf)>,
+) -> anyhow::Result<(GlyphMasks, Vec<String>)> {
+    let mut masks = Vec::new();
+    let mut skipped = Vec::new();
+    for (char, path) in chars {
+        match image::open(path) {
+            Ok(img) => masks.push((char.to_string(), GlyphMask::new(img.to_luma8()))),
+            Err(e) => skipped.push(format!("{:?} from {:?}: {}", char, path, e)),
+        }
+    }
+    if masks.is_empty() && !skipped.is_empty() {
+        anyhow::bail!(
+            "none of the glyph char bitmaps could be loaded:\n{}",
+            skipped.join("\n")
+        );
+    }
+    Ok((masks, skipped))
+}
+
+/// default name of the glyph row containing the latitude
+const LAT_ROW: &str = "latitude";
+/// default name of the glyph row containing the longitude
+const LNG_ROW: &str = "longitude";
+
+/// what one or more named glyph rows should be parsed into
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(
+    tag = "type",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum GlyphField {
+    /// latitude and longitude, each scraped from their own row
+    LatLng { lat_row: String, lng_row: String },
+    /// a local date/time, parsed with a chrono format string
+    DateTime { row: String, format: String },
+    /// the scraped string, as-is
+    Raw { row: String },
+    /// a speed, in whatever unit the overlay shows it in
+    Speed {
+        row: String,
+        /// units to strip from either end of the number, as they appear in the overlay
+        #[serde(default = "default_speed_units")]
+        strip: Vec<String>,
+    },
+    /// a compass heading in degrees
+    Heading {
+        row: String,
+        /// units to strip from either end of the number, as they appear in the overlay
+        #[serde(default = "default_heading_units")]
+        strip: Vec<String>,
+    },
+}
+fn default_speed_units() -> Vec<String> {
+    ["MPH", "KM/H", "KMH", "KPH"].map(String::from).to_vec()
+}
+fn default_heading_units() -> Vec<String> {
+    vec!["°".into()]
+}
+impl GlyphField {
+    /// the latitude/longitude field using the default row names
+    pub fn location() -> Self {
+        Self::LatLng {
+            lat_row: LAT_ROW.into(),
+            lng_row: LNG_ROW.into(),
+        }
+    }
+    /// name of the field in exports
+    pub fn name(&self) -> String {
+        match self {
+            Self::LatLng { lat_row, lng_row } => format!("{lat_row}/{lng_row}"),
+            Self::DateTime { row, .. }
+            | Self::Raw { row }
+            | Self::Speed { row, .. }
+            | Self::Heading { row, .. } => row.clone(),
         }
-        Ok(char_masks)
+    }
+    fn rows(&self) -> Vec<&str> {
+        match self {
+            Self::LatLng { lat_row, lng_row } => vec![lat_row, lng_row],
+            Self::DateTime { row, .. }
+            | Self::Raw { row }
+            | Self::Speed { row, .. }
+            | Self::Heading { row, .. } => vec![row],
+        }
+    }
+    fn parse(&self, strings: &BTreeMap<&str, String>) -> anyhow::Result<GlyphValue> {
+        let get = |row: &str| {
+            strings
+                .get(row)
+                .map(String::as_str)
+                .ok_or_else(|| anyhow::anyhow!("row {:?} was not scraped", row))
+        };
+        Ok(match self {
+            Self::LatLng { lat_row, lng_row } => {
+                GlyphValue::LatLng(LatLng::from_strings(get(lat_row)?, get(lng_row)?)?)
+            }
+            Self::DateTime { row, format } => {
+                let s = get(row)?;
+                GlyphValue::DateTime(
+                    chrono::NaiveDateTime::parse_from_str(s.trim(), format)
+                        .with_context(|| format!("parse {:?} with format {:?}", s, format))?,
+                )
+            }
+            Self::Raw { row } => GlyphValue::Raw(get(row)?.to_string()),
+            Self::Speed { row, strip } => GlyphValue::Number(parse_number(get(row)?, strip)?),
+            Self::Heading { row, strip } => {
+                let heading = parse_number(get(row)?, strip)?;
+                if !(0.0..=360.0).contains(&heading) {
+                    anyhow::bail!("heading {} is not between 0 and 360 degrees", heading);
+                }
+                GlyphValue::Number(heading)
+            }
+        })
     }
 }
 
-#[derive(Debug, Default)]
+/// a number scraped from a row, after stripping any of `strip` (like units) from either end
+fn parse_number(s: &str, strip: &[String]) -> anyhow::Result<f64> {
+    let mut number = s.trim();
+    for affix in strip.iter().filter(|affix| !affix.is_empty()) {
+        number = number.strip_prefix(affix.as_str()).unwrap_or(number).trim();
+        number = number.strip_suffix(affix.as_str()).unwrap_or(number).trim();
+    }
+    number
+        .parse::<f64>()
+        .ok()
+        .filter(|n| n.is_finite())
+        .ok_or_else(|| anyhow::anyhow!("{:?} is not a number", s))
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(untagged)]
+pub enum GlyphValue {
+    LatLng(LatLng),
+    #[serde(serialize_with = "serialize_naive_datetime")]
+    DateTime(chrono::NaiveDateTime),
+    Raw(String),
+    Number(f64),
+}
+fn serialize_naive_datetime<S: serde::Serializer>(
+    dt: &chrono::NaiveDateTime,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&dt.format("%Y-%m-%dT%H:%M:%S"))
+}
+
+/// the parsed glyph fields of every clip in the timeline
+pub struct ScrapedFields {
+    pub fields: Vec<GlyphField>,
+    /// `values[clip][field]`, in timeline order, with `None` where the field could not be parsed
+    pub values: Vec<Vec<Option<GlyphValue>>>,
+    /// whether each clip was scraped directly, rather than filled in from an earlier clip
+    pub scraped: Vec<bool>,
+}
+impl ScrapedFields {
+    /// the values of the first `GlyphField::LatLng`, defaulting where it could not be parsed
+    pub fn locations(&self) -> Option<Vec<LatLng>> {
+        let locations = self.parsed_locations()?;
+        Some(
+            locations
+                .into_iter()
+                .map(Option::unwrap_or_default)
+                .collect(),
+        )
+    }
+    /// the values of the first `GlyphField::LatLng`, with `None` where it could not be parsed
+    pub fn parsed_locations(&self) -> Option<Vec<Option<LatLng>>> {
+        let idx = self
+            .fields
+            .iter()
+            .position(|f| matches!(f, GlyphField::LatLng { .. }))?;
+        Some(
+            self.values
+                .iter()
+                .map(|values| match &values[idx] {
+                    Some(GlyphValue::LatLng(loc)) => Some(loc.clone()),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+    /// the values of the first `GlyphField::Speed`, with `None` where it could not be parsed
+    pub fn speeds(&self) -> Option<Vec<Option<f64>>> {
+        self.numbers(|f| matches!(f, GlyphField::Speed { .. }))
+    }
+    /// the values of the first `GlyphField::Heading`, with `None` where it could not be parsed
+    pub fn headings(&self) -> Option<Vec<Option<f64>>> {
+        self.numbers(|f| matches!(f, GlyphField::Heading { .. }))
+    }
+    fn numbers(&self, is_field: impl Fn(&GlyphField) -> bool) -> Option<Vec<Option<f64>>> {
+        let idx = self.fields.iter().position(is_field)?;
+        Some(
+            self.values
+                .iter()
+                .map(|values| match values[idx] {
+                    Some(GlyphValue::Number(n)) => Some(n),
+                    _ => None,
+                })
+                .collect(),
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct LatLng {
     pub lat: f64,
     pub lng: f64,
 }
+/// the overlay formats a coordinate can be burned in as, with the cardinals as `{pos}` and `{neg}`
+///
+/// each is tried in order until one matches
+const COORD_PATTERNS: [&str; 4] = [
+    // `N 40 71280`, degrees then their decimal digits
+    r"(?P<card>[{pos}{neg}])[:. ](?P<deg>\d{2,3})[:. ](?P<frac>\d+)",
+    // `40°42'46.1"N`, degrees minutes seconds
+    r#"(?P<deg>\d{1,3})°\s*(?P<min>\d{1,2})['′]\s*(?P<sec>\d{1,2}(?:\.\d+)?)["″]?\s*(?P<card>[{pos}{neg}])"#,
+    // `40.7128 N`, decimal degrees followed by the cardinal
+    r"(?P<dec>\d{1,3}\.\d+)\s*°?\s*(?P<card>[{pos}{neg}])",
+    // `-74.0060`, signed decimal degrees on their own
+    r"^\s*(?P<dec>-?\d{1,3}\.\d+)\s*°?\s*$",
+];
+
+/// the compiled `COORD_PATTERNS` for one axis of a coordinate
+struct CoordAxis {
+    name: &'static str,
+    neg: &'static str,
+    max: f64,
+    patterns: Vec<Regex>,
+}
+impl CoordAxis {
+    fn new(name: &'static str, pos: &'static str, neg: &'static str, max: f64) -> Self {
+        let patterns = COORD_PATTERNS
+            .iter()
+            .map(|p| {
+                Regex::new(&p.replace("{pos}", pos).replace("{neg}", neg))
+                    .expect("compile coordinate regex")
+            })
+            .collect();
+        Self {
+            name,
+            neg,
+            max,
+            patterns,
+        }
+    }
+    fn parse(&self, s: &str) -> anyhow::Result<f64> {
+        let caps = self
+            .patterns
+            .iter()
+            .find_map(|r| r.captures(s))
+            .ok_or_else(|| anyhow::anyhow!("{:?} does not match any {} format", s, self.name))?;
+        let group = |name| caps.name(name).map(|m| m.as_str());
+        let num = |name| -> anyhow::Result<f64> {
+            group(name)
+                .unwrap_or("0")
+                .parse::<f64>()
+                .with_context(|| format!("parse {} of {:?}", name, s))
+        };
+
+        let val = match (group("dec"), group("frac")) {
+            (Some(_), _) => num("dec")?,
+            (None, Some(frac)) => format!("{}.{}", num("deg")?, frac)
+                .parse::<f64>()
+                .context("parse f64")?,
+            (None, None) => num("deg")? + num("min")? / 60.0 + num("sec")? / 3600.0,
+        };
+        let val = match group("card") {
+            Some(card) if card == self.neg => -val,
+            _ => val,
+        };
+        if val.abs() > self.max {
+            anyhow::bail!("{} {} is out of range", self.name, val);
+        }
+        Ok(val)
+    }
+}
+
 impl LatLng {
     fn from_strings(lat: &str, lng: &str) -> anyhow::Result<Self> {
         use std::sync::LazyLock;
-        static LAT_REGEXP: LazyLock<Regex> = LazyLock::new(|| {
-            Regex::new(r"(N|S)[:. ](\d{2,3})[:. ](\d+)").expect("compile latitude regex")
-        });
-        static LNG_REGEXP: LazyLock<Regex> = LazyLock::new(|| {
-            Regex::new(r"(E|W)[:. ](\d{2,3})[:. ](\d+)").expect("compile longitude regex")
-        });
+        static LAT: LazyLock<CoordAxis> =
+            LazyLock::new(|| CoordAxis::new("latitude", "N", "S", 90.0));
+        static LNG: LazyLock<CoordAxis> =
+            LazyLock::new(|| CoordAxis::new("longitude", "E", "W", 180.0));
 
         Ok(Self {
-            lat: Self::parse_lat_lng(lat, &LAT_REGEXP).context("parse latitude")?,
-            lng: Self::parse_lat_lng(lng, &LNG_REGEXP).context("parse longitude")?,
+            lat: LAT.parse(lat).context("parse latitude")?,
+            lng: LNG.parse(lng).context("parse longitude")?,
         })
     }
-    fn parse_lat_lng(s: &str, r: &Regex) -> anyhow::Result<f64> {
-        let (_, [cardinal, major, decimal]) = r
-            .captures(s)
-            .ok_or_else(|| anyhow::anyhow!("{} unmatched by regular expression {}", s, r))
-            .context("match regular expression")?
-            .extract();
-        let val = format!("{}.{}", major, decimal)
-            .parse::<f64>()
-            .context("parse f64")?;
-        match cardinal {
-            "N" | "E" => Ok(val),
-            "S" | "W" => Ok(-val),
-            c => Err(anyhow::anyhow!("invalid cardinal {}", c)),
-        }
+    /// whether this is a real coordinate, rather than the default from a failed scrape
+    pub fn is_valid(&self) -> bool {
+        self.lat != 0.0 || self.lng != 0.0
+    }
+    /// format as an ISO 6709 string (e.g. `+40.7128-074.0060/`)
+    pub fn to_iso6709(&self) -> String {
+        format!("{:+08.4}{:+09.4}/", self.lat, self.lng)
+    }
+}
+/// frames with an average luma at or below this are considered black
+const BLACK_FRAME_MAX_LUMA: f64 = 16.0;
+/// how far into the clip to accurately seek when the first frame is black
+const BLACK_FRAME_RETRY_OFFSET: Duration = Duration::from_millis(500);
+
+fn is_black_frame(img: &RgbImage) -> bool {
+    let pixels = img.width() as u64 * img.height() as u64;
+    if pixels == 0 {
+        return true;
+    }
+    let luma_sum = img
+        .pixels()
+        .map(|&Rgb([r, g, b])| (r as u64 * 299 + g as u64 * 587 + b as u64 * 114) / 1000)
+        .sum::<u64>();
+    luma_sum as f64 / pixels as f64 <= BLACK_FRAME_MAX_LUMA
+}
+
+/// extract the frame to scrape glyphs from, retrying with an accurate seek if the first frame is black
+fn extract_scrape_frame(
+    info: &dyn JobContext,
+    clip_path: &Path,
+    opts: ffmpeg::ExtractOptions,
+) -> anyhow::Result<RgbImage> {
+    let jpg_data = ffmpeg::extract_frame_as(clip_path, Duration::ZERO, opts)?;
+    let rgb = image::load_from_memory(&jpg_data.data)?.to_rgb8();
+    std::mem::drop(jpg_data);
+    if !is_black_frame(&rgb) {
+        return Ok(rgb);
     }
+
+    info.set_progress(SetProgressInfo::detail(format!(
+        "first frame of {:?} is black, retrying with accurate seek @ {:.02}s",
+        clip_path,
+        BLACK_FRAME_RETRY_OFFSET.as_secs_f64()
+    )));
+    let jpg_data = ffmpeg::extract_frame_accurate(clip_path, BLACK_FRAME_RETRY_OFFSET, opts)
+        .context("extract frame with accurate seek")?;
+    Ok(image::load_from_memory(&jpg_data.data)?.to_rgb8())
 }
-fn scrape_clip_location(
-    info: &JobInfo,
+
+fn scrape_clip_fields(
+    info: &dyn JobContext,
     gcfg: &GlyphConfig,
     chars: &[(String, GlyphMask)],
+    fields: &[GlyphField],
     clip_path: &Path,
-) -> anyhow::Result<LatLng> {
+    opts: ffmpeg::ExtractOptions,
+) -> anyhow::Result<Vec<Option<GlyphValue>>> {
     info.cancel_result()?;
 
-    let jpg_data = ffmpeg::extract_frame(clip_path, Duration::ZERO)?;
-    let rgb = image::load_from_memory(&jpg_data)?.to_rgb8();
-    std::mem::drop(jpg_data);
+    let rgb = extract_scrape_frame(info, clip_path, opts);
+    info.log_ffmpeg_diagnostics(clip_path);
+    let rgb = match rgb {
+        Ok(rgb) => rgb,
+        Err(e) => {
+            // the clip still counts towards the total, so keep the progress bar in step
+            info.set_progress(SetProgressInfo {
+                progress_inc: Some(1),
+                ..Default::default()
+            });
+            return Err(e);
+        }
+    };
 
-    let strings = gcfg
-        .glyph_rows
+    // scrape only the rows that are needed by the requested fields
+    let mut strings = BTreeMap::new();
+    for name in fields.iter().flat_map(GlyphField::rows) {
+        if !strings.contains_key(name) {
+            let row = gcfg.row(name)?;
+            strings.insert(name, row.scrape_string(&rgb, &gcfg.white_thresholds, chars));
+        }
+    }
+
+    let mut warnings = Vec::new();
+    let values = fields
         .iter()
-        .map(|row| row.scrape_string(&rgb, &chars))
-        .collect::<Vec<_>>();
-    debug_assert_eq!(strings.len(), 2);
-
-    let res = LatLng::from_strings(&strings[0], &strings[1]);
-    let detail = match &res {
-        Ok(_) => format!("scraped clip geolocation {:?}", clip_path),
-        Err(e) => format!(
-            "WARN: could not scrape clip geolocation {:?}\n{:?}\n\n",
-            clip_path, e
-        ),
+        .map(|field| match field.parse(&strings) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                warnings.push(format!("{}: {:?}", field.name(), e));
+                None
+            }
+        })
+        .collect();
+    let progress = if warnings.is_empty() {
+        SetProgressInfo::detail(format!("scraped clip glyphs {:?}", clip_path))
+    } else {
+        SetProgressInfo::warn(format!(
+            "could not scrape clip glyphs {:?}\n{}\n\n",
+            clip_path,
+            warnings.join("\n")
+        ))
     };
     info.set_progress(SetProgressInfo {
         progress_inc: Some(1),
-        detail: Some(detail),
-        ..Default::default()
+        ..progress
     });
-    Ok(res.unwrap_or_default())
+    Ok(values)
+}
+
+/// scrape `fields` from a single clip, failing if any of them can't be parsed, to catch a glyph
+/// config that doesn't match the clips before every clip is scraped
+pub fn check_scrape(
+    info: &dyn JobContext,
+    clip_path: &Path,
+    fields: &[GlyphField],
+    denoise: Option<f32>,
+) -> anyhow::Result<()> {
+    let gcfg = GlyphConfig::from_resources(info)?;
+    let chars = gcfg.load_glyph_masks(info).context("load glyph masks")?;
+    let opts = ffmpeg::ExtractOptions {
+        denoise,
+        ..Default::default()
+    };
+    let rgb = extract_scrape_frame(info, clip_path, opts);
+    info.log_ffmpeg_diagnostics(clip_path);
+    let rgb = rgb.with_context(|| format!("extract frame to scrape from {:?}", clip_path))?;
+
+    let mut problems = Vec::new();
+    for field in fields {
+        let mut strings = BTreeMap::new();
+        for name in field.rows() {
+            let row = gcfg.row(name)?;
+            strings.insert(
+                name,
+                row.scrape_string(&rgb, &gcfg.white_thresholds, &chars),
+            );
+        }
+        if let Err(e) = field.parse(&strings) {
+            problems.push(format!("{}: {:#}", field.name(), e));
+        }
+    }
+    if !problems.is_empty() {
+        anyhow::bail!(
+            "glyphs scraped from {:?} could not be parsed, so the glyph config likely doesn't match the clips:\n{}",
+            clip_path,
+            problems.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// fill in the values of every clip from the sample at or before it, where every `every`th clip was sampled
+fn forward_fill<T: Clone>(sampled: &[T], every: usize, clip_count: usize) -> Vec<T> {
+    (0..clip_count)
+        .map(|i| sampled[i / every].clone())
+        .collect()
 }
 
-pub fn scrape_locations(
-    info: Arc<JobInfo>,
+/// scrape `fields` from every `every`th clip, forward-filling the clips in between
+pub fn scrape_fields(
+    info: Arc<dyn JobContext>,
     timeline: Arc<Timeline>,
     pool: &WorkerPool,
+    fields: Vec<GlyphField>,
+    every: usize,
+    denoise: Option<f32>,
     _output_dir: &Path,
-) -> anyhow::Result<Vec<LatLng>> {
-    let gcfg = Arc::new(GlyphConfig::from_resources(&info)?);
+) -> anyhow::Result<ScrapedFields> {
+    let every = every.max(1);
+    let gcfg = Arc::new(GlyphConfig::from_resources(&*info)?);
+    // make sure every requested row exists before scraping any clips
+    for name in fields.iter().flat_map(GlyphField::rows) {
+        gcfg.row(name)?;
+    }
 
     // annotate frames = aligning/debugging the GlyphRows to timeline clip's thumbnail
     #[cfg(feature = "annotated-glyph-frames")]
-    annotate::annotate_frames(&info, &timeline, &gcfg, _output_dir).context("annotate frames")?;
+    annotate::annotate_frames(&*info, &timeline, &gcfg, _output_dir).context("annotate frames")?;
     // organize glyphs = extract glyphs from clips and export them (organizing by similarity)
     #[cfg(feature = "organized-glyph-bitmaps")]
-    organize::organize_glyphs(&info, &timeline, &gcfg, _output_dir).context("recognize glyphs")?;
+    organize::organize_glyphs(&*info, &timeline, &gcfg, _output_dir).context("recognize glyphs")?;
 
-    let (timeline_len, _) = timeline.iter().size_hint();
     info.set_progress(SetProgressInfo {
-        total: Some(timeline_len),
+        total: Some(timeline.clip_count().div_ceil(every)),
         progress: Some(0),
         ..Default::default()
     });
 
-    let chars = Arc::new(gcfg.load_glyph_masks(&info).context("load glyph masks")?);
-    let locations = pool.run_ordered_channel(timeline.iter().map(|clip| {
+    let chars = gcfg.load_glyph_masks(&*info).context("load glyph masks")?;
+    let shared_fields = Arc::new(fields.clone());
+    let opts = ffmpeg::ExtractOptions {
+        denoise,
+        ..Default::default()
+    };
+    let values = pool.run_ordered_channel(timeline.iter().step_by(every).map(|clip| {
         let info = Arc::clone(&info);
         let gcfg = Arc::clone(&gcfg);
         let chars = Arc::clone(&chars);
+        let fields = Arc::clone(&shared_fields);
         let clip_path = clip.path.clone();
         move || {
-            scrape_clip_location(&info, &gcfg, &chars, &clip_path)
-                .with_context(|| format!("scrape_clip_location for {:?}", clip_path))
+            scrape_clip_fields(&*info, &gcfg, &chars, &fields, &clip_path, opts)
+                .with_context(|| format!("scrape_clip_fields for {:?}", clip_path))
         }
     }));
 
-    let locations = locations.into_iter().collect::<anyhow::Result<_>>()?;
-    info.set_progress(SetProgressInfo::detail("finished scraping geolocations"));
-    Ok(locations)
+    let sampled = values.into_iter().collect::<anyhow::Result<Vec<_>>>()?;
+    let clip_count = timeline.clip_count();
+    info.set_progress(SetProgressInfo::detail("finished scraping glyphs"));
+    Ok(ScrapedFields {
+        fields,
+        values: forward_fill(&sampled, every, clip_count),
+        scraped: (0..clip_count).map(|i| i % every == 0).collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_glyph_masks, forward_fill, parse_number, GlyphConfig, GlyphField, GlyphRow,
+        GlyphValue, LatLng, WhiteThresholds,
+    };
+    use image::{Rgb, RgbImage};
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn forward_fill_repeats_previous_sample() {
+        assert_eq!(forward_fill(&[1, 4, 7], 3, 8), vec![1, 1, 1, 4, 4, 4, 7, 7]);
+        assert_eq!(forward_fill(&[1, 2, 3], 1, 3), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn numbers_are_parsed_without_their_units() {
+        let units = ["MPH".to_string(), "°".to_string()];
+        assert_eq!(parse_number(" 42 MPH", &units).unwrap(), 42.0);
+        assert_eq!(parse_number("°270.5", &units).unwrap(), 270.5);
+        assert!(parse_number("4Z MPH", &units).is_err());
+        assert!(parse_number("inf", &units).is_err());
+
+        let heading: GlyphField =
+            serde_json::from_str(r#"{"type": "heading", "row": "heading"}"#).unwrap();
+        let parse = |s: &str| heading.parse(&BTreeMap::from([("heading", s.to_string())]));
+        assert!(matches!(parse("123°"), Ok(GlyphValue::Number(n)) if n == 123.0));
+        assert!(parse("400°").is_err());
+    }
+
+    #[test]
+    fn rows_mask_their_own_text_color() {
+        let row = |extra: &str| -> GlyphRow {
+            serde_json::from_str(&format!(
+                r#"{{"name": "speed", "top": 0, "right": 0, "width": 2, "height": 1, "columns": 1{extra}}}"#
+            ))
+            .unwrap()
+        };
+        let mut img = RgbImage::new(2, 1);
+        img.put_pixel(0, 0, Rgb([250, 250, 250]));
+        img.put_pixel(1, 0, Rgb([240, 210, 20]));
+        let mask = |row: &GlyphRow| {
+            let glyph = row
+                .glyphs(&img, &WhiteThresholds::default())
+                .next()
+                .unwrap();
+            glyph.bmp.pixels().map(|px| px.0[0]).collect::<Vec<_>>()
+        };
+
+        assert_eq!(mask(&row("")), [255, 0]);
+        let yellow = row(r#", "color": {"rgb": [255, 220, 0], "tolerance": 40}"#);
+        assert_eq!(mask(&yellow), [0, 255]);
+        let dim_white = row(r#", "whiteThresholds": {"avgMin": 255}"#);
+        assert_eq!(mask(&dim_white), [0, 0]);
+    }
+
+    #[cfg(feature = "app")]
+    #[test]
+    fn validation_reports_config_problems() {
+        let dir = tempfile::tempdir().unwrap();
+        image::GrayImage::new(4, 8)
+            .save(dir.path().join("a.bmp"))
+            .unwrap();
+        let config = r#"{
+            "glyphRows": [
+                { "name": "row", "top": 0, "right": 0, "width": 4, "height": 8, "columns": 2 },
+                { "name": "empty", "top": 0, "right": 0, "width": 0, "height": 8, "columns": 2 }
+            ],
+            "glyphChars": [
+                { "char": "A", "filepath": "a.bmp" },
+                { "char": "B", "filepath": "missing.bmp" }
+            ]
+        }"#;
+        let config_path = dir.path().join("glyphconfig.json");
+        std::fs::write(&config_path, config).unwrap();
+
+        let problems = super::validate_glyph_config(config_path, |p| dir.path().join(p)).unwrap();
+        assert_eq!(problems.len(), 3, "{problems:#?}");
+        assert!(problems[0].contains("degenerate"));
+        assert!(problems[1].contains("row \"empty\" glyphs are 0x8"));
+        assert!(problems[2].contains("missing.bmp"));
+    }
+
+    #[test]
+    fn missing_char_bitmaps_are_skipped_unless_all_are() {
+        let dir = tempfile::tempdir().unwrap();
+        let found = dir.path().join("a.bmp");
+        image::GrayImage::new(4, 8).save(&found).unwrap();
+        let missing = dir.path().join("b.bmp");
+
+        let (masks, skipped) =
+            decode_glyph_masks([("A", &found), ("B", &missing)].into_iter()).unwrap();
+        assert_eq!(masks.len(), 1);
+        assert_eq!(masks[0].0, "A");
+        assert_eq!(skipped.len(), 1);
+        assert!(skipped[0].starts_with("\"B\""));
+
+        assert!(decode_glyph_masks([("B", &missing)].into_iter()).is_err());
+        assert!(decode_glyph_masks(std::iter::empty()).is_ok());
+    }
+
+    #[test]
+    fn unnamed_rows_are_the_latitude_and_longitude() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = r#"{
+            "glyphRows": [
+                { "top": 0, "right": 0, "width": 4, "height": 8, "columns": 9 },
+                { "top": 8, "right": 0, "width": 4, "height": 8, "columns": 10 },
+                { "top": 16, "right": 0, "width": 4, "height": 8, "columns": 3 }
+            ],
+            "glyphChars": []
+        }"#;
+        let config_path = dir.path().join("glyphconfig.json");
+        std::fs::write(&config_path, config).unwrap();
+
+        let gcfg = GlyphConfig::from_path(config_path).unwrap();
+        assert_eq!(gcfg.row("latitude").unwrap().top, 0);
+        assert_eq!(gcfg.row("longitude").unwrap().top, 8);
+        assert_eq!(gcfg.row("2").unwrap().top, 16);
+    }
+
+    #[test]
+    fn loading_rejects_rows_without_columns() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = r#"{
+            "glyphRows": [
+                { "name": "latitude", "top": 0, "right": 0, "width": 4, "height": 8, "columns": 0 }
+            ],
+            "glyphChars": []
+        }"#;
+        let config_path = dir.path().join("glyphconfig.json");
+        std::fs::write(&config_path, config).unwrap();
+
+        let err = GlyphConfig::from_path(config_path).unwrap_err();
+        assert!(format!("{err:#}").contains("row \"latitude\" has a degenerate geometry"));
+    }
+
+    #[test]
+    fn coordinates_are_parsed_from_each_overlay_format() {
+        let parse = |lat, lng| {
+            let loc = LatLng::from_strings(lat, lng).unwrap();
+            ((loc.lat * 1e4).round() / 1e4, (loc.lng * 1e4).round() / 1e4)
+        };
+        assert_eq!(parse("N 40 71280", "W 074 00600"), (40.7128, -74.006));
+        assert_eq!(parse("S:33:86880", "E:151:20930"), (-33.8688, 151.2093));
+        assert_eq!(parse("40°42'46.08\"N", "74°0'21.6\"W"), (40.7128, -74.006));
+        assert_eq!(
+            parse("33°52'7.68\"S", "151°12'33.48\"E"),
+            (-33.8688, 151.2093)
+        );
+        assert_eq!(parse("40.7128 N", "74.0060 W"), (40.7128, -74.006));
+        assert_eq!(parse("33.8688S", "151.2093°E"), (-33.8688, 151.2093));
+        assert_eq!(parse("-33.8688", "151.2093"), (-33.8688, 151.2093));
+
+        assert!(LatLng::from_strings("95.0 N", "0.0 E").is_err());
+        assert!(LatLng::from_strings("40.7128 E", "74.0060 W").is_err());
+        assert!(LatLng::from_strings("garbled", "74.0060 W").is_err());
+    }
 }
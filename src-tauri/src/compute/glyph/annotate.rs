@@ -1,6 +1,9 @@
 use std::{path::Path, time::Duration};
 
-use crate::{compute::{glyph::GlyphConfig, timeline::Timeline}, ffmpeg, JobInfo, SetProgressInfo};
+use crate::{
+    compute::{font, glyph::GlyphConfig, timeline::Timeline},
+    ffmpeg, JobContext, SetProgressInfo,
+};
 
 use anyhow::Context;
 use image::{Rgb, RgbImage};
@@ -13,7 +16,8 @@ struct Rect {
     height: u32,
 }
 
-fn draw_rect_outline(img: &mut RgbImage, rect: Rect, color: Rgb<u8>) {
+/// draw a `thickness` pixel wide outline just inside `rect`, blended over the image by `opacity`
+fn draw_rect_outline(img: &mut RgbImage, rect: Rect, color: Rgb<u8>, thickness: u32, opacity: f32) {
     let img_w = img.width();
     let img_h = img.height();
 
@@ -26,19 +30,50 @@ fn draw_rect_outline(img: &mut RgbImage, rect: Rect, color: Rgb<u8>) {
         return;
     }
 
-    for x in x0..=x1 {
-        img.put_pixel(x, y0, color);
-        img.put_pixel(x, y1, color);
+    // every pixel is visited once, so overlapping edges are not blended twice
+    let thickness = thickness.max(1);
+    let opacity = opacity.clamp(0.0, 1.0);
+    for y in y0..=y1 {
+        let on_edge_row = y < y0 + thickness || y + thickness > y1;
+        for x in x0..=x1 {
+            if !on_edge_row && x >= x0 + thickness && x + thickness <= x1 {
+                continue;
+            }
+            let pixel = img.get_pixel_mut(x, y);
+            for (channel, &target) in pixel.0.iter_mut().zip(&color.0) {
+                let blended = *channel as f32 * (1.0 - opacity) + target as f32 * opacity;
+                *channel = blended.round() as u8;
+            }
+        }
     }
+}
 
-    for y in y0..=y1 {
-        img.put_pixel(x0, y, color);
-        img.put_pixel(x1, y, color);
+/// how the glyph rows are drawn onto annotated frames
+#[derive(Debug, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub(super) struct AnnotateStyle {
+    /// rgb color of the glyph outlines and labels
+    outline_color: [u8; 3],
+    /// width of the glyph outlines in pixels, which needs to be higher to be seen on large frames
+    outline_width: u32,
+    /// how opaque the glyph outlines are from 0 to 1, to keep the glyphs under them visible
+    outline_opacity: f32,
+    /// draw the name of each glyph row above it
+    labels: bool,
+}
+impl Default for AnnotateStyle {
+    fn default() -> Self {
+        Self {
+            outline_color: [255, 0, 0],
+            outline_width: 1,
+            outline_opacity: 1.0,
+            labels: false,
+        }
     }
 }
 
 fn annotate_image(img: &mut RgbImage, gcfg: &GlyphConfig) {
-    const OUTLINE_COLOR: Rgb<u8> = Rgb([255, 0, 0]);
+    let color = Rgb(gcfg.annotate.outline_color);
 
     for grow in &gcfg.glyph_rows {
         for col in 0..grow.columns {
@@ -48,13 +83,28 @@ fn annotate_image(img: &mut RgbImage, gcfg: &GlyphConfig) {
                 width: grow.width,
                 height: grow.height,
             };
-            draw_rect_outline(img, rect, OUTLINE_COLOR);
+            draw_rect_outline(
+                img,
+                rect,
+                color,
+                gcfg.annotate.outline_width,
+                gcfg.annotate.outline_opacity,
+            );
+        }
+
+        if gcfg.annotate.labels {
+            // scale the label relative to the glyphs, and keep a small gap above the row
+            let scale = (grow.height / 10).max(1);
+            let y = grow
+                .top
+                .saturating_sub(font::text_height(scale) + scale * 2);
+            font::draw_text(img, grow.right, y, scale, color, &grow.name);
         }
     }
 }
 
 pub fn annotate_frames(
-    info: &JobInfo,
+    info: &dyn JobContext,
     timeline: &Timeline,
     gcfg: &GlyphConfig,
     output_dir: &Path,
@@ -91,3 +141,28 @@ pub fn annotate_frames(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{draw_rect_outline, Rect};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn thick_outlines_are_blended_inside_the_rect() {
+        let mut img = RgbImage::from_pixel(10, 10, Rgb([0, 0, 0]));
+        let rect = Rect {
+            x: 1,
+            y: 1,
+            width: 8,
+            height: 8,
+        };
+        draw_rect_outline(&mut img, rect, Rgb([200, 100, 0]), 2, 0.5);
+
+        assert_eq!(img.get_pixel(1, 1), &Rgb([100, 50, 0]));
+        assert_eq!(img.get_pixel(2, 5), &Rgb([100, 50, 0]));
+        assert_eq!(img.get_pixel(7, 5), &Rgb([100, 50, 0]));
+        // inside the outline and outside the rect are untouched
+        assert_eq!(img.get_pixel(3, 5), &Rgb([0, 0, 0]));
+        assert_eq!(img.get_pixel(0, 0), &Rgb([0, 0, 0]));
+    }
+}
@@ -2,13 +2,13 @@ use std::{path::Path, time::Duration};
 
 use crate::{
     compute::{glyph::GlyphConfig, timeline::Timeline},
-    ffmpeg, JobInfo, SetProgressInfo,
+    ffmpeg, JobContext, SetProgressInfo,
 };
 
 const GLYPH_MASK_SIMILARITY_THRESHOLD: f64 = 0.85;
 
 pub fn organize_glyphs(
-    info: &JobInfo,
+    info: &dyn JobContext,
     timeline: &Timeline,
     gcfg: &GlyphConfig,
     output_dir: &Path,
@@ -25,7 +25,7 @@ pub fn organize_glyphs(
         std::mem::drop(jpg_data);
 
         for row in gcfg.glyph_rows.iter() {
-            for gmask in row.glyphs(&rgb) {
+            for gmask in row.glyphs(&rgb, &gcfg.white_thresholds) {
                 let mut best_idx = 0;
                 let mut best_score = 0.0;
                 for (i, unique_gmask) in unique_glyphs.iter().enumerate() {
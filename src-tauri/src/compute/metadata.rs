@@ -0,0 +1,125 @@
+use std::io::Cursor;
+
+use anyhow::Context;
+use exif::{experimental::Writer, Field, In, Rational, Tag, Value};
+use img_parts::{jpeg::Jpeg, ImageEXIF};
+
+use crate::compute::glyph::LatLng;
+
+/// where and when a frame was recorded in the real world
+#[derive(Debug, Default, Clone)]
+pub struct FrameMeta {
+    pub taken_at: Option<chrono::DateTime<chrono_tz::Tz>>,
+    pub location: Option<LatLng>,
+}
+
+fn ascii(tag: Tag, s: &str) -> Field {
+    Field {
+        tag,
+        ifd_num: In::PRIMARY,
+        value: Value::Ascii(vec![s.as_bytes().to_vec()]),
+    }
+}
+
+/// a coordinate as exif degrees, minutes and seconds
+fn dms(coord: f64) -> Value {
+    let coord = coord.abs();
+    let degrees = coord.trunc();
+    let minutes = ((coord - degrees) * 60.0).trunc();
+    let seconds = (coord - degrees - minutes / 60.0) * 3600.0;
+    Value::Rational(vec![
+        Rational::from((degrees as u32, 1)),
+        Rational::from((minutes as u32, 1)),
+        Rational::from(((seconds * 1000.0).round() as u32, 1000)),
+    ])
+}
+
+/// replace the exif data of a jpg with the `DateTimeOriginal` and gps tags from `meta`
+pub fn embed_exif(jpg_data: Vec<u8>, meta: &FrameMeta) -> anyhow::Result<Vec<u8>> {
+    let mut fields = Vec::new();
+    if let Some(taken_at) = &meta.taken_at {
+        let datetime = taken_at.format("%Y:%m:%d %H:%M:%S").to_string();
+        let offset = taken_at.format("%:z").to_string();
+        fields.push(ascii(Tag::DateTimeOriginal, &datetime));
+        fields.push(ascii(Tag::OffsetTimeOriginal, &offset));
+    }
+    if let Some(loc) = meta.location.as_ref().filter(|loc| loc.is_valid()) {
+        fields.push(Field {
+            tag: Tag::GPSVersionID,
+            ifd_num: In::PRIMARY,
+            value: Value::Byte(vec![2, 3, 0, 0]),
+        });
+        fields.push(ascii(
+            Tag::GPSLatitudeRef,
+            if loc.lat < 0.0 { "S" } else { "N" },
+        ));
+        fields.push(Field {
+            tag: Tag::GPSLatitude,
+            ifd_num: In::PRIMARY,
+            value: dms(loc.lat),
+        });
+        fields.push(ascii(
+            Tag::GPSLongitudeRef,
+            if loc.lng < 0.0 { "W" } else { "E" },
+        ));
+        fields.push(Field {
+            tag: Tag::GPSLongitude,
+            ifd_num: In::PRIMARY,
+            value: dms(loc.lng),
+        });
+    }
+    if fields.is_empty() {
+        return Ok(jpg_data);
+    }
+
+    let mut writer = Writer::new();
+    for field in &fields {
+        writer.push_field(field);
+    }
+    let mut tiff = Cursor::new(Vec::new());
+    writer.write(&mut tiff, false).context("write exif data")?;
+
+    let mut jpeg = Jpeg::from_bytes(jpg_data.into()).context("parse jpg segments")?;
+    jpeg.set_exif(Some(tiff.into_inner().into()));
+    Ok(jpeg.encoder().bytes().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{embed_exif, FrameMeta};
+    use crate::compute::{glyph::LatLng, overlay::encode_jpeg};
+    use chrono::TimeZone;
+    use exif::{In, Tag};
+
+    #[test]
+    fn exif_round_trips_through_reader() {
+        let jpg_data = encode_jpeg(&image::RgbImage::new(8, 8)).unwrap();
+        let meta = FrameMeta {
+            taken_at: Some(
+                chrono_tz::America::New_York
+                    .with_ymd_and_hms(2024, 5, 6, 14, 30, 0)
+                    .unwrap(),
+            ),
+            location: Some(LatLng {
+                lat: 40.5,
+                lng: -73.25,
+            }),
+        };
+        let jpg_data = embed_exif(jpg_data, &meta).unwrap();
+        assert!(image::load_from_memory(&jpg_data).is_ok());
+
+        let exif = exif::Reader::new()
+            .read_from_container(&mut std::io::Cursor::new(&jpg_data))
+            .unwrap();
+        let get = |tag| {
+            exif.get_field(tag, In::PRIMARY)
+                .unwrap()
+                .display_value()
+                .to_string()
+        };
+        assert_eq!(get(Tag::DateTimeOriginal), "2024-05-06 14:30:00");
+        assert_eq!(get(Tag::GPSLatitudeRef), "N");
+        assert_eq!(get(Tag::GPSLongitudeRef), "W");
+        assert_eq!(get(Tag::GPSLongitude), "73 deg 15 min 0 sec");
+    }
+}
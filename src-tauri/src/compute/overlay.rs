@@ -0,0 +1,318 @@
+use std::time::Duration;
+
+use anyhow::Context;
+use image::{
+    codecs::jpeg::JpegEncoder, imageops::FilterType, GenericImageView, PixelWithColorType, Rgb,
+    RgbImage,
+};
+
+use crate::compute::font;
+
+/// quality of frames that are re-encoded after drawing overlays
+const OVERLAY_JPEG_QUALITY: u8 = 95;
+
+/// where a frame comes from, for overlays that depend on it
+pub struct FrameContext {
+    /// offset of the frame within the timeline
+    pub ts: Duration,
+    /// total length of the timeline
+    pub timeline_len: Duration,
+    /// the real-time frame of the source clip, when there is a picture-in-picture to draw
+    pub pip: Option<RgbImage>,
+    /// file name of the clip the frame was extracted from
+    pub source_name: String,
+    /// offset of the frame within its clip
+    pub ts_in_clip: Duration,
+}
+
+/// overlays drawn onto each timelapse frame before encoding
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Overlays {
+    pub progress_bar: Option<ProgressBarOverlay>,
+    pub picture_in_picture: Option<PictureInPictureOverlay>,
+    pub source: Option<SourceOverlay>,
+}
+impl Overlays {
+    pub fn is_empty(&self) -> bool {
+        self.progress_bar.is_none() && self.picture_in_picture.is_none() && self.source.is_none()
+    }
+    /// draw the enabled overlays onto an already decoded frame
+    pub fn draw(&self, img: &mut RgbImage, frame: &FrameContext) {
+        if let Some(pip) = &self.picture_in_picture {
+            pip.draw(img, frame);
+        }
+        if let Some(bar) = &self.progress_bar {
+            bar.draw(img, frame);
+        }
+        if let Some(source) = &self.source {
+            source.draw(img, frame);
+        }
+    }
+}
+
+/// a bar along the bottom of the frame, filled by how far through the timeline the frame is
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct ProgressBarOverlay {
+    pub color: [u8; 3],
+    /// height of the bar in pixels
+    pub height: u32,
+}
+impl Default for ProgressBarOverlay {
+    fn default() -> Self {
+        Self {
+            color: [255, 255, 255],
+            height: 6,
+        }
+    }
+}
+impl ProgressBarOverlay {
+    fn draw(&self, img: &mut RgbImage, frame: &FrameContext) {
+        let (width, height) = img.dimensions();
+        let fraction = if frame.timeline_len.is_zero() {
+            0.0
+        } else {
+            (frame.ts.as_secs_f64() / frame.timeline_len.as_secs_f64()).clamp(0.0, 1.0)
+        };
+        let fill_width = (width as f64 * fraction).round() as u32;
+
+        for y in height.saturating_sub(self.height)..height {
+            for x in 0..fill_width {
+                img.put_pixel(x, y, Rgb(self.color));
+            }
+        }
+    }
+}
+
+/// a corner of the frame
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    #[default]
+    BottomRight,
+}
+
+/// the source clip playing at real-time speed, inset in a corner of the timelapse
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct PictureInPictureOverlay {
+    pub corner: Corner,
+    /// width of the inset, as a fraction of the width of the frame
+    pub scale: f32,
+    /// gap between the inset and the edges of the frame in pixels
+    pub margin: u32,
+    /// width of the white border around the inset in pixels
+    pub border: u32,
+}
+impl Default for PictureInPictureOverlay {
+    fn default() -> Self {
+        Self {
+            corner: Corner::default(),
+            scale: 0.3,
+            margin: 16,
+            border: 2,
+        }
+    }
+}
+impl PictureInPictureOverlay {
+    fn draw(&self, img: &mut RgbImage, frame: &FrameContext) {
+        let Some(pip) = &frame.pip else {
+            return;
+        };
+        let (width, height) = img.dimensions();
+        let inset_w = ((width as f32 * self.scale.clamp(0.0, 1.0)).round() as u32).max(1);
+        let inset_h = (inset_w as u64 * pip.height() as u64 / pip.width().max(1) as u64).max(1);
+        let inset = image::imageops::resize(pip, inset_w, inset_h as u32, FilterType::Triangle);
+
+        let (outer_w, outer_h) = (
+            inset.width() as i64 + 2 * self.border as i64,
+            inset.height() as i64 + 2 * self.border as i64,
+        );
+        let margin = self.margin as i64;
+        let x = match self.corner {
+            Corner::TopLeft | Corner::BottomLeft => margin,
+            Corner::TopRight | Corner::BottomRight => width as i64 - outer_w - margin,
+        };
+        let y = match self.corner {
+            Corner::TopLeft | Corner::TopRight => margin,
+            Corner::BottomLeft | Corner::BottomRight => height as i64 - outer_h - margin,
+        };
+
+        // the border is a filled rectangle that the inset then covers the middle of
+        for py in y.max(0)..(y + outer_h).min(height as i64) {
+            for px in x.max(0)..(x + outer_w).min(width as i64) {
+                img.put_pixel(px as u32, py as u32, Rgb([255, 255, 255]));
+            }
+        }
+        let border = self.border as i64;
+        image::imageops::overlay(img, &inset, x + border, y + border);
+    }
+}
+
+/// the source clip's file name and the offset of the frame within it, so every frame of an
+/// evidentiary timelapse can be traced back to the exact footage it came from
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SourceOverlay {
+    pub corner: Corner,
+    /// size of each pixel of the font, which is 5 pixels tall
+    pub scale: u32,
+    /// gap between the label and the edges of the frame in pixels
+    pub margin: u32,
+    pub color: [u8; 3],
+}
+impl Default for SourceOverlay {
+    fn default() -> Self {
+        Self {
+            corner: Corner::TopLeft,
+            scale: 3,
+            margin: 16,
+            color: [255, 255, 255],
+        }
+    }
+}
+impl SourceOverlay {
+    fn label(frame: &FrameContext) -> String {
+        let millis = frame.ts_in_clip.as_millis();
+        format!(
+            "{} +{:02}:{:02}:{:02}.{:03}",
+            frame.source_name,
+            millis / 3_600_000,
+            millis / 60_000 % 60,
+            millis / 1000 % 60,
+            millis % 1000
+        )
+    }
+    fn draw(&self, img: &mut RgbImage, frame: &FrameContext) {
+        let label = Self::label(frame);
+        let scale = self.scale.max(1);
+        let (width, height) = img.dimensions();
+        // the label sits on a black box a font pixel wider on each side, so it reads on any footage
+        let (box_w, box_h) = (
+            font::text_width(scale, &label) + 2 * scale,
+            font::text_height(scale) + 2 * scale,
+        );
+        let x = match self.corner {
+            Corner::TopLeft | Corner::BottomLeft => self.margin,
+            Corner::TopRight | Corner::BottomRight => width.saturating_sub(box_w + self.margin),
+        };
+        let y = match self.corner {
+            Corner::TopLeft | Corner::TopRight => self.margin,
+            Corner::BottomLeft | Corner::BottomRight => height.saturating_sub(box_h + self.margin),
+        };
+        for py in y..(y + box_h).min(height) {
+            for px in x..(x + box_w).min(width) {
+                img.put_pixel(px, py, Rgb([0, 0, 0]));
+            }
+        }
+        font::draw_text(img, x + scale, y + scale, scale, Rgb(self.color), &label);
+    }
+}
+
+pub fn encode_jpeg<I>(img: &I) -> anyhow::Result<Vec<u8>>
+where
+    I: GenericImageView,
+    I::Pixel: PixelWithColorType,
+{
+    let mut buf = Vec::new();
+    JpegEncoder::new_with_quality(&mut buf, OVERLAY_JPEG_QUALITY)
+        .encode_image(img)
+        .context("encode jpg")?;
+    Ok(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Corner, FrameContext, PictureInPictureOverlay, ProgressBarOverlay, SourceOverlay};
+    use image::{Rgb, RgbImage};
+    use std::time::Duration;
+
+    #[test]
+    fn progress_bar_fills_proportionally() {
+        let mut img = RgbImage::new(100, 20);
+        let bar = ProgressBarOverlay {
+            color: [255, 0, 0],
+            height: 4,
+        };
+        bar.draw(
+            &mut img,
+            &FrameContext {
+                ts: Duration::from_secs(25),
+                timeline_len: Duration::from_secs(100),
+                pip: None,
+                source_name: String::new(),
+                ts_in_clip: Duration::ZERO,
+            },
+        );
+        assert_eq!(*img.get_pixel(24, 19), Rgb([255, 0, 0]));
+        assert_eq!(*img.get_pixel(24, 16), Rgb([255, 0, 0]));
+        assert_eq!(*img.get_pixel(25, 19), Rgb([0, 0, 0]));
+        assert_eq!(*img.get_pixel(0, 15), Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn picture_in_picture_is_inset_in_its_corner() {
+        let mut img = RgbImage::new(100, 100);
+        let pip = PictureInPictureOverlay {
+            corner: Corner::BottomRight,
+            scale: 0.2,
+            margin: 4,
+            border: 2,
+        };
+        pip.draw(
+            &mut img,
+            &FrameContext {
+                ts: Duration::ZERO,
+                timeline_len: Duration::from_secs(100),
+                pip: Some(RgbImage::from_pixel(40, 20, Rgb([255, 0, 0]))),
+                source_name: String::new(),
+                ts_in_clip: Duration::ZERO,
+            },
+        );
+        // a 20x10 inset with a 2px border ends 4px from the bottom right
+        assert_eq!(*img.get_pixel(72, 82), Rgb([255, 255, 255]));
+        assert_eq!(*img.get_pixel(74, 84), Rgb([255, 0, 0]));
+        assert_eq!(*img.get_pixel(93, 93), Rgb([255, 0, 0]));
+        assert_eq!(*img.get_pixel(95, 95), Rgb([255, 255, 255]));
+        assert_eq!(*img.get_pixel(71, 82), Rgb([0, 0, 0]));
+        assert_eq!(*img.get_pixel(96, 96), Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn source_label_is_drawn_in_its_corner() {
+        let frame = FrameContext {
+            ts: Duration::ZERO,
+            timeline_len: Duration::from_secs(100),
+            pip: None,
+            source_name: "2024_0501_120000_F.mp4".into(),
+            ts_in_clip: Duration::from_millis(83_450),
+        };
+        assert_eq!(
+            SourceOverlay::label(&frame),
+            "2024_0501_120000_F.mp4 +00:01:23.450"
+        );
+
+        let mut img = RgbImage::from_pixel(400, 100, Rgb([0, 0, 255]));
+        let source = SourceOverlay {
+            corner: Corner::BottomRight,
+            scale: 1,
+            margin: 2,
+            color: [255, 255, 255],
+        };
+        source.draw(&mut img, &frame);
+        // 36 chars are 143px wide, boxed to 145x7 and ending 2px from the bottom right
+        assert_eq!(*img.get_pixel(252, 90), Rgb([0, 0, 255]));
+        assert_eq!(*img.get_pixel(253, 91), Rgb([0, 0, 0]));
+        assert_eq!(*img.get_pixel(397, 97), Rgb([0, 0, 0]));
+        assert_eq!(*img.get_pixel(398, 98), Rgb([0, 0, 255]));
+        let white = img
+            .pixels()
+            .filter(|&&px| px == Rgb([255, 255, 255]))
+            .count();
+        assert!(white > 0);
+    }
+}
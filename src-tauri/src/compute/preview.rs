@@ -0,0 +1,126 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use base64::Engine;
+
+use crate::{
+    compute::{timelapse, timeline::Timeline, workers::WorkerPool, TimelapseSettings},
+    ffmpeg, JobContext, SetProgressInfo,
+};
+
+/// seconds of output encoded for a preview, unless asked for another length
+#[cfg(feature = "app")]
+pub const DEFAULT_PREVIEW_SECS: u32 = 3;
+/// upper bound on the seconds of output encoded for a preview, to keep it quick
+const MAX_PREVIEW_SECS: u32 = 10;
+
+/// encode the first `secs` seconds of the timelapse with its video settings, returning the mp4 as
+/// a base64 data url that a `<video>` can play inline
+pub fn encode_preview(
+    info: Arc<dyn JobContext>,
+    timeline: Arc<Timeline>,
+    pool: &WorkerPool,
+    settings: &TimelapseSettings,
+    secs: u32,
+) -> anyhow::Result<String> {
+    let (timestamps, black) = timelapse::timelapse_frames(&*info, &timeline, settings);
+    let frames = (secs.clamp(1, MAX_PREVIEW_SECS) * settings.fps.max(1)) as usize;
+    let sample = timestamps
+        .into_iter()
+        .zip(black)
+        .take(frames)
+        .collect::<Vec<_>>();
+    if sample.is_empty() {
+        anyhow::bail!("timelapse would not have any frames");
+    }
+
+    info.set_progress(SetProgressInfo {
+        progress: Some(0),
+        total: Some(sample.len()),
+        ..Default::default()
+    });
+
+    let overlays = Arc::new(settings.overlays.clone());
+    let extract = ffmpeg::ExtractOptions {
+        intermediate: settings.intermediate,
+        ..settings.extract_options()
+    };
+    let crop = settings.crop;
+    let jobs = pool.run_ordered_channel(sample.iter().map(|&(ts, black)| {
+        let info = Arc::clone(&info);
+        let timeline = Arc::clone(&timeline);
+        let overlays = Arc::clone(&overlays);
+        move || {
+            info.cancel_result()?;
+            // the picture-in-picture is sampled at the frame itself, like in a size estimate
+            let frame = timelapse::extract_timelapse_frame(
+                &timeline,
+                &overlays,
+                ts,
+                false,
+                extract,
+                crop,
+                Some(ts),
+            );
+            info.log_ffmpeg_diagnostics(&timeline.get_at(ts).1.path);
+            let frame = frame?;
+            match black {
+                true => frame.blacked_out(),
+                false => Ok(frame),
+            }
+        }
+    }));
+
+    // always mp4, since that is what the webview can play, whatever the container of the output
+    let temp_dir = tempfile::tempdir().context("create temp dir for preview encode")?;
+    let preview_path = temp_dir.path().join("preview.mp4");
+    let mut enc = ffmpeg::Mp4FrameEncoder::new(
+        &preview_path,
+        extract.intermediate.frame_input(),
+        &[],
+        &[],
+        // a target size is meant for the whole timelapse, and would be spent on the short preview
+        &ffmpeg::VideoOptions {
+            rate: ffmpeg::RateControl::Crf,
+            ..settings.video_options(ffmpeg::Container::Mp4)
+        },
+    )
+    .context("create preview encoder")?;
+    let mut encoded_frames = 0;
+    for (i, job) in jobs.into_iter().enumerate() {
+        info.cancel_result()?;
+        match job.with_context(|| format!("extract preview frame {}", i)) {
+            Ok(frame) => {
+                let data = frame.into_intermediate(extract.intermediate)?;
+                enc.encode_frame(&data)
+                    .with_context(|| format!("encode preview frame {}", i))?;
+                encoded_frames += 1;
+            }
+            Err(e) => info.set_progress(SetProgressInfo::warn(format!(
+                "could not extract preview frame {i}/{}\n{e}\n\n",
+                sample.len()
+            ))),
+        }
+        info.set_progress(SetProgressInfo {
+            progress_inc: Some(1),
+            ..Default::default()
+        });
+    }
+    if encoded_frames == 0 {
+        anyhow::bail!("none of the preview frames could be extracted");
+    }
+    enc.finish().context("finish preview encode")?;
+    info.log_ffmpeg_diagnostics(&preview_path);
+
+    let mp4 = std::fs::read(&preview_path).context("read preview encode")?;
+    info.set_progress(SetProgressInfo::detail(format!(
+        "encoded a {:.02}s preview of {} frames into {:.02}MB",
+        encoded_frames as f64 / settings.fps.max(1) as f64,
+        encoded_frames,
+        mp4.len() as f64 / 1024.0 / 1024.0
+    )));
+    Ok(format!(
+        "data:video/mp4;base64,{}",
+        base64::engine::general_purpose::STANDARD.encode(mp4)
+    ))
+}
@@ -0,0 +1,139 @@
+use std::{
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+
+use crate::{
+    compute::{
+        overlay::Overlays,
+        timelapse,
+        timeline::{Timeline, TimelineOptions},
+        workers::WorkerPool,
+    },
+    ffmpeg, JobContext, SetProgressInfo,
+};
+
+/// named like a dashcam clip so the timeline can parse a timestamp from it
+const TEST_CLIP_NAME: &str = "2024_0101_120000_selftest.mp4";
+const TEST_CLIP_LENGTH: Duration = Duration::from_secs(2);
+const TEST_VIDEO_FPS: u32 = 30;
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelfTestStage {
+    pub name: &'static str,
+    pub passed: bool,
+    /// the error chain, if the stage failed
+    pub error: Option<String>,
+    pub elapsed_secs: f64,
+}
+
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SelfTestReport {
+    pub passed: bool,
+    /// every stage that ran, stopping at the first failure since each stage needs the last one
+    pub stages: Vec<SelfTestStage>,
+}
+
+/// exercise the whole pipeline on a synthetic clip: ffmpeg, ffprobe, the worker pool and the encoder
+pub fn self_test(info: Arc<dyn JobContext>, threads: usize) -> anyhow::Result<SelfTestReport> {
+    let temp_dir = tempfile::tempdir().context("create temp dir for self test")?;
+    let clip_dir = temp_dir.path().join("clips");
+    std::fs::create_dir(&clip_dir).context("create self test clip dir")?;
+    let pool = WorkerPool::new(threads);
+
+    let mut stages = Vec::new();
+    let result = (|| {
+        run_stage(&*info, &mut stages, "generate clip", || {
+            ffmpeg::generate_test_clip(&clip_dir.join(TEST_CLIP_NAME), TEST_CLIP_LENGTH)
+        })?;
+        let timeline = run_stage(&*info, &mut stages, "build timeline", || {
+            Timeline::new_from_path(
+                Arc::clone(&info),
+                &pool,
+                &clip_dir,
+                &TimelineOptions::default(),
+            )
+        })?;
+        let jpg = run_stage(&*info, &mut stages, "extract frame", || {
+            timelapse::extract_timelapse_frame(
+                &timeline,
+                &Overlays::default(),
+                timeline.len() / 2,
+                false,
+                ffmpeg::ExtractOptions::default(),
+                None,
+                None,
+            )?
+            .into_jpg()
+        })?;
+        run_stage(&*info, &mut stages, "encode video", || {
+            let output = temp_dir.path().join("selftest.mp4");
+            let mut enc = ffmpeg::Mp4FrameEncoder::new(
+                &output,
+                ffmpeg::FrameInput::Mjpeg,
+                &[],
+                &[],
+                &ffmpeg::VideoOptions {
+                    fps: TEST_VIDEO_FPS,
+                    container: ffmpeg::Container::Mp4,
+                    rate: ffmpeg::RateControl::Crf,
+                    extra_args: Vec::new(),
+                },
+            )?;
+            for _ in 0..TEST_VIDEO_FPS {
+                enc.encode_frame(&jpg)?;
+            }
+            enc.finish()?;
+            if std::fs::metadata(&output)
+                .context("read encoded video")?
+                .len()
+                == 0
+            {
+                anyhow::bail!("encoded video is empty");
+            }
+            Ok(())
+        })
+    })();
+
+    Ok(SelfTestReport {
+        passed: result.is_some(),
+        stages,
+    })
+}
+
+/// time one stage and record how it went, returning its output if it passed
+fn run_stage<T>(
+    info: &dyn JobContext,
+    stages: &mut Vec<SelfTestStage>,
+    name: &'static str,
+    f: impl FnOnce() -> anyhow::Result<T>,
+) -> Option<T> {
+    let start = Instant::now();
+    let result = f();
+    info.log_ffmpeg_diagnostics(Path::new(name));
+    let elapsed_secs = start.elapsed().as_secs_f64();
+    let error = match &result {
+        Ok(_) => {
+            info.set_progress(SetProgressInfo::detail(format!("self test: {name} passed")));
+            None
+        }
+        Err(e) => {
+            info.set_progress(SetProgressInfo::error(format!(
+                "self test: {name} failed\n{e:?}"
+            )));
+            Some(format!("{e:#}"))
+        }
+    };
+    stages.push(SelfTestStage {
+        name,
+        passed: error.is_none(),
+        error,
+        elapsed_secs,
+    });
+    result.ok()
+}
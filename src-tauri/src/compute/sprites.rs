@@ -0,0 +1,213 @@
+use std::{path::Path, sync::Arc};
+
+use anyhow::Context;
+use image::{imageops::FilterType, RgbImage};
+
+use crate::{
+    compute::{
+        archive::ExtractedZip, atomic::write_atomically, overlay, thumbnail, timeline::Timeline,
+        workers::WorkerPool,
+    },
+    JobContext, SetProgressInfo,
+};
+
+/// the widest or tallest a sheet can be, which is the most a jpg allows
+const MAX_SHEET_SIZE: u32 = u16::MAX as u32;
+
+/// how the thumbnail of every clip is tiled into sprite sheets, for scrubbing through a timeline
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct SpriteSheetOptions {
+    pub tile_width: u32,
+    pub tile_height: u32,
+    /// tiles per row of a sheet
+    pub columns: u32,
+    /// frames sampled from each clip when picking its thumbnail, where 1 is fastest
+    pub candidates: usize,
+}
+impl Default for SpriteSheetOptions {
+    fn default() -> Self {
+        Self {
+            tile_width: 160,
+            tile_height: 90,
+            columns: 10,
+            candidates: 1,
+        }
+    }
+}
+impl SpriteSheetOptions {
+    /// the most tiles that fit in one sheet
+    fn tiles_per_sheet(&self) -> usize {
+        let rows = (MAX_SHEET_SIZE / self.tile_height).max(1);
+        (self.columns * rows) as usize
+    }
+    /// which sheet tile `i` is on, and the pixel position of its top-left corner there
+    fn tile_position(&self, i: usize) -> (usize, u32, u32) {
+        let per_sheet = self.tiles_per_sheet();
+        let on_sheet = (i % per_sheet) as u32;
+        (
+            i / per_sheet,
+            on_sheet % self.columns * self.tile_width,
+            on_sheet / self.columns * self.tile_height,
+        )
+    }
+}
+
+/// the companion json of the sprite sheets, locating each clip's tile
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpriteIndex {
+    /// file names of the sheets, in the output directory
+    sheets: Vec<String>,
+    tile_width: u32,
+    tile_height: u32,
+    tiles: Vec<SpriteTile>,
+}
+#[derive(Debug, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+struct SpriteTile {
+    file_path: String,
+    timestamp: String,
+    /// index into `sheets`
+    sheet: usize,
+    x: u32,
+    y: u32,
+    /// the clip's thumbnail couldn't be extracted, so its tile is left black
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    missing: bool,
+}
+
+/// tile a thumbnail of every clip into `sprites_N.jpg` sheets, indexed by `sprites.json`
+pub fn export_sprite_sheet(
+    info: Arc<dyn JobContext>,
+    timeline: &Timeline,
+    extracted: Option<&ExtractedZip>,
+    pool: &WorkerPool,
+    opts: &SpriteSheetOptions,
+    output_dir: &Path,
+) -> anyhow::Result<()> {
+    if opts.tile_width == 0 || opts.tile_height == 0 || opts.columns == 0 {
+        anyhow::bail!(
+            "sprite tiles of {} columns of {}x{} must all be above 0",
+            opts.columns,
+            opts.tile_width,
+            opts.tile_height
+        );
+    }
+    let sheet_width = opts.columns as u64 * opts.tile_width as u64;
+    if sheet_width > u64::from(MAX_SHEET_SIZE) || opts.tile_height > MAX_SHEET_SIZE {
+        anyhow::bail!(
+            "sprite sheets of {} columns of {}x{} would be over the jpg limit of {} pixels",
+            opts.columns,
+            opts.tile_width,
+            opts.tile_height,
+            MAX_SHEET_SIZE
+        );
+    }
+
+    info.set_progress(SetProgressInfo {
+        progress: Some(0),
+        total: Some(timeline.clip_count()),
+        ..Default::default()
+    });
+    let (width, height, candidates) = (opts.tile_width, opts.tile_height, opts.candidates);
+    let thumbnails = pool.run_ordered_channel(timeline.iter().map(|clip| {
+        let info = Arc::clone(&info);
+        let clip_path = clip.path.clone();
+        move || {
+            let tile = thumbnail::representative_frame(&clip_path, candidates)
+                .and_then(|jpg| Ok(image::load_from_memory(&jpg)?))
+                .map(|img| {
+                    img.resize_to_fill(width, height, FilterType::Triangle)
+                        .to_rgb8()
+                })
+                .with_context(|| format!("extract thumbnail of {:?}", clip_path));
+            info.log_ffmpeg_diagnostics(&clip_path);
+            info.set_progress(SetProgressInfo {
+                progress_inc: Some(1),
+                ..Default::default()
+            });
+            tile
+        }
+    }));
+
+    let mut sheets: Vec<RgbImage> = Vec::new();
+    let mut tiles = Vec::with_capacity(timeline.clip_count());
+    for (i, (clip, tile)) in timeline.iter().zip(thumbnails).enumerate() {
+        info.cancel_result()?;
+        let (sheet, x, y) = opts.tile_position(i);
+        if sheet == sheets.len() {
+            let remaining = (timeline.clip_count() - i).min(opts.tiles_per_sheet()) as u32;
+            let columns = remaining.min(opts.columns);
+            let rows = remaining.div_ceil(opts.columns);
+            sheets.push(RgbImage::new(columns * width, rows * height));
+        }
+        let missing = match tile {
+            Ok(tile) => {
+                image::imageops::replace(&mut sheets[sheet], &tile, x.into(), y.into());
+                false
+            }
+            Err(e) => {
+                info.set_progress(SetProgressInfo::warn(format!("{:#}", e)));
+                true
+            }
+        };
+        tiles.push(SpriteTile {
+            file_path: extracted
+                .map_or_else(|| clip.path.clone(), |zip| zip.source_path(&clip.path))
+                .to_string_lossy()
+                .into(),
+            timestamp: clip.creation_time.to_rfc3339(),
+            sheet,
+            x,
+            y,
+            missing,
+        });
+    }
+
+    let mut sheet_names = Vec::with_capacity(sheets.len());
+    for (i, sheet) in sheets.iter().enumerate() {
+        let name = format!("sprites_{i}.jpg");
+        let jpg = overlay::encode_jpeg(sheet).context("encode sprite sheet")?;
+        write_atomically(&output_dir.join(&name), &jpg)
+            .with_context(|| format!("write sprite sheet {:?}", name))?;
+        info.add_bytes_written(jpg.len() as u64);
+        sheet_names.push(name);
+    }
+    let index = SpriteIndex {
+        sheets: sheet_names,
+        tile_width: width,
+        tile_height: height,
+        tiles,
+    };
+    let json = serde_json::to_string(&index)?;
+    write_atomically(&output_dir.join("sprites.json"), &json).context("write sprite index")?;
+    info.add_bytes_written(json.len() as u64);
+    info.set_progress(SetProgressInfo::detail(format!(
+        "tiled {} clips into {} sprite sheets",
+        index.tiles.len(),
+        index.sheets.len()
+    )));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SpriteSheetOptions;
+
+    #[test]
+    fn tiles_wrap_into_rows_and_then_sheets() {
+        let opts = SpriteSheetOptions {
+            tile_width: 100,
+            tile_height: 30000,
+            columns: 3,
+            candidates: 1,
+        };
+        // only 2 rows of 30000 fit under the jpg height limit
+        assert_eq!(opts.tiles_per_sheet(), 6);
+        assert_eq!(opts.tile_position(0), (0, 0, 0));
+        assert_eq!(opts.tile_position(2), (0, 200, 0));
+        assert_eq!(opts.tile_position(4), (0, 100, 30000));
+        assert_eq!(opts.tile_position(7), (1, 100, 0));
+    }
+}
@@ -0,0 +1,93 @@
+use chrono::{DateTime, NaiveDate, Utc};
+
+use super::glyph::LatLng;
+
+/// julian day of the unix epoch
+const UNIX_EPOCH_JULIAN_DAY: f64 = 2440587.5;
+/// julian day of the J2000 epoch the solar equations are relative to
+const J2000_JULIAN_DAY: f64 = 2451545.0;
+/// axial tilt of the earth, in degrees
+const EARTH_OBLIQUITY: f64 = 23.4397;
+/// altitude of the sun's center at sunrise and sunset, accounting for refraction and its radius
+const SUNRISE_ALTITUDE: f64 = -0.833;
+
+/// sunrise and sunset at `loc` on `date`, or `None` if the sun never rises or sets that day
+///
+/// uses the sunrise equation, which is accurate to within a minute or two away from the poles
+pub(super) fn sunrise_sunset(
+    date: NaiveDate,
+    loc: &LatLng,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    let noon_julian_day = UNIX_EPOCH_JULIAN_DAY
+        + 0.5
+        + date
+            .signed_duration_since(DateTime::UNIX_EPOCH.date_naive())
+            .num_days() as f64;
+    // mean solar noon, with longitude positive to the east
+    let mean_noon = (noon_julian_day - J2000_JULIAN_DAY + 0.0008).round() - loc.lng / 360.0;
+
+    let anomaly = (357.5291 + 0.98560028 * mean_noon)
+        .rem_euclid(360.0)
+        .to_radians();
+    let center =
+        1.9148 * anomaly.sin() + 0.02 * (2.0 * anomaly).sin() + 0.0003 * (3.0 * anomaly).sin();
+    let ecliptic_lng = (anomaly.to_degrees() + center + 180.0 + 102.9372)
+        .rem_euclid(360.0)
+        .to_radians();
+    let transit =
+        J2000_JULIAN_DAY + mean_noon + 0.0053 * anomaly.sin() - 0.0069 * (2.0 * ecliptic_lng).sin();
+
+    let declination = (ecliptic_lng.sin() * EARTH_OBLIQUITY.to_radians().sin()).asin();
+    let lat = loc.lat.to_radians();
+    let cos_hour_angle = (SUNRISE_ALTITUDE.to_radians().sin() - lat.sin() * declination.sin())
+        / (lat.cos() * declination.cos());
+    if !(-1.0..=1.0).contains(&cos_hour_angle) {
+        return None;
+    }
+    let half_day = cos_hour_angle.acos().to_degrees() / 360.0;
+
+    let to_utc = |julian_day: f64| {
+        let secs = (julian_day - UNIX_EPOCH_JULIAN_DAY) * 86400.0;
+        DateTime::from_timestamp(secs as i64, 0)
+    };
+    Some((to_utc(transit - half_day)?, to_utc(transit + half_day)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sunrise_sunset;
+    use crate::compute::LatLng;
+    use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+
+    fn assert_near(actual: DateTime<Utc>, expected: DateTime<Utc>) {
+        let diff = (actual - expected).num_seconds().abs();
+        assert!(diff < 5 * 60, "{actual} is not near {expected}");
+    }
+
+    #[test]
+    fn new_york_summer_solstice() {
+        let nyc = LatLng {
+            lat: 40.7128,
+            lng: -74.0060,
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        let (sunrise, sunset) = sunrise_sunset(date, &nyc).unwrap();
+
+        // 5:25am and 8:31pm EDT
+        assert_near(
+            sunrise,
+            Utc.with_ymd_and_hms(2024, 6, 21, 9, 25, 0).unwrap(),
+        );
+        assert_near(sunset, Utc.with_ymd_and_hms(2024, 6, 22, 0, 31, 0).unwrap());
+    }
+
+    #[test]
+    fn no_sunset_during_polar_day() {
+        let svalbard = LatLng {
+            lat: 78.2232,
+            lng: 15.6267,
+        };
+        let date = NaiveDate::from_ymd_opt(2024, 6, 21).unwrap();
+        assert!(sunrise_sunset(date, &svalbard).is_none());
+    }
+}
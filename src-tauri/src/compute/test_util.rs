@@ -0,0 +1,37 @@
+use std::{path::PathBuf, sync::Mutex};
+
+use crate::{JobContext, SetProgressInfo};
+
+/// a job that is never cancelled and drops its progress
+pub struct NoProgress;
+impl JobContext for NoProgress {
+    fn set_progress(&self, _info: SetProgressInfo) {}
+    fn cancelled(&self) -> bool {
+        false
+    }
+    fn resolve_resource(&self, path: &str) -> PathBuf {
+        PathBuf::from(path)
+    }
+}
+
+/// keeps the progress and total the way the app's progress bar would see them
+#[derive(Default)]
+pub struct ProgressBar(pub Mutex<(usize, usize)>);
+impl JobContext for ProgressBar {
+    fn set_progress(&self, info: SetProgressInfo) {
+        let mut bar = self.0.lock().unwrap();
+        if let Some(progress) = info.progress {
+            bar.0 = progress;
+        }
+        bar.0 += info.progress_inc.unwrap_or(0);
+        if let Some(total) = info.total {
+            bar.1 = total;
+        }
+    }
+    fn cancelled(&self) -> bool {
+        false
+    }
+    fn resolve_resource(&self, path: &str) -> PathBuf {
+        PathBuf::from(path)
+    }
+}
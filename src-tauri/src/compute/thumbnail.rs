@@ -0,0 +1,89 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::Context;
+use image::GrayImage;
+
+use crate::ffmpeg;
+
+/// number of frames sampled from a clip when picking its representative frame
+#[cfg(feature = "app")]
+pub const DEFAULT_THUMBNAIL_CANDIDATES: usize = 5;
+/// the widest spacing of candidates that are still decoded in one ffmpeg run, past which seeking
+/// to each one is quicker than decoding everything between them
+const MAX_BATCHED_SPACING: Duration = Duration::from_secs(2);
+
+/// pick the most detailed of `candidates` frames spread evenly across a clip, as a jpg
+///
+/// the first frame of a clip is often black or blurry, so this makes for a better preview
+pub fn representative_frame(clip_path: &Path, candidates: usize) -> anyhow::Result<Vec<u8>> {
+    let length = ffmpeg::probe(clip_path).context("probe clip")?.duration;
+    let candidates = candidates.max(1);
+
+    // sample from the middle of each slice so the very first and last frames are avoided
+    let ats = (0..candidates)
+        .map(|i| length.mul_f64((i as f64 + 0.5) / candidates as f64))
+        .collect::<Vec<_>>();
+    let frames = match length / candidates as u32 <= MAX_BATCHED_SPACING {
+        true => ffmpeg::extract_frames(clip_path, &ats)
+            .context("extract candidate frames")?
+            .into_iter()
+            .map(Ok)
+            .collect(),
+        false => ats
+            .iter()
+            .map(|&at| ffmpeg::extract_frame(clip_path, at))
+            .collect::<Vec<_>>(),
+    };
+
+    let mut best: Option<(f64, Vec<u8>)> = None;
+    let mut last_err = None;
+    for (at, jpg) in ats.into_iter().zip(frames) {
+        let scored = jpg.and_then(|jpg| {
+            let img = image::load_from_memory(&jpg).context("decode candidate frame")?;
+            Ok((detail_score(&img.to_luma8()), jpg))
+        });
+        match scored {
+            Ok((score, jpg)) if best.as_ref().is_none_or(|(best, _)| score > *best) => {
+                best = Some((score, jpg))
+            }
+            Ok(_) => {}
+            Err(e) => last_err = Some(e.context(format!("candidate at {:?}", at))),
+        }
+    }
+    match (best, last_err) {
+        (Some((_, jpg)), _) => Ok(jpg),
+        (None, Some(e)) => Err(e),
+        (None, None) => anyhow::bail!("no candidate frames extracted from {:?}", clip_path),
+    }
+}
+
+/// variance of the luma, which is near zero for flat frames and grows with detail
+fn detail_score(img: &GrayImage) -> f64 {
+    let n = img.pixels().len() as f64;
+    if n == 0.0 {
+        return 0.0;
+    }
+    let mean = img.pixels().map(|p| p.0[0] as f64).sum::<f64>() / n;
+    img.pixels()
+        .map(|p| (p.0[0] as f64 - mean).powi(2))
+        .sum::<f64>()
+        / n
+}
+
+#[cfg(test)]
+mod tests {
+    use super::detail_score;
+    use image::{GrayImage, Luma};
+
+    #[test]
+    fn detailed_frames_score_higher_than_flat_ones() {
+        let black = GrayImage::new(8, 8);
+        let gray = GrayImage::from_pixel(8, 8, Luma([128]));
+        let checker =
+            GrayImage::from_fn(8, 8, |x, y| Luma([if (x + y) % 2 == 0 { 0 } else { 255 }]));
+
+        assert_eq!(detail_score(&black), 0.0);
+        assert_eq!(detail_score(&gray), 0.0);
+        assert!(detail_score(&checker) > 0.0);
+    }
+}
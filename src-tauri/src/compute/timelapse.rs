@@ -1,117 +1,1888 @@
 use std::{
+    fs,
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{BufWriter, Write},
+    ops::Range,
     path::{Path, PathBuf},
     sync::Arc,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use anyhow::Context;
+use image::{imageops::FilterType, RgbImage};
 
 use crate::{
-    compute::{timeline::Timeline, workers::WorkerPool},
-    ffmpeg, JobInfo,
+    compute::{
+        atomic::{write_atomically, AtomicFile},
+        deflicker::Deflicker,
+        glyph::LatLng,
+        metadata::{self, FrameMeta},
+        overlay::{self, FrameContext, Overlays},
+        timeline::{Timeline, TimelineClip, CLIP_TIMEZONE},
+        workers::{AdaptiveConcurrency, WorkerPool, STREAMING_TASKS_PER_THREAD},
+        TimelapseSettings,
+    },
+    ffmpeg, JobContext,
 };
 
-pub trait TimelapseEncoder: Sized {
-    fn encode_frame(&mut self, jpg_data: Vec<u8>) -> anyhow::Result<()>;
-    fn finish(self) -> anyhow::Result<()> {
+/// a frame ready to be encoded, either still compressed or already decoded
+#[derive(Clone)]
+pub enum Frame {
+    Jpg(Vec<u8>),
+    Png(Vec<u8>),
+    Rgb(RgbImage),
+}
+impl Frame {
+    /// a frame of `data` extracted as `intermediate`
+    pub fn extracted(data: Vec<u8>, intermediate: ffmpeg::Intermediate) -> Self {
+        match intermediate {
+            ffmpeg::Intermediate::Mjpeg { .. } => Self::Jpg(data),
+            ffmpeg::Intermediate::Png => Self::Png(data),
+        }
+    }
+    pub fn into_jpg(self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Jpg(jpg_data) => Ok(jpg_data),
+            frame => overlay::encode_jpeg(&frame.into_rgb()?),
+        }
+    }
+    /// the frame as a single channel gray jpg, for frames that were extracted in gray
+    ///
+    /// jpgs that already have a single component are kept as they are. ffmpeg's mjpeg encoder has
+    /// no gray pixel format, so extracted jpgs still have three components even when the
+    /// extraction filter made them gray, and have to be encoded again
+    pub fn into_gray_jpg(self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Jpg(jpg_data) if jpg_components(&jpg_data)? == 1 => Ok(jpg_data),
+            frame => overlay::encode_jpeg(&image::imageops::grayscale(&frame.into_rgb()?)),
+        }
+    }
+    pub fn into_png(self) -> anyhow::Result<Vec<u8>> {
+        match self {
+            Self::Png(png_data) => Ok(png_data),
+            frame => encode_png(&frame.into_rgb()?),
+        }
+    }
+    /// the frame in the format that an encoder fed with `intermediate` frames reads
+    pub fn into_intermediate(self, intermediate: ffmpeg::Intermediate) -> anyhow::Result<Vec<u8>> {
+        match intermediate {
+            ffmpeg::Intermediate::Mjpeg { .. } => self.into_jpg(),
+            ffmpeg::Intermediate::Png => self.into_png(),
+        }
+    }
+    pub fn into_rgb(self) -> anyhow::Result<RgbImage> {
+        match self {
+            Self::Jpg(jpg_data) => Ok(image::load_from_memory(&jpg_data)
+                .context("decode jpg frame")?
+                .to_rgb8()),
+            Self::Png(png_data) => Ok(image::load_from_memory(&png_data)
+                .context("decode png frame")?
+                .to_rgb8()),
+            Self::Rgb(img) => Ok(img),
+        }
+    }
+    /// the width and height of the frame, which only reads the header of compressed frames
+    pub fn dimensions(&self) -> anyhow::Result<(u32, u32)> {
+        let (data, format) = match self {
+            Self::Jpg(jpg_data) => (jpg_data, image::ImageFormat::Jpeg),
+            Self::Png(png_data) => (png_data, image::ImageFormat::Png),
+            Self::Rgb(img) => return Ok(img.dimensions()),
+        };
+        image::ImageReader::with_format(std::io::Cursor::new(data), format)
+            .into_dimensions()
+            .context("read frame dimensions")
+    }
+    /// scale the frame to fit within `size`, padding the rest with black
+    fn fit_to(self, (width, height): (u32, u32)) -> anyhow::Result<Self> {
+        self.map_rgb(|img| letterbox(&img, width, height))
+    }
+    /// blend `weight` of `other` over the frame, from 0 leaving it as is to 1 replacing it
+    fn crossfade(self, other: &Frame, weight: f32) -> anyhow::Result<Self> {
+        let other = other.clone().into_rgb()?;
+        self.map_rgb(|img| {
+            let (width, height) = img.dimensions();
+            match other.dimensions() == (width, height) {
+                true => blend(img, &other, weight),
+                false => blend(img, &letterbox(&other, width, height), weight),
+            }
+        })
+    }
+    /// a black frame of the same size and format
+    pub fn blacked_out(self) -> anyhow::Result<Self> {
+        self.map_rgb(|img| RgbImage::new(img.width(), img.height()))
+    }
+    /// change the decoded pixels of the frame, keeping it in the same form it was in
+    fn map_rgb(self, f: impl FnOnce(RgbImage) -> RgbImage) -> anyhow::Result<Self> {
+        self.try_map_rgb(|img| Ok(f(img)))
+    }
+    fn try_map_rgb(
+        self,
+        f: impl FnOnce(RgbImage) -> anyhow::Result<RgbImage>,
+    ) -> anyhow::Result<Self> {
+        match self {
+            Self::Jpg(_) => Ok(Self::Jpg(overlay::encode_jpeg(&f(self.into_rgb()?)?)?)),
+            Self::Png(_) => Ok(Self::Png(encode_png(&f(self.into_rgb()?)?)?)),
+            Self::Rgb(img) => Ok(Self::Rgb(f(img)?)),
+        }
+    }
+}
+
+/// a rectangle of the source frame to keep, in pixels from the top left
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+impl Crop {
+    /// fail if the crop doesn't fit in a frame of `(img_w, img_h)`
+    fn check(&self, (img_w, img_h): (u32, u32)) -> anyhow::Result<()> {
+        let fits = self.width > 0
+            && self.height > 0
+            && self
+                .x
+                .checked_add(self.width)
+                .is_some_and(|right| right <= img_w)
+            && self
+                .y
+                .checked_add(self.height)
+                .is_some_and(|bottom| bottom <= img_h);
+        if !fits {
+            anyhow::bail!(
+                "crop of {}x{} at ({}, {}) does not fit in the {}x{} frame",
+                self.width,
+                self.height,
+                self.x,
+                self.y,
+                img_w,
+                img_h
+            );
+        }
         Ok(())
     }
+    fn apply(&self, img: &RgbImage) -> anyhow::Result<RgbImage> {
+        self.check(img.dimensions())?;
+        Ok(image::imageops::crop_imm(img, self.x, self.y, self.width, self.height).to_image())
+    }
+}
+
+/// the number of color components of a jpg, read from its header without decoding it
+fn jpg_components(data: &[u8]) -> anyhow::Result<u8> {
+    use image::ImageDecoder;
+    let decoder = image::codecs::jpeg::JpegDecoder::new(std::io::Cursor::new(data))
+        .context("read jpg header")?;
+    Ok(decoder.color_type().channel_count())
+}
+
+fn encode_png(img: &RgbImage) -> anyhow::Result<Vec<u8>> {
+    let mut buf = std::io::Cursor::new(Vec::new());
+    img.write_to(&mut buf, image::ImageFormat::Png)
+        .context("encode png")?;
+    Ok(buf.into_inner())
+}
+
+/// mix `weight` of `other` into `img`, which are the same size
+fn blend(mut img: RgbImage, other: &RgbImage, weight: f32) -> RgbImage {
+    for (px, other) in img.pixels_mut().zip(other.pixels()) {
+        for (c, o) in px.0.iter_mut().zip(other.0) {
+            *c = (*c as f32 + (o as f32 - *c as f32) * weight).round() as u8;
+        }
+    }
+    img
+}
+
+/// scale `img` to fit within `width`x`height` while keeping its aspect ratio, centered on black
+fn letterbox(img: &RgbImage, width: u32, height: u32) -> RgbImage {
+    let (img_w, img_h) = img.dimensions();
+    let scale = (width as f64 / img_w as f64).min(height as f64 / img_h as f64);
+    let scaled_w = ((img_w as f64 * scale).round() as u32).clamp(1, width);
+    let scaled_h = ((img_h as f64 * scale).round() as u32).clamp(1, height);
+    let scaled = image::imageops::resize(img, scaled_w, scaled_h, FilterType::Triangle);
+
+    let mut canvas = RgbImage::new(width, height);
+    image::imageops::overlay(
+        &mut canvas,
+        &scaled,
+        ((width - scaled_w) / 2).into(),
+        ((height - scaled_h) / 2).into(),
+    );
+    canvas
+}
+
+pub trait TimelapseEncoder: Sized {
+    fn encode_frame(&mut self, frame: Frame, meta: &FrameMeta) -> anyhow::Result<()>;
+    /// finish the output, returning the number of bytes written to disk
+    fn finish(self) -> anyhow::Result<u64> {
+        Ok(0)
+    }
+    /// whether every frame has to be the same size as the first, like for a video stream
+    fn fixed_geometry(&self) -> bool {
+        false
+    }
+    /// the first frames of the output that an interrupted run already encoded, which don't have to
+    /// be extracted again
+    fn resumed_frames(&self) -> usize {
+        0
+    }
+    /// count a frame that wasn't extracted since it was already encoded, in place of encoding it
+    fn skip_frame(&mut self) {}
 }
 
 pub struct JpgTimelapseEnc {
     output_dir: PathBuf,
     frame_n: usize,
+    /// bucket frames into numbered subdirectories of this many frames, instead of one flat directory
+    frames_per_dir: Option<usize>,
+    /// write each frame's `FrameMeta` into its exif data
+    exif: bool,
+    /// write single channel jpgs instead of color ones
+    gray: bool,
+    bytes_written: u64,
 }
 impl JpgTimelapseEnc {
-    pub fn new<P: Into<PathBuf>>(output_dir: P) -> Self {
+    pub fn new<P: Into<PathBuf>>(
+        output_dir: P,
+        frames_per_dir: Option<usize>,
+        exif: bool,
+        gray: bool,
+    ) -> Self {
         Self {
             frame_n: 0,
             output_dir: output_dir.into(),
+            frames_per_dir: frames_per_dir.filter(|&n| n > 0),
+            exif,
+            gray,
+            bytes_written: 0,
+        }
+    }
+
+    /// the directory that frame `frame_n` (starting from 1) is written into
+    fn frame_dir(&self, frame_n: usize) -> PathBuf {
+        match self.frames_per_dir {
+            None => self.output_dir.clone(),
+            Some(per_dir) => self
+                .output_dir
+                .join(format!("{:04}", (frame_n - 1) / per_dir)),
         }
     }
 }
 impl TimelapseEncoder for JpgTimelapseEnc {
-    fn encode_frame(&mut self, jpg_data: Vec<u8>) -> anyhow::Result<()> {
+    fn encode_frame(&mut self, frame: Frame, meta: &FrameMeta) -> anyhow::Result<()> {
         self.frame_n += 1;
-        std::fs::write(
-            self.output_dir.join(&format!("{}.jpg", self.frame_n)),
-            jpg_data,
-        )?;
+        let dir = self.frame_dir(self.frame_n);
+        if self.frames_per_dir.is_some() {
+            std::fs::create_dir_all(&dir).with_context(|| format!("create frame dir {:?}", dir))?;
+        }
+        let mut jpg_data = match self.gray {
+            true => frame.into_gray_jpg()?,
+            false => frame.into_jpg()?,
+        };
+        if self.exif {
+            jpg_data = metadata::embed_exif(jpg_data, meta).context("embed frame exif")?;
+        }
+        self.bytes_written += jpg_data.len() as u64;
+        std::fs::write(dir.join(format!("{}.jpg", self.frame_n)), jpg_data)?;
+        Ok(())
+    }
+    fn finish(self) -> anyhow::Result<u64> {
+        Ok(self.bytes_written)
+    }
+}
+
+/// drops every frame, to measure how fast frames are extracted without the cost of encoding them
+pub struct NullTimelapseEnc {
+    info: Arc<dyn JobContext>,
+    frames: usize,
+    started: Instant,
+}
+impl NullTimelapseEnc {
+    pub fn new(info: Arc<dyn JobContext>) -> Self {
+        Self {
+            info,
+            frames: 0,
+            started: Instant::now(),
+        }
+    }
+}
+impl TimelapseEncoder for NullTimelapseEnc {
+    fn encode_frame(&mut self, _frame: Frame, _meta: &FrameMeta) -> anyhow::Result<()> {
+        self.frames += 1;
         Ok(())
     }
+    fn finish(self) -> anyhow::Result<u64> {
+        let elapsed = self.started.elapsed().as_secs_f64();
+        self.info
+            .set_progress(crate::SetProgressInfo::detail(format!(
+                "discarded {} frames in {:.02}s ({:.02} frames/sec)",
+                self.frames,
+                elapsed,
+                self.frames as f64 / elapsed
+            )));
+        Ok(0)
+    }
+}
+
+/// how an mp4 encode is split into segments, so an interrupted encode can resume where it left off
+#[derive(Debug, Clone, Copy)]
+pub struct SegmentPlan {
+    pub frames_per_segment: usize,
+    /// output frames in the whole encode, which a resumed encode has to match
+    pub total_frames: usize,
+    /// the `frames_fingerprint` of the encode, which a resumed encode has to match
+    pub frames: u64,
+}
+
+/// a hash of everything that decides what each frame of the output shows: when each frame is
+/// taken, the clips they are taken from, and how they are drawn
+pub(super) fn frames_fingerprint(
+    timeline: &Timeline,
+    timestamps: &[Duration],
+    settings: &TimelapseSettings,
+) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    timestamps.hash(&mut hasher);
+    for clip in timeline.iter() {
+        clip.path.hash(&mut hasher);
+        clip.creation_time.hash(&mut hasher);
+        clip.length.hash(&mut hasher);
+    }
+    // the settings aren't `Hash`, but their debug output is just as telling
+    format!(
+        "{:?}",
+        (
+            settings.gaps,
+            settings.hold_start,
+            settings.hold_end,
+            settings.loop_crossfade,
+            settings.extract_options(),
+            settings.crop,
+            &settings.overlays,
+            settings.deflicker,
+        )
+    )
+    .hash(&mut hasher);
+    hasher.finish()
+}
+
+/// what the segments left in a directory were encoded with, which decides whether they can be
+/// resumed from
+#[derive(Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SegmentManifest {
+    frames_per_segment: usize,
+    total_frames: usize,
+    /// the ffmpeg command each segment is encoded with, since segments are joined without
+    /// encoding them again and so have to match exactly
+    command_line: String,
+    /// the `frames_fingerprint` of the frames that are encoded
+    frames: u64,
+}
+
+/// an encode split into segments, which are joined into the output once every frame is encoded
+struct Segments {
+    dir: PathBuf,
+    frames_per_segment: usize,
+    /// finished segments, including any left by an earlier run
+    done: Vec<PathBuf>,
+    /// frames encoded into the segment that is being written
+    frames_in_segment: usize,
+    /// frames that an earlier run already encoded into `done`, which are dropped instead
+    resumed: usize,
+    /// frames passed to the encoder so far, whether they were encoded or dropped
+    next_frame: usize,
+}
+impl Segments {
+    const MANIFEST_FILE: &str = "segments.json";
+    /// what segments are named in the manifest's command line, which doesn't depend on where the
+    /// output is
+    const SEGMENT_NAME: &str = "segment.mp4";
+
+    /// pick up the finished segments in `dir` if they were encoded with `manifest`, or start over
+    fn open(dir: PathBuf, manifest: SegmentManifest) -> anyhow::Result<Self> {
+        let manifest_path = dir.join(Self::MANIFEST_FILE);
+        let existing = fs::read(&manifest_path)
+            .ok()
+            .and_then(|json| serde_json::from_slice::<SegmentManifest>(&json).ok());
+        if existing.as_ref() != Some(&manifest) {
+            if dir.exists() {
+                fs::remove_dir_all(&dir).with_context(|| format!("remove stale {:?}", dir))?;
+            }
+            fs::create_dir_all(&dir).with_context(|| format!("create {:?}", dir))?;
+            write_atomically(&manifest_path, serde_json::to_vec(&manifest)?)
+                .with_context(|| format!("write {:?}", manifest_path))?;
+        }
+
+        // a segment is only renamed into place once it's finished, so the first missing one is
+        // where the earlier run stopped
+        let mut segments = Self {
+            dir,
+            frames_per_segment: manifest.frames_per_segment,
+            done: Vec::new(),
+            frames_in_segment: 0,
+            resumed: 0,
+            next_frame: 0,
+        };
+        while segments.next_path().is_file() {
+            segments.done.push(segments.next_path());
+        }
+        segments.resumed = segments.done.len() * segments.frames_per_segment;
+        Ok(segments)
+    }
+    /// where the segment after the finished ones is written
+    fn next_path(&self) -> PathBuf {
+        self.dir.join(format!("segment_{:05}.mp4", self.done.len()))
+    }
+}
+
+/// how the video output of a timelapse is encoded
+pub struct Mp4Options {
+    pub video: ffmpeg::VideoOptions,
+    /// pipe frames to ffmpeg as raw rgb24 pixels instead of `intermediate` images
+    pub raw: bool,
+    pub intermediate: ffmpeg::Intermediate,
+    pub chapters: Vec<ffmpeg::Chapter>,
+    pub segments: Option<SegmentPlan>,
+}
+impl Mp4Options {
+    /// how each segment is encoded, which is always an mp4 until they're joined into the output
+    fn segment_video(&self) -> ffmpeg::VideoOptions {
+        ffmpeg::VideoOptions {
+            container: ffmpeg::Container::Mp4,
+            ..self.video.clone()
+        }
+    }
 }
 
 pub struct Mp4TimelapseEnc {
-    enc: ffmpeg::Mp4FrameEncoder,
+    /// for raw frames or segments, this is only started once the first frame is encoded
+    enc: Option<ffmpeg::Mp4FrameEncoder>,
+    opts: Mp4Options,
+    /// size of the raw frames, which every later frame is resized to
+    raw_size: Option<(u32, u32)>,
+    output: PathBuf,
+    metadata: Vec<(&'static str, String)>,
+    segments: Option<Segments>,
+    info: Arc<dyn JobContext>,
+    /// whether the ffmpeg command has been logged, which is only done for the first encoder
+    logged_command: bool,
 }
 impl Mp4TimelapseEnc {
-    pub fn new<P: AsRef<Path>>(output: P, fps: u32) -> anyhow::Result<Self> {
-        Ok(Self {
-            enc: ffmpeg::Mp4FrameEncoder::new(output.as_ref(), fps)?,
-        })
+    pub fn new<P: AsRef<Path>>(
+        info: Arc<dyn JobContext>,
+        output: P,
+        location: Option<&LatLng>,
+        opts: Mp4Options,
+    ) -> anyhow::Result<Self> {
+        let output = output.as_ref().to_path_buf();
+        let segments = match opts.segments {
+            Some(plan) => {
+                if plan.frames_per_segment == 0 {
+                    anyhow::bail!("segments must have at least one frame");
+                }
+                if opts.video.rate != ffmpeg::RateControl::Crf {
+                    anyhow::bail!(
+                        "a target size needs every frame for its two passes, so the video can't be encoded in segments"
+                    );
+                }
+                // raw frames are only sized once the first is extracted, but their size follows
+                // from the frames, which the fingerprint already covers
+                let input = match opts.raw {
+                    true => ffmpeg::FrameInput::Rgb24 {
+                        width: 0,
+                        height: 0,
+                    },
+                    false => opts.intermediate.frame_input(),
+                };
+                let command_line = ffmpeg::Mp4FrameEncoder::command_line_for(
+                    Path::new(Segments::SEGMENT_NAME),
+                    input,
+                    &opts.segment_video(),
+                )?;
+                let manifest = SegmentManifest {
+                    frames_per_segment: plan.frames_per_segment,
+                    total_frames: plan.total_frames,
+                    command_line,
+                    frames: plan.frames,
+                };
+                Some(Segments::open(output.with_extension("segments"), manifest)?)
+            }
+            None => None,
+        };
+        let metadata = location
+            .map(|loc| vec![("location", loc.to_iso6709())])
+            .unwrap_or_default();
+        let mut enc = Self {
+            enc: None,
+            opts,
+            raw_size: None,
+            output,
+            metadata,
+            segments,
+            info,
+            logged_command: false,
+        };
+        if !enc.opts.raw && enc.segments.is_none() {
+            enc.start()?;
+        }
+        Ok(enc)
+    }
+
+    /// start encoding into the output, or into the next segment when split into them
+    fn start(&mut self) -> anyhow::Result<()> {
+        let input = match self.raw_size {
+            Some((width, height)) => ffmpeg::FrameInput::Rgb24 { width, height },
+            None => self.opts.intermediate.frame_input(),
+        };
+        let enc = match &self.segments {
+            // the output's metadata and chapters are only written once the segments are joined
+            Some(segments) => ffmpeg::Mp4FrameEncoder::new(
+                &segments.next_path(),
+                input,
+                &[],
+                &[],
+                &self.opts.segment_video(),
+            )?,
+            None => ffmpeg::Mp4FrameEncoder::new(
+                &self.output,
+                input,
+                &self.metadata,
+                &self.opts.chapters,
+                &self.opts.video,
+            )?,
+        };
+        if !self.logged_command {
+            self.info
+                .set_progress(crate::SetProgressInfo::detail(format!(
+                    "encoding video with {}",
+                    enc.command_line()
+                )));
+            self.logged_command = true;
+        }
+        self.enc = Some(enc);
+        Ok(())
+    }
+
+    /// finish the segment being written once it has all of its frames
+    fn finish_full_segment(&mut self) -> anyhow::Result<()> {
+        let Some(segments) = &mut self.segments else {
+            return Ok(());
+        };
+        segments.frames_in_segment += 1;
+        if segments.frames_in_segment == segments.frames_per_segment {
+            if let Some(mut enc) = self.enc.take() {
+                enc.finish().context("finish segment")?;
+            }
+            segments.done.push(segments.next_path());
+            segments.frames_in_segment = 0;
+        }
+        Ok(())
     }
 }
 impl TimelapseEncoder for Mp4TimelapseEnc {
-    fn encode_frame(&mut self, jpg_data: Vec<u8>) -> anyhow::Result<()> {
-        self.enc.encode_frame(&jpg_data)
+    fn encode_frame(&mut self, frame: Frame, _meta: &FrameMeta) -> anyhow::Result<()> {
+        if let Some(segments) = &mut self.segments {
+            segments.next_frame += 1;
+            if segments.next_frame <= segments.resumed {
+                return Ok(());
+            }
+        }
+
+        let data = match self.opts.raw {
+            false => frame.into_intermediate(self.opts.intermediate)?,
+            true => {
+                let img = frame.into_rgb()?;
+                let &mut size = self.raw_size.get_or_insert(img.dimensions());
+                if img.dimensions() != size {
+                    // raw frames have no size of their own, so a mismatch would garble the rest of
+                    // the video
+                    anyhow::bail!(
+                        "raw frame is {:?} but the encoder was started with {:?}",
+                        img.dimensions(),
+                        size
+                    );
+                }
+                img.into_raw()
+            }
+        };
+        if self.enc.is_none() {
+            self.start().context("start frame encoder")?;
+        }
+        let enc = self.enc.as_mut().expect("encoder started above");
+        enc.encode_frame(&data)?;
+        self.finish_full_segment()
+    }
+    fn finish(mut self) -> anyhow::Result<u64> {
+        if let Some(enc) = &mut self.enc {
+            enc.finish()?;
+        }
+        match self.segments.take() {
+            Some(mut segments) => {
+                if self.enc.is_some() {
+                    segments.done.push(segments.next_path());
+                }
+                if segments.done.is_empty() {
+                    anyhow::bail!("no frames were encoded");
+                }
+                ffmpeg::concat_segments(
+                    &segments.done,
+                    &self.output,
+                    self.opts.video.container,
+                    &self.metadata,
+                    &self.opts.chapters,
+                )
+                .context("join segments")?;
+                // the output is complete, so a leftover segment only wastes space
+                let _ = fs::remove_dir_all(&segments.dir);
+            }
+            None if self.enc.is_none() => anyhow::bail!("no frames were encoded"),
+            None => {}
+        }
+        file_size(&self.output)
+    }
+    fn fixed_geometry(&self) -> bool {
+        true
+    }
+    fn resumed_frames(&self) -> usize {
+        self.segments
+            .as_ref()
+            .map_or(0, |segments| segments.resumed)
+    }
+    fn skip_frame(&mut self) {
+        if let Some(segments) = &mut self.segments {
+            segments.next_frame += 1;
+        }
+    }
+}
+
+/// the most frames an animated image output can have, since they compress far worse than video
+pub const MAX_ANIMATED_FRAMES: usize = 900;
+
+/// writes the timelapse as a single looping animated image, like `output.webp`
+pub struct AnimatedTimelapseEnc {
+    enc: ffmpeg::Mp4FrameEncoder,
+    output: PathBuf,
+}
+impl AnimatedTimelapseEnc {
+    /// `frame_count` is how many frames will be encoded, including held frames
+    pub fn new<P: AsRef<Path>>(
+        output: P,
+        fps: u32,
+        format: ffmpeg::AnimatedFormat,
+        frame_count: usize,
+    ) -> anyhow::Result<Self> {
+        if frame_count > MAX_ANIMATED_FRAMES {
+            anyhow::bail!(
+                "{} output would have {} frames, but is limited to {}",
+                format.extension(),
+                frame_count,
+                MAX_ANIMATED_FRAMES
+            );
+        }
+        let output = output.as_ref().to_path_buf();
+        let enc =
+            ffmpeg::Mp4FrameEncoder::animated(&output, fps, format, ffmpeg::FrameInput::Mjpeg)?;
+        Ok(Self { enc, output })
+    }
+}
+impl TimelapseEncoder for AnimatedTimelapseEnc {
+    fn encode_frame(&mut self, frame: Frame, _meta: &FrameMeta) -> anyhow::Result<()> {
+        self.enc.encode_frame(&frame.into_jpg()?)
+    }
+    fn finish(mut self) -> anyhow::Result<u64> {
+        self.enc.finish()?;
+        file_size(&self.output)
+    }
+    fn fixed_geometry(&self) -> bool {
+        true
+    }
+}
+
+fn file_size(path: &Path) -> anyhow::Result<u64> {
+    let metadata = fs::metadata(path).with_context(|| format!("read size of {:?}", path))?;
+    Ok(metadata.len())
+}
+
+/// the number of frames in the timelapse, before any are skipped
+///
+/// with a sample rate, the frame count follows from the footage and the length follows from `fps`.
+/// otherwise, the frame count is whatever fills `length` at `fps`
+fn num_frames(timeline_len: Duration, settings: &TimelapseSettings) -> u32 {
+    match settings.sample_rate {
+        Some(rate) => (timeline_len.as_secs_f64() * rate) as u32,
+        None => (settings.length.as_secs_f64() * settings.fps as f64) as u32,
     }
-    fn finish(mut self) -> anyhow::Result<()> {
-        self.enc.finish()
+}
+
+/// the timeline offsets of each output frame, excluding the first `skip` frames
+///
+/// the offsets are evenly spaced over `[0, timeline_len)`, so the last frame never lands on the end of
+/// the timeline and the number of offsets is exactly the number of frames that will be encoded
+fn frame_timestamps(timeline_len: Duration, num_frames: u32, skip: Option<u32>) -> Vec<Duration> {
+    if num_frames == 0 {
+        return Vec::new();
     }
+    let step = timeline_len / num_frames;
+    (skip.unwrap_or(0)..num_frames)
+        .map(|frame_n| frame_n * step)
+        .collect()
+}
+
+/// like `frame_timestamps`, but spaced evenly over the real-world time from when the first clip
+/// started recording to when the last one stopped, flagging the frames that fall in a gap
+fn realtime_frame_timestamps(
+    timeline: &Timeline,
+    num_frames: u32,
+    skip: Option<u32>,
+) -> Vec<(Duration, bool)> {
+    let Some((start, end)) = timeline.span().filter(|_| num_frames > 0) else {
+        return Vec::new();
+    };
+    let step = (end - start) / num_frames as i32;
+    (skip.unwrap_or(0)..num_frames)
+        .map(|frame_n| timeline.offset_at_time(start + step * frame_n as i32))
+        .collect()
 }
 
+/// thin out `timestamps` so no more than `cap` of them fall in the same clip, evenly spaced within it
+///
+/// timestamps that `clip_index_at` puts in no clip, like frames in the gaps between clips, are all
+/// kept and don't count towards any clip's cap
+///
+/// returns the kept timestamps and the number of clips that were capped
+fn cap_frames_per_clip<T: Copy>(
+    timestamps: Vec<T>,
+    clip_index_at: impl Fn(T) -> Option<usize>,
+    cap: usize,
+) -> (Vec<T>, usize) {
+    let mut kept = Vec::with_capacity(timestamps.len());
+    let mut capped_clips = 0;
+    // timestamps are sorted, so each clip's timestamps are contiguous
+    for group in timestamps.chunk_by(|&a, &b| clip_index_at(a) == clip_index_at(b)) {
+        if group.len() > cap && clip_index_at(group[0]).is_some() {
+            capped_clips += 1;
+            kept.extend((0..cap).map(|i| group[i * group.len() / cap]));
+        } else {
+            kept.extend_from_slice(group);
+        }
+    }
+    (kept, capped_clips)
+}
+
+/// the timeline offset of the real-time picture-in-picture for each of `timestamps`
+///
+/// it starts at the first frame sampled from each clip, and plays one frame of footage for every
+/// frame of the timelapse until the timelapse moves on to the next clip
+fn realtime_offsets(
+    timestamps: &[Duration],
+    clip_index_at: impl Fn(Duration) -> usize,
+    fps: u32,
+) -> Vec<Duration> {
+    let frame_len = Duration::from_secs(1) / fps.max(1);
+    timestamps
+        .chunk_by(|&a, &b| clip_index_at(a) == clip_index_at(b))
+        .flat_map(|group| (0..group.len()).map(move |i| group[0] + frame_len * i as u32))
+        .collect()
+}
+
+/// what fills the frames of a timelapse that fall between two clips
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum GapPolicy {
+    /// play the clips back to back, so no frame falls between them
+    #[default]
+    Skip,
+    /// space frames over real-world time, repeating the last frame before a gap through it
+    HoldPrevious,
+    /// space frames over real-world time, with black frames through a gap
+    Black,
+}
+
+/// decode the frames that a clip contributes in runs, each with one ffmpeg, instead of seeking to
+/// every frame on its own
+///
+/// this is off unless asked for, since it hasn't been measured against seeking on real footage.
+/// the benchmark times both, so it can be turned on where it is faster
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BatchExtraction {
+    /// only clips contributing at least this many frames are decoded in runs
+    pub min_frames: usize,
+    /// the most frames in one run, up to `MAX_FRAMES_PER_RUN`, so that the frames of a long clip
+    /// are still spread over the pool
+    pub frames_per_run: usize,
+}
+
+/// the most frames in one run, which keeps the frames held by the runs in flight to what single
+/// frames would hold at the pool's `streaming_depth`
+pub(super) const MAX_FRAMES_PER_RUN: usize = STREAMING_TASKS_PER_THREAD;
+
+/// split the frames, which are in the clips at `clips`, into the runs that are each extracted by
+/// one task
+///
+/// every frame is a run of its own, unless `batch` groups the consecutive frames of a clip. frames
+/// whose clip is `None` are never grouped
+pub(super) fn extraction_runs(
+    clips: &[Option<usize>],
+    batch: Option<BatchExtraction>,
+) -> Vec<Range<usize>> {
+    let mut runs = Vec::new();
+    let mut start = 0;
+    while start < clips.len() {
+        let in_clip = clips[start..]
+            .iter()
+            .take_while(|&&clip| clip.is_some() && clip == clips[start])
+            .count();
+        let end = start + in_clip.max(1);
+        match batch.filter(|batch| end - start >= batch.min_frames.max(2)) {
+            Some(batch) => {
+                let per_run = batch.frames_per_run.clamp(1, MAX_FRAMES_PER_RUN);
+                runs.extend(
+                    (start..end)
+                        .step_by(per_run)
+                        .map(|i| i..(i + per_run).min(end)),
+                );
+            }
+            None => runs.extend((start..end).map(|i| i..i + 1)),
+        }
+        start = end;
+    }
+    runs
+}
+
+/// the timeline offsets of every frame in the timelapse described by `settings`
+pub(super) fn timelapse_timestamps(
+    info: &dyn JobContext,
+    timeline: &Timeline,
+    settings: &TimelapseSettings,
+) -> Vec<Duration> {
+    timelapse_frames(info, timeline, settings).0
+}
+
+/// like `timelapse_timestamps`, along with whether each frame is drawn black for being in a gap
+pub(super) fn timelapse_frames(
+    info: &dyn JobContext,
+    timeline: &Timeline,
+    settings: &TimelapseSettings,
+) -> (Vec<Duration>, Vec<bool>) {
+    if let Some(rate) = settings.sample_rate {
+        info.set_progress(crate::SetProgressInfo::detail(format!(
+            "sampling {} frames per second of footage, played back at {} fps ({:.01}x speed)",
+            rate,
+            settings.fps,
+            settings.fps as f64 / rate
+        )));
+    }
+    let num_frames = num_frames(timeline.len(), settings);
+    let frames = match settings.gaps {
+        GapPolicy::Skip => frame_timestamps(timeline.len(), num_frames, settings.skip)
+            .into_iter()
+            .map(|ts| (ts, false))
+            .collect(),
+        GapPolicy::HoldPrevious | GapPolicy::Black => {
+            let frames = realtime_frame_timestamps(timeline, num_frames, settings.skip);
+            let in_gaps = frames.iter().filter(|&&(_, in_gap)| in_gap).count();
+            info.set_progress(crate::SetProgressInfo::detail(format!(
+                "spacing frames over real-world time, which puts {} of {} frames between clips",
+                in_gaps,
+                frames.len()
+            )));
+            frames
+        }
+    };
+    let frames = match settings.max_frames_per_clip {
+        None => frames,
+        Some(cap) => {
+            let total = frames.len();
+            let (frames, capped_clips) = cap_frames_per_clip(
+                frames,
+                |(ts, in_gap)| (!in_gap).then(|| timeline.clip_index_at(ts)),
+                cap.max(1),
+            );
+            if capped_clips > 0 {
+                info.set_progress(crate::SetProgressInfo::detail(format!(
+                    "capped {} clips to {} frames each, dropping {} of {} frames",
+                    capped_clips,
+                    cap.max(1),
+                    total - frames.len(),
+                    total
+                )));
+            }
+            frames
+        }
+    };
+    let black = settings.gaps == GapPolicy::Black;
+    frames
+        .into_iter()
+        .map(|(ts, in_gap)| (ts, black && in_gap))
+        .unzip()
+}
+
+/// when and where the frame at `ts` in the timeline was recorded
+fn frame_meta(timeline: &Timeline, ts: Duration, clip_locations: Option<&[LatLng]>) -> FrameMeta {
+    let (clip_ts, clip) = timeline.get_at(ts);
+    FrameMeta {
+        taken_at: chrono::TimeDelta::from_std(ts - clip_ts)
+            .ok()
+            .map(|offset| (clip.creation_time + offset).with_timezone(&CLIP_TIMEZONE)),
+        location: clip_locations
+            .and_then(|locs| locs.get(timeline.clip_index_at(ts)))
+            .cloned(),
+    }
+}
+
+/// what the chapters of the video output are split by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChapterMode {
+    /// a chapter for each source clip
+    Clip,
+    /// a chapter for each real-world day, in the clip timezone
+    Day,
+}
+
+/// the number of output frames that make up a hold of `hold`
+fn hold_frames(hold: Duration, fps: u32) -> usize {
+    (hold.as_secs_f64() * fps as f64).round() as usize
+}
+/// the number of frames at each end of `num_frames` that are crossfaded together to loop, which
+/// is at most half of them
+fn loop_crossfade_frames(num_frames: usize, settings: &TimelapseSettings) -> usize {
+    hold_frames(settings.loop_crossfade, settings.fps).min(num_frames / 2)
+}
+/// the number of frames in the output made from `num_frames` timestamps, including held frames
+/// and without the first frames that are crossfaded into the end
+pub(super) fn output_frame_count(num_frames: usize, settings: &TimelapseSettings) -> usize {
+    num_frames
+        + hold_frames(settings.hold_start, settings.fps)
+        + hold_frames(settings.hold_end, settings.fps)
+        - loop_crossfade_frames(num_frames, settings)
+}
+
+/// chapters for the video made from `timestamps`, split whenever the title of a frame changes
+///
+/// frames that fail to extract are not known ahead of time, so chapters drift slightly when any do
+pub(super) fn chapters(
+    timeline: &Timeline,
+    timestamps: &[Duration],
+    settings: &TimelapseSettings,
+    mode: ChapterMode,
+) -> Vec<ffmpeg::Chapter> {
+    let crossfaded = loop_crossfade_frames(timestamps.len(), settings);
+    let titles = timestamps.iter().skip(crossfaded).map(|&ts| match mode {
+        ChapterMode::Clip => timeline
+            .get_at(ts)
+            .1
+            .path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default(),
+        ChapterMode::Day => frame_meta(timeline, ts, None)
+            .taken_at
+            .map(|taken_at| taken_at.format("%Y-%m-%d").to_string())
+            .unwrap_or_default(),
+    });
+    group_chapters(
+        titles,
+        settings.fps,
+        hold_frames(settings.hold_start, settings.fps),
+        hold_frames(settings.hold_end, settings.fps),
+    )
+}
+
+/// one chapter for each run of equal `titles`, with the first and last covering the held frames
+fn group_chapters(
+    titles: impl Iterator<Item = String>,
+    fps: u32,
+    hold_start_frames: usize,
+    hold_end_frames: usize,
+) -> Vec<ffmpeg::Chapter> {
+    let frame_time = |frame: usize| Duration::from_secs_f64(frame as f64 / fps.max(1) as f64);
+    let mut chapters: Vec<ffmpeg::Chapter> = Vec::new();
+    let mut frames = hold_start_frames;
+    for title in titles {
+        frames += 1;
+        match chapters.last_mut() {
+            Some(last) if last.title == title => last.end = frame_time(frames),
+            _ => chapters.push(ffmpeg::Chapter {
+                // the held first frame belongs to the first chapter
+                start: match chapters.last() {
+                    Some(last) => last.end,
+                    None => Duration::ZERO,
+                },
+                end: frame_time(frames),
+                title,
+            }),
+        }
+    }
+    if let Some(last) = chapters.last_mut() {
+        last.end = frame_time(frames + hold_end_frames);
+    }
+    chapters
+}
+
+/// extract the frame at `ts` in the timeline, crop it, and draw the overlays onto it
+///
+/// `raw` frames are left decoded, so the encoder does not have to decode them again.
+/// `pip_at` is the timeline offset of the real-time frame for the picture-in-picture, which is
+/// always taken from the same clip as the frame itself
+pub(super) fn extract_timelapse_frame(
+    timeline: &Timeline,
+    overlays: &Overlays,
+    ts: Duration,
+    raw: bool,
+    extract: ffmpeg::ExtractOptions,
+    crop: Option<Crop>,
+    pip_at: Option<Duration>,
+) -> anyhow::Result<Frame> {
+    let (clip_ts, clip) = timeline.get_at(ts);
+    let ts_in_clip = ts - clip_ts;
+    let data = extract_from_clip(clip, ts_in_clip, extract).with_context(|| {
+        format!(
+            "extract frame from {} @ {:.02}s",
+            clip.path.to_string_lossy(),
+            ts_in_clip.as_secs_f64()
+        )
+    })?;
+    process_timelapse_frame(timeline, overlays, ts, data, raw, extract, crop, pip_at)
+}
+
+/// like `extract_timelapse_frame` for each of `frames`, which are all in the same clip, but
+/// decoding them with one ffmpeg
+///
+/// if that fails, each frame is extracted on its own so one bad frame doesn't lose the whole run
+pub(super) fn extract_timelapse_run(
+    info: &dyn JobContext,
+    timeline: &Timeline,
+    overlays: &Overlays,
+    frames: &[(Duration, Option<Duration>)],
+    raw: bool,
+    extract: ffmpeg::ExtractOptions,
+    crop: Option<Crop>,
+) -> Vec<anyhow::Result<Frame>> {
+    let Some(&(first, _)) = frames.first() else {
+        return Vec::new();
+    };
+    let (clip_ts, clip) = timeline.get_at(first);
+    let in_clip = frames
+        .iter()
+        .map(|&(ts, _)| ts - clip_ts)
+        .collect::<Vec<_>>();
+    match ffmpeg::extract_frames_as(&clip.path, &in_clip, extract) {
+        Ok(data) => data
+            .into_iter()
+            .zip(frames)
+            .map(|(data, &(ts, pip_at))| {
+                process_timelapse_frame(timeline, overlays, ts, data, raw, extract, crop, pip_at)
+            })
+            .collect(),
+        Err(e) => {
+            info.set_progress(crate::SetProgressInfo::warn(format!(
+                "could not decode {} frames of {} in one run, extracting each on its own\n{e:#}",
+                frames.len(),
+                clip.path.to_string_lossy()
+            )));
+            frames
+                .iter()
+                .map(|&(ts, pip_at)| {
+                    extract_timelapse_frame(timeline, overlays, ts, raw, extract, crop, pip_at)
+                })
+                .collect()
+        }
+    }
+}
+
+/// extract the frame `at` into `clip`, counting frames instead where its timestamps are broken
+fn extract_from_clip(
+    clip: &TimelineClip,
+    at: Duration,
+    opts: ffmpeg::ExtractOptions,
+) -> anyhow::Result<ffmpeg::ExtractedFrame> {
+    match clip.unreliable_timestamps {
+        true => ffmpeg::extract_frame_reindexed(&clip.path, at, opts),
+        false => ffmpeg::extract_frame_as(&clip.path, at, opts),
+    }
+}
+
+/// crop and draw the overlays onto `data`, the frame extracted at `ts`
+#[allow(clippy::too_many_arguments)]
+fn process_timelapse_frame(
+    timeline: &Timeline,
+    overlays: &Overlays,
+    ts: Duration,
+    data: ffmpeg::ExtractedFrame,
+    raw: bool,
+    extract: ffmpeg::ExtractOptions,
+    crop: Option<Crop>,
+    pip_at: Option<Duration>,
+) -> anyhow::Result<Frame> {
+    let (clip_ts, clip) = timeline.get_at(ts);
+    // frames past the end of the clip are its last frame, so they're labelled with where that is
+    let ts_in_clip = data.last_frame_at.unwrap_or(ts - clip_ts);
+    let extracted = Frame::extracted(data.data, extract.intermediate);
+    let pip = match pip_at.filter(|_| overlays.picture_in_picture.is_some()) {
+        Some(pip_at) => {
+            let at = pip_at.saturating_sub(clip_ts).min(clip.length);
+            let opts = ffmpeg::ExtractOptions {
+                intermediate: ffmpeg::Intermediate::default(),
+                ..extract
+            };
+            let jpg =
+                extract_from_clip(clip, at, opts).context("extract picture-in-picture frame")?;
+            let img =
+                image::load_from_memory(&jpg.data).context("decode picture-in-picture frame")?;
+            Some(img.to_rgb8())
+        }
+        None => None,
+    };
+    let frame = FrameContext {
+        ts,
+        timeline_len: timeline.len(),
+        pip,
+        source_name: clip
+            .path
+            .file_name()
+            .unwrap_or(clip.path.as_os_str())
+            .to_string_lossy()
+            .into(),
+        ts_in_clip,
+    };
+    let process = |img: RgbImage| {
+        let mut img = match crop {
+            Some(crop) => crop.apply(&img)?,
+            None => img,
+        };
+        overlays.draw(&mut img, &frame);
+        anyhow::Ok(img)
+    };
+    if raw {
+        Ok(Frame::Rgb(process(extracted.into_rgb()?)?))
+    } else if crop.is_none() && overlays.is_empty() {
+        Ok(extracted)
+    } else {
+        extracted
+            .try_map_rgb(process)
+            .context("crop and draw frame overlays")
+    }
+}
+
+/// a csv mapping every output frame to when and where it was recorded, for markers in video editors
+struct FramesCsv<W: Write> {
+    out: W,
+    fps: u32,
+    next_frame: usize,
+    bytes_written: u64,
+}
+impl FramesCsv<BufWriter<AtomicFile>> {
+    fn create(path: &Path, fps: u32) -> anyhow::Result<Self> {
+        Self::new(BufWriter::new(AtomicFile::create(path)?), fps)
+    }
+    /// move the finished csv into place, returning its size in bytes
+    fn commit(self) -> anyhow::Result<u64> {
+        let (out, bytes_written) = self.finish()?;
+        out.into_inner()
+            .map_err(|e| e.into_error())
+            .context("flush frames csv")?
+            .commit()?;
+        Ok(bytes_written)
+    }
+}
+impl<W: Write> FramesCsv<W> {
+    fn new(out: W, fps: u32) -> anyhow::Result<Self> {
+        let mut csv = Self {
+            out,
+            fps: fps.max(1),
+            next_frame: 0,
+            bytes_written: 0,
+        };
+        csv.write_line("frame_index,output_time_secs,real_world_timestamp,source_path".into())
+            .context("write frames csv header")?;
+        Ok(csv)
+    }
+    fn write_line(&mut self, mut line: String) -> std::io::Result<()> {
+        line.push('\n');
+        self.out.write_all(line.as_bytes())?;
+        self.bytes_written += line.len() as u64;
+        Ok(())
+    }
+    /// add `count` output frames that all show the same source frame
+    fn write_frames(
+        &mut self,
+        meta: &FrameMeta,
+        source: &Path,
+        count: usize,
+    ) -> anyhow::Result<()> {
+        let taken_at = meta
+            .taken_at
+            .map(|taken_at| taken_at.to_rfc3339())
+            .unwrap_or_default();
+        let source = format!("\"{}\"", source.display().to_string().replace('"', "\"\""));
+        for _ in 0..count {
+            let row = format!(
+                "{},{:.3},{},{}",
+                self.next_frame,
+                self.next_frame as f64 / self.fps as f64,
+                taken_at,
+                source
+            );
+            self.write_line(row).context("write frames csv row")?;
+            self.next_frame += 1;
+        }
+        Ok(())
+    }
+    /// flush the csv, returning the writer and the csv's size in bytes
+    fn finish(mut self) -> anyhow::Result<(W, u64)> {
+        self.out.flush().context("flush frames csv")?;
+        Ok((self.out, self.bytes_written))
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn timelapse<E: TimelapseEncoder>(
-    info: Arc<JobInfo>,
+    info: Arc<dyn JobContext>,
     timeline: Arc<Timeline>,
     pool: &WorkerPool,
     mut enc: E,
-    len: Duration,
-    fps: u32,
-    skip: Option<u32>,
+    settings: &TimelapseSettings,
+    timestamps: Vec<Duration>,
+    black: Vec<bool>,
+    output_dir: &Path,
 ) -> anyhow::Result<()> {
-    let num_frames = (len.as_secs_f64() * fps as f64) as u32;
-    let timestamps =
-        (skip.unwrap_or(0)..=num_frames).map(|frame_n| frame_n * (timeline.len() / num_frames));
-    let num_frames = num_frames - skip.unwrap_or(0);
+    let num_frames = timestamps.len();
+    let (hold_start_frames, hold_end_frames) = (
+        hold_frames(settings.hold_start, settings.fps),
+        hold_frames(settings.hold_end, settings.fps),
+    );
+
+    // the first frames are blended into the last ones, so the end of the output leads seamlessly
+    // back into its start when it repeats
+    let crossfade_frames = loop_crossfade_frames(num_frames, settings);
+    if crossfade_frames > 0 && hold_start_frames + hold_end_frames > 0 {
+        anyhow::bail!("holding the first or last frame would break the crossfade of a loop");
+    }
 
+    // the crossfaded first frames still count as progress when they are extracted
     info.set_progress(crate::SetProgressInfo {
         progress: Some(0),
-        total: Some(num_frames as usize),
+        total: Some(output_frame_count(num_frames, settings) + crossfade_frames),
         ..Default::default()
     });
 
-    let jobs = pool.run_ordered_channel(timestamps.map(|ts| {
+    let overlays = Arc::new(settings.overlays.clone());
+    let raw = settings.raw_frames;
+    let extract = settings.extract_options();
+    let crop = settings.crop;
+    // a crop that doesn't fit would fail every frame, so it's checked against the first clip once
+    // instead of skipping frames until the end
+    if let Some(crop) = crop {
+        let (_, first_clip) = timeline.get_at(Duration::ZERO);
+        let data = extract_from_clip(first_clip, Duration::ZERO, extract)
+            .with_context(|| format!("extract frame to check crop from {:?}", first_clip.path))?;
+        let size = Frame::extracted(data.data, extract.intermediate).dimensions()?;
+        crop.check(size)
+            .context("check crop against the first clip")?;
+    }
+    let pip_offsets = match settings.overlays.picture_in_picture {
+        Some(_) => realtime_offsets(&timestamps, |ts| timeline.clip_index_at(ts), settings.fps)
+            .into_iter()
+            .map(Some)
+            .collect(),
+        None => vec![None; timestamps.len()],
+    };
+    // an interrupted run already encoded the first frames, so they aren't extracted again unless
+    // they're still needed to crossfade into or hold the end
+    let resumed = enc.resumed_frames();
+    let already_encoded = |i: usize| {
+        i >= crossfade_frames
+            && i + 1 < num_frames
+            && hold_start_frames + i - crossfade_frames < resumed
+    };
+    if resumed > 0 {
+        info.set_progress(crate::SetProgressInfo::detail(format!(
+            "resuming after the {} frames encoded by an earlier run",
+            resumed
+        )));
+    }
+    // clips with broken timestamps can't be decoded in runs, since runs pick frames by timestamp
+    let clips = timestamps
+        .iter()
+        .map(|&ts| {
+            let clip = timeline.clip_index_at(ts);
+            (!timeline.get_at(ts).1.unreliable_timestamps).then_some(clip)
+        })
+        .collect::<Vec<_>>();
+    let runs = extraction_runs(&clips, settings.batch_extraction);
+    // frames are only extracted a little ahead of the encoder, so a slow encoder doesn't pile up
+    // extracted frames in memory. runs hold several frames each, so fewer of them are in flight
+    let longest_run = runs.iter().map(|run| run.len()).max().unwrap_or(1);
+    let in_flight = (pool.streaming_depth() / longest_run).max(pool.threads());
+    let tasks = runs.into_iter().map(|run| {
+        let frames = run
+            .map(|i| (timestamps[i], pip_offsets[i], black[i], already_encoded(i)))
+            .collect::<Vec<_>>();
         let info = Arc::clone(&info);
         let timeline = Arc::clone(&timeline);
+        let overlays = Arc::clone(&overlays);
         move || {
-            info.cancel_result()?;
-            let (clip_ts, clip) = timeline.get_at(ts);
-            let ts_in_clip = ts - clip_ts;
-            ffmpeg::extract_frame(&clip.path, ts_in_clip).with_context(|| {
-                format!(
-                    "extract frame from {} @ {:.02}s",
-                    clip.path.to_string_lossy(),
-                    ts_in_clip.as_secs_f64()
-                )
-            })
+            let wanted = frames
+                .iter()
+                .filter(|&&(.., skip)| !skip)
+                .map(|&(ts, pip_at, ..)| (ts, pip_at))
+                .collect::<Vec<_>>();
+            let extracted = match (info.cancel_result(), wanted.as_slice()) {
+                (Err(_), _) | (Ok(()), []) => Vec::new(),
+                (Ok(()), &[(ts, pip_at)]) => vec![extract_timelapse_frame(
+                    &timeline, &overlays, ts, raw, extract, crop, pip_at,
+                )],
+                (Ok(()), wanted) => {
+                    extract_timelapse_run(&*info, &timeline, &overlays, wanted, raw, extract, crop)
+                }
+            };
+            if let Some(&(ts, _)) = wanted.first() {
+                info.log_ffmpeg_diagnostics(&timeline.get_at(ts).1.path);
+            }
+            let mut extracted = extracted.into_iter();
+            frames
+                .iter()
+                .map(|&(_, _, black, skip)| {
+                    info.cancel_result()?;
+                    if skip {
+                        return Ok(None);
+                    }
+                    // a black frame is still extracted, so that it is the size of the frames
+                    // around it
+                    let frame = extracted.next().expect("extracted every wanted frame")?;
+                    match black {
+                        true => frame.blacked_out().map(Some),
+                        false => Ok(Some(frame)),
+                    }
+                })
+                .collect::<Vec<_>>()
         }
-    }));
+    });
+    let jobs = pool.run_ordered_streaming(in_flight, tasks).flatten();
 
-    for (i, job) in jobs.into_iter().enumerate() {
-        let detail = match job.with_context(|| format!("extract frame {}", i)) {
-            Ok(jpg_data) => {
-                enc.encode_frame(jpg_data)
-                    .with_context(|| format!("encode frame {}", i))?;
-                format!("encoded frame {}/{}", i, num_frames)
+    let mut adaptive = settings
+        .adaptive_concurrency
+        .then(|| AdaptiveConcurrency::new(pool));
+    // the size of the first frame, which every later frame is fit to when the encoder needs it
+    let mut geometry = None;
+    let mut mismatched_frames = 0;
+    let mut deflicker = settings.deflicker.map(Deflicker::new);
+    let mut frames_csv = settings
+        .frames_csv
+        .then(|| FramesCsv::create(&output_dir.join("frames.csv"), settings.fps))
+        .transpose()?;
+    // the first frame is held as soon as it is encoded, but the last is only known once done
+    let mut held_start = hold_start_frames == 0;
+    let mut last_frame = None;
+    // the first frames, or `None` where they failed to extract, until they're blended into the end
+    let mut loop_start = Vec::with_capacity(crossfade_frames);
+    let hold = |enc: &mut E, frame: &Frame, meta: &FrameMeta, count: usize| {
+        for _ in 0..count {
+            enc.encode_frame(frame.clone(), meta)
+                .context("encode held frame")?;
+            info.set_progress(crate::SetProgressInfo {
+                progress_inc: Some(1),
+                ..Default::default()
+            });
+        }
+        anyhow::Ok(())
+    };
+    for (i, job) in jobs.enumerate() {
+        if let Some(min_free) = settings.min_free_space {
+            info.wait_for_disk_space(output_dir, min_free)?;
+        }
+        // a frame whose size can't be read is skipped like one that couldn't be extracted
+        let job = job.and_then(|frame| match frame {
+            Some(frame) if enc.fixed_geometry() => {
+                let size = frame.dimensions()?;
+                Ok(Some((frame, Some(size))))
+            }
+            frame => Ok(frame.map(|frame| (frame, None))),
+        });
+        let progress = match job.with_context(|| format!("extract frame {}", i)) {
+            Ok(None) => {
+                let meta = frame_meta(&timeline, timestamps[i], settings.clip_locations);
+                let source = &timeline.get_at(timestamps[i]).1.path;
+                if !held_start {
+                    (0..hold_start_frames).for_each(|_| enc.skip_frame());
+                    if let Some(csv) = &mut frames_csv {
+                        csv.write_frames(&meta, source, hold_start_frames)?;
+                    }
+                    info.set_progress(crate::SetProgressInfo {
+                        progress_inc: Some(hold_start_frames),
+                        ..Default::default()
+                    });
+                    held_start = true;
+                }
+                if let Some(csv) = &mut frames_csv {
+                    csv.write_frames(&meta, source, 1)?;
+                }
+                enc.skip_frame();
+                crate::SetProgressInfo::detail(format!(
+                    "frame {}/{} was already encoded",
+                    i, num_frames
+                ))
+            }
+            Ok(Some((mut frame, size))) => {
+                if let Some(size) = size {
+                    let &mut expected = geometry.get_or_insert(size);
+                    if size != expected {
+                        if mismatched_frames == 0 {
+                            info.set_progress(crate::SetProgressInfo::warn(format!(
+                                "frame {i} is {}x{} but the video is {}x{}, scaling mixed resolution frames to fit",
+                                size.0, size.1, expected.0, expected.1
+                            )));
+                        }
+                        mismatched_frames += 1;
+                        frame = frame
+                            .fit_to(expected)
+                            .with_context(|| format!("scale frame {}", i))?;
+                    }
+                }
+                if let Some(deflicker) = &mut deflicker {
+                    frame = frame
+                        .map_rgb(|mut img| {
+                            deflicker.apply(&mut img);
+                            img
+                        })
+                        .with_context(|| format!("deflicker frame {}", i))?;
+                }
+                if i < crossfade_frames {
+                    loop_start.push(Some(frame));
+                    crate::SetProgressInfo::detail(format!(
+                        "kept frame {i}/{num_frames} to crossfade into the end"
+                    ))
+                } else {
+                    // the last frames fade further into each first frame, which comes next
+                    let j = (i + crossfade_frames).checked_sub(num_frames);
+                    if let Some((j, Some(start))) = j.and_then(|j| Some((j, loop_start.get(j)?))) {
+                        let weight = (j + 1) as f32 / (crossfade_frames + 1) as f32;
+                        frame = frame
+                            .crossfade(start, weight)
+                            .with_context(|| format!("crossfade frame {}", i))?;
+                    }
+                    let meta = frame_meta(&timeline, timestamps[i], settings.clip_locations);
+                    let source = &timeline.get_at(timestamps[i]).1.path;
+                    if !held_start {
+                        hold(&mut enc, &frame, &meta, hold_start_frames)?;
+                        if let Some(csv) = &mut frames_csv {
+                            csv.write_frames(&meta, source, hold_start_frames)?;
+                        }
+                        held_start = true;
+                    }
+                    if let Some(csv) = &mut frames_csv {
+                        csv.write_frames(&meta, source, 1)?;
+                    }
+                    if hold_end_frames > 0 {
+                        last_frame = Some((frame.clone(), meta.clone(), source.clone()));
+                    }
+                    enc.encode_frame(frame, &meta)
+                        .with_context(|| format!("encode frame {}", i))?;
+                    crate::SetProgressInfo::detail(format!("encoded frame {}/{}", i, num_frames))
+                }
+            }
+            Err(e) => {
+                if i < crossfade_frames {
+                    loop_start.push(None);
+                }
+                crate::SetProgressInfo::warn(format!(
+                    "could not extract frame {i}/{num_frames}\n{e}\n\n"
+                ))
             }
-            Err(e) => format!("WARN: could not extract frame {i}/{num_frames}\n{e}\n\n"),
         };
         info.set_progress(crate::SetProgressInfo {
             progress_inc: Some(1),
-            detail: Some(detail),
-            ..Default::default()
+            ..progress
         });
+        if let Some((concurrency, rate)) = adaptive.as_mut().and_then(|a| a.task_finished()) {
+            info.set_progress(crate::SetProgressInfo::detail(format!(
+                "adjusted concurrency to {} threads at {:.01} frames/s",
+                concurrency, rate
+            )));
+        }
+    }
+    if let Some((frame, meta, source)) = last_frame {
+        hold(&mut enc, &frame, &meta, hold_end_frames)?;
+        if let Some(csv) = &mut frames_csv {
+            csv.write_frames(&meta, &source, hold_end_frames)?;
+        }
     }
-    enc.finish().context("finish encoding")?;
+    if let Some(csv) = frames_csv {
+        info.add_bytes_written(csv.commit()?);
+    }
+    if mismatched_frames > 0 {
+        info.set_progress(crate::SetProgressInfo::detail(format!(
+            "scaled {} frames with a different resolution than the first",
+            mismatched_frames
+        )));
+    }
+    info.add_bytes_written(enc.finish().context("finish encoding")?);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        cap_frames_per_clip, extraction_runs, frame_timestamps, group_chapters, jpg_components,
+        letterbox, realtime_frame_timestamps, realtime_offsets, AnimatedTimelapseEnc,
+        BatchExtraction, Crop, Frame, FrameMeta, FramesCsv, JpgTimelapseEnc, SegmentManifest,
+        Segments, TimelapseEncoder, MAX_ANIMATED_FRAMES, MAX_FRAMES_PER_RUN,
+    };
+    use crate::{compute::overlay, ffmpeg};
+    use image::{Rgb, RgbImage};
+    use std::time::Duration;
+
+    #[test]
+    fn only_clips_with_enough_frames_are_split_into_runs() {
+        let clips = [
+            Some(0),
+            Some(1),
+            Some(1),
+            Some(1),
+            Some(1),
+            Some(1),
+            None,
+            None,
+            Some(2),
+            Some(2),
+        ];
+        assert_eq!(extraction_runs(&clips, None).len(), clips.len());
+
+        let batch = BatchExtraction {
+            min_frames: 3,
+            frames_per_run: 2,
+        };
+        assert_eq!(
+            extraction_runs(&clips, Some(batch)),
+            [0..1, 1..3, 3..5, 5..6, 6..7, 7..8, 8..9, 9..10]
+        );
+
+        // long runs would hold more frames than the extraction is allowed to run ahead by
+        let one_clip = [Some(0); MAX_FRAMES_PER_RUN + 1];
+        let batch = BatchExtraction {
+            min_frames: 2,
+            frames_per_run: 100,
+        };
+        assert_eq!(
+            extraction_runs(&one_clip, Some(batch)),
+            [
+                0..MAX_FRAMES_PER_RUN,
+                MAX_FRAMES_PER_RUN..MAX_FRAMES_PER_RUN + 1
+            ]
+        );
+    }
+
+    #[test]
+    fn jpg_frames_are_bucketed_into_subdirectories() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut enc = JpgTimelapseEnc::new(dir.path(), Some(2), false, false);
+        for _ in 0..5 {
+            enc.encode_frame(Frame::Jpg(vec![0]), &FrameMeta::default())
+                .unwrap();
+        }
+
+        for path in [
+            "0000/1.jpg",
+            "0000/2.jpg",
+            "0001/3.jpg",
+            "0001/4.jpg",
+            "0002/5.jpg",
+        ] {
+            assert!(dir.path().join(path).is_file(), "missing {path}");
+        }
+    }
+
+    #[test]
+    fn gray_looking_jpgs_are_encoded_with_one_channel() {
+        // the same as what ffmpeg extracts through the gray filter, which is gray in three channels
+        let gray = RgbImage::from_pixel(8, 8, image::Rgb([90, 90, 90]));
+        let extracted = overlay::encode_jpeg(&gray).unwrap();
+        assert_eq!(jpg_components(&extracted).unwrap(), 3);
+
+        let jpg = Frame::Jpg(extracted).into_gray_jpg().unwrap();
+        assert_eq!(jpg_components(&jpg).unwrap(), 1);
+        assert_eq!(
+            image::load_from_memory(&jpg).unwrap().color(),
+            image::ColorType::L8
+        );
+    }
+
+    #[test]
+    fn gray_jpg_frames_have_one_channel() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut enc = JpgTimelapseEnc::new(dir.path(), None, false, true);
+        let frame = Frame::Rgb(RgbImage::from_pixel(8, 8, image::Rgb([200, 40, 40])));
+        enc.encode_frame(frame, &FrameMeta::default()).unwrap();
+
+        let img = image::open(dir.path().join("1.jpg")).unwrap();
+        assert_eq!(img.color(), image::ColorType::L8);
+
+        // frames that are already a single channel aren't encoded a second time
+        let extracted = std::fs::read(dir.path().join("1.jpg")).unwrap();
+        enc.encode_frame(Frame::Jpg(extracted.clone()), &FrameMeta::default())
+            .unwrap();
+        assert_eq!(std::fs::read(dir.path().join("2.jpg")).unwrap(), extracted);
+    }
+
+    #[test]
+    fn finished_segments_are_resumed_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let segments_dir = dir.path().join("output.segments");
+        let manifest = || SegmentManifest {
+            frames_per_segment: 10,
+            total_frames: 100,
+            command_line: "ffmpeg -c:v libx264 -crf 23 segment.mp4.part".into(),
+            frames: 1,
+        };
+        let write_segments = || {
+            for name in [
+                "segment_00000.mp4",
+                "segment_00001.mp4",
+                "segment_00002.mp4.part",
+            ] {
+                std::fs::write(segments_dir.join(name), b"segment").unwrap();
+            }
+        };
+        drop(Segments::open(segments_dir.clone(), manifest()).unwrap());
+        write_segments();
+
+        // the unfinished third segment is encoded again
+        let segments = Segments::open(segments_dir.clone(), manifest()).unwrap();
+        assert_eq!(segments.done.len(), 2);
+        assert_eq!(segments.resumed, 20);
+        assert_eq!(segments.next_path(), segments_dir.join("segment_00002.mp4"));
+
+        // segments of a different encode are thrown away
+        let different = [
+            SegmentManifest {
+                total_frames: 200,
+                ..manifest()
+            },
+            SegmentManifest {
+                command_line: "ffmpeg -c:v libx264 -crf 18 segment.mp4.part".into(),
+                ..manifest()
+            },
+            SegmentManifest {
+                frames: 2,
+                ..manifest()
+            },
+        ];
+        for other in different {
+            drop(Segments::open(segments_dir.clone(), manifest()).unwrap());
+            write_segments();
+            let segments = Segments::open(segments_dir.clone(), other).unwrap();
+            assert_eq!(segments.resumed, 0);
+            assert!(!segments_dir.join("segment_00000.mp4").exists());
+        }
+    }
+
+    #[test]
+    fn frame_count_matches_requested_length() {
+        let timestamps = frame_timestamps(Duration::from_secs(3600), 10 * 30, None);
+        assert_eq!(timestamps.len(), 300);
+        assert_eq!(timestamps[0], Duration::ZERO);
+        assert!(*timestamps.last().unwrap() < Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn realtime_frames_fall_into_gaps() {
+        use crate::compute::timeline::{Timeline, TimelineClip};
+        use chrono::TimeZone;
+
+        let clip = |start, len| TimelineClip {
+            creation_time: chrono::Utc.timestamp_opt(start, 0).unwrap(),
+            length: Duration::from_secs(len),
+            path: Default::default(),
+            unreliable_timestamps: false,
+        };
+        // 10s recorded, a 70s gap, and another 20s recorded
+        let timeline = Timeline::from_sorted_clips(vec![clip(0, 10), clip(80, 20)]);
+        let frames = realtime_frame_timestamps(&timeline, 5, None);
+        let in_gap: Vec<_> = frames.iter().map(|&(_, in_gap)| in_gap).collect();
+        assert_eq!(in_gap, [false, true, true, true, false]);
+        assert_eq!(frames[4].0, Duration::from_secs(10));
+        assert_eq!(realtime_frame_timestamps(&timeline, 5, Some(3)).len(), 2);
+    }
+
+    #[test]
+    fn skipped_frames_are_excluded_from_total() {
+        let timestamps = frame_timestamps(Duration::from_secs(3600), 10 * 30, Some(100));
+        assert_eq!(timestamps.len(), 200);
+        assert_eq!(timestamps[0], 100 * Duration::from_secs(12));
+    }
+
+    #[test]
+    fn skip_past_end_yields_no_frames() {
+        let timestamps = frame_timestamps(Duration::from_secs(60), 10, Some(20));
+        assert!(timestamps.is_empty());
+    }
+
+    #[test]
+    fn crossfade_blends_toward_the_other_frame() {
+        let frame = Frame::Rgb(RgbImage::from_pixel(4, 2, Rgb([0, 100, 200])));
+        // a differently sized frame is fit to the one it is blended into first
+        let other = Frame::Rgb(RgbImage::from_pixel(2, 1, Rgb([200, 100, 0])));
+        let blended = frame.crossfade(&other, 0.25).unwrap().into_rgb().unwrap();
+        assert_eq!(blended.dimensions(), (4, 2));
+        assert_eq!(*blended.get_pixel(1, 1), Rgb([50, 100, 150]));
+    }
+
+    #[test]
+    fn letterbox_keeps_aspect_ratio() {
+        let img = RgbImage::from_pixel(100, 100, Rgb([255, 255, 255]));
+        let boxed = letterbox(&img, 200, 100);
+        assert_eq!(boxed.dimensions(), (200, 100));
+        assert_eq!(*boxed.get_pixel(10, 50), Rgb([0, 0, 0]));
+        assert_eq!(*boxed.get_pixel(100, 50), Rgb([255, 255, 255]));
+        assert_eq!(*boxed.get_pixel(190, 50), Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn jpg_dimensions_are_read_from_header() {
+        let img = RgbImage::new(64, 48);
+        let frame = Frame::Jpg(crate::compute::overlay::encode_jpeg(&img).unwrap());
+        assert_eq!(frame.dimensions().unwrap(), (64, 48));
+    }
+
+    #[test]
+    fn frames_per_clip_are_capped_evenly() {
+        let timestamps = (0..10).map(Duration::from_secs).collect::<Vec<_>>();
+        // clip 0 is [0s, 8s), clip 1 is [8s, ..)
+        let clip_index_at = |ts: Duration| Some(usize::from(ts >= Duration::from_secs(8)));
+        let (kept, capped) = cap_frames_per_clip(timestamps, clip_index_at, 4);
+        let secs = kept.iter().map(Duration::as_secs).collect::<Vec<_>>();
+        assert_eq!(secs, vec![0, 2, 4, 6, 8, 9]);
+        assert_eq!(capped, 1);
+    }
+
+    #[test]
+    fn gap_frames_are_not_capped() {
+        // clip 0 is [0s, 8s) and clip 1 is [8s, ..), with gap frames in between that are all
+        // mapped to the same point of clip 0
+        let frames = (0..10)
+            .map(|s| match (2..8).contains(&s) {
+                true => (Duration::from_secs(2), true),
+                false => (Duration::from_secs(s), false),
+            })
+            .collect::<Vec<_>>();
+        let clip_index_at = |(ts, in_gap): (Duration, bool)| {
+            (!in_gap).then(|| usize::from(ts >= Duration::from_secs(8)))
+        };
+        let (kept, capped) = cap_frames_per_clip(frames, clip_index_at, 1);
+        let in_gaps = kept.iter().filter(|&&(_, in_gap)| in_gap).count();
+        assert_eq!((kept.len(), in_gaps), (8, 6));
+        assert_eq!(capped, 2);
+    }
+
+    #[test]
+    fn picture_in_picture_plays_each_clip_in_real_time() {
+        let secs = |s: &[u64]| {
+            s.iter()
+                .map(|&s| Duration::from_secs(s))
+                .collect::<Vec<_>>()
+        };
+        // clips are 10s long, and each is sampled 3 times
+        let offsets = realtime_offsets(
+            &secs(&[1, 4, 7, 11, 14, 17]),
+            |ts| ts.as_secs() as usize / 10,
+            2,
+        );
+        let millis = offsets.iter().map(Duration::as_millis).collect::<Vec<_>>();
+        assert_eq!(millis, [1000, 1500, 2000, 11000, 11500, 12000]);
+    }
+
+    #[test]
+    fn animated_output_is_limited_in_frames() {
+        let dir = tempfile::tempdir().unwrap();
+        let err = AnimatedTimelapseEnc::new(
+            dir.path().join("output.webp"),
+            30,
+            ffmpeg::AnimatedFormat::Webp,
+            MAX_ANIMATED_FRAMES + 1,
+        )
+        .err()
+        .unwrap();
+        assert!(format!("{err}").contains("limited to"));
+    }
+
+    #[test]
+    #[ignore = "needs ffmpeg and ffprobe on the PATH"]
+    fn animated_output_has_every_frame() {
+        let _ = ffmpeg::set_binary_paths("ffmpeg".into(), "ffprobe".into());
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("output.avif");
+        let mut enc =
+            AnimatedTimelapseEnc::new(&output, 10, ffmpeg::AnimatedFormat::Avif, 5).unwrap();
+        for i in 0..5 {
+            let frame = Frame::Rgb(RgbImage::from_pixel(64, 48, Rgb([i * 50, 0, 0])));
+            enc.encode_frame(frame, &FrameMeta::default()).unwrap();
+        }
+        assert!(enc.finish().unwrap() > 0);
+
+        #[rustfmt::skip]
+        let probed = std::process::Command::new("ffprobe")
+            .args([
+                "-v", "error",
+                "-select_streams", "v:0",
+                "-show_entries", "stream=nb_frames",
+                "-of", "default=noprint_wrappers=1:nokey=1",
+            ])
+            .arg(&output)
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8_lossy(&probed.stdout).trim(), "5");
+    }
+
+    #[test]
+    fn frames_csv_numbers_held_frames_and_quotes_paths() {
+        let mut out = Vec::new();
+        let mut csv = FramesCsv::new(&mut out, 2).unwrap();
+        let path = std::path::Path::new("clips/a \"b\".mp4");
+        csv.write_frames(&FrameMeta::default(), path, 2).unwrap();
+        csv.write_frames(&FrameMeta::default(), path, 1).unwrap();
+        let (_, bytes_written) = csv.finish().unwrap();
+        assert_eq!(bytes_written, out.len() as u64);
+
+        let rows = String::from_utf8(out).unwrap();
+        let rows = rows.lines().collect::<Vec<_>>();
+        assert_eq!(rows.len(), 4);
+        assert_eq!(rows[2], "1,0.500,,\"clips/a \"\"b\"\".mp4\"");
+        assert!(rows[3].starts_with("2,1.000,"));
+    }
+
+    #[test]
+    fn chapters_follow_title_changes_and_cover_held_frames() {
+        let titles = ["a", "a", "b", "b", "b", "c"].map(String::from);
+        let chapters = group_chapters(titles.into_iter(), 2, 2, 4);
+        let spans = chapters
+            .iter()
+            .map(|c| (c.title.as_str(), c.start.as_secs_f64(), c.end.as_secs_f64()))
+            .collect::<Vec<_>>();
+        assert_eq!(spans, [("a", 0.0, 2.0), ("b", 2.0, 3.5), ("c", 3.5, 6.0)]);
+    }
+
+    #[test]
+    fn crop_keeps_the_region_and_rejects_ones_past_the_edge() {
+        let mut img = RgbImage::new(100, 50);
+        img.put_pixel(60, 20, Rgb([255, 0, 0]));
+        let crop = Crop {
+            x: 50,
+            y: 10,
+            width: 50,
+            height: 40,
+        };
+        let cropped = crop.apply(&img).unwrap();
+        assert_eq!(cropped.dimensions(), (50, 40));
+        assert_eq!(cropped.get_pixel(10, 10), &Rgb([255, 0, 0]));
+
+        let too_wide = Crop { x: 51, ..crop };
+        assert!(too_wide.apply(&img).is_err());
+        // it's checked against the frame size alone before any frames are extracted
+        assert!(crop.check((100, 50)).is_ok());
+        assert!(too_wide.check((100, 50)).is_err());
+        let empty = Crop { width: 0, ..crop };
+        assert!(empty.apply(&img).is_err());
+    }
+}
@@ -1,15 +1,30 @@
-use crate::{compute::workers::WorkerPool, SetProgressInfo};
+use crate::{
+    compute::{
+        glyph::LatLng,
+        sun,
+        workers::{Priority, WorkerPool},
+    },
+    Cancelled, SetProgressInfo,
+};
 
-use super::JobInfo;
+use super::JobContext;
 use anyhow::Context;
+use regex::Regex;
 use std::{
+    collections::BTreeMap,
     error::Error,
     ffi::OsStr,
     path::{Path, PathBuf},
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
+/// the timezone that the dashcam stamps clip filenames in
+pub(super) const CLIP_TIMEZONE: chrono_tz::Tz = chrono_tz::America::New_York;
+
 pub struct TimelineClip {
     /// start offset of the clip within the timeline
     pub creation_time: chrono::DateTime<chrono::Utc>,
@@ -17,14 +32,41 @@ pub struct TimelineClip {
     pub length: Duration,
     /// the path to the clip
     pub path: PathBuf,
+    /// the clip's timestamps jump backwards, so its frames are found by decoding it in order
+    /// instead of seeking
+    pub unreliable_timestamps: bool,
 }
 impl TimelineClip {
-    fn process(job: &JobInfo, path: PathBuf) -> anyhow::Result<Self> {
+    /// `date_by_modified` dates a clip without a timestamp in its filename by when it was last
+    /// modified, instead of failing
+    fn process(
+        job: &dyn JobContext,
+        path: PathBuf,
+        filename_timestamp: &FilenameTimestamp,
+        check_packet_timing: bool,
+        date_by_modified: bool,
+    ) -> anyhow::Result<Self> {
         job.cancel_result()?;
 
-        let info = crate::ffmpeg::probe(&path).context("probe info")?;
-        let creation_time =
-            Self::parse_timestamp_from_path(&path).context("parse timestamp from path")?;
+        let info = crate::ffmpeg::probe(&path).context("probe info");
+        job.log_ffmpeg_diagnostics(&path);
+        let info = info?;
+        let creation_time = match Self::parse_timestamp_from_path(&path, filename_timestamp) {
+            Ok(creation_time) => creation_time,
+            Err(e) if date_by_modified => {
+                let modified = std::fs::metadata(&path)
+                    .and_then(|meta| meta.modified())
+                    .with_context(|| format!("read modified time of {:?}", path))?;
+                job.set_progress(SetProgressInfo::warn(format!(
+                    "dating {:?} by its modified time, since its filename has no timestamp: \
+                     {:#}",
+                    path, e
+                )));
+                chrono::DateTime::<chrono::Utc>::from(modified) - info.duration
+            }
+            Err(e) => return Err(e.context("parse timestamp from path")),
+        };
+        let unreliable_timestamps = check_packet_timing && Self::check_packet_timing(job, &path)?;
 
         job.set_progress(SetProgressInfo::detail(format!(
             "processed TimelineClip {:?}",
@@ -34,24 +76,614 @@ impl TimelineClip {
             creation_time,
             length: info.duration,
             path,
+            unreliable_timestamps,
         })
     }
 
-    fn parse_timestamp_from_path(path: &Path) -> anyhow::Result<chrono::DateTime<chrono::Utc>> {
-        use chrono::{NaiveDateTime, TimeZone};
+    /// whether the timestamps of the clip at `path` jump backwards, warning about it if they do
+    fn check_packet_timing(job: &dyn JobContext, path: &Path) -> anyhow::Result<bool> {
+        let discontinuities = crate::ffmpeg::timestamp_discontinuities(path);
+        job.log_ffmpeg_diagnostics(path);
+        let discontinuities = discontinuities.context("probe packet timing")?;
+        if discontinuities > 0 {
+            job.set_progress(SetProgressInfo::warn(format!(
+                "{:?} has {} timestamps that jump backwards, so its frames may be unreliable and are found by decoding it in order, which is slower",
+                path, discontinuities
+            )));
+        }
+        Ok(discontinuities > 0)
+    }
+
+    fn parse_timestamp_from_path(
+        path: &Path,
+        filename_timestamp: &FilenameTimestamp,
+    ) -> anyhow::Result<chrono::DateTime<chrono::Utc>> {
+        use chrono::NaiveDateTime;
 
         let filename = path
             .file_name()
             .map(OsStr::to_string_lossy)
             .ok_or(anyhow::anyhow!("get filename from path"))?;
-        let date_str = &filename[..16]; // the first 16 characters includes the date: YYYY_MMDD_HHmmss
-        let ndt = NaiveDateTime::parse_from_str(date_str, "%Y_%m%d_%H%M%S")?;
-        chrono_tz::America::New_York
-            .from_local_datetime(&ndt)
-            .single()
-            .map(|dt| dt.to_utc())
-            .ok_or(anyhow::anyhow!("from_local_datetime not single"))
+        let (date_str, rest) = filename_timestamp.locate(&filename)?;
+        let mut ndt = NaiveDateTime::parse_from_str(date_str, &filename_timestamp.format)
+            .with_context(|| {
+                format!(
+                    "parse {:?} with format {:?}",
+                    date_str, filename_timestamp.format
+                )
+            })?;
+        // clips without milliseconds just start on the second, rather than failing to parse
+        if let Some(millis) = leading_millis(rest).filter(|_| filename_timestamp.millis) {
+            ndt += chrono::TimeDelta::milliseconds(millis.into());
+        }
+        filename_timestamp.dst.resolve(ndt).ok_or_else(|| {
+            anyhow::anyhow!(
+                "{} is repeated or skipped by a daylight saving change in {}",
+                ndt,
+                CLIP_TIMEZONE
+            )
+        })
+    }
+}
+
+/// the error from building a timeline that ends up without any clips, so that callers which can
+/// do without one can tell it apart from a failure
+#[derive(Debug)]
+pub struct EmptyTimeline(String);
+impl std::fmt::Display for EmptyTimeline {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+impl Error for EmptyTimeline {}
+impl EmptyTimeline {
+    /// whether `err` was caused by the timeline being empty, under any amount of context
+    pub fn caused(err: &anyhow::Error) -> bool {
+        err.chain().any(|cause| cause.is::<EmptyTimeline>())
+    }
+}
+
+/// the real-world span of a timeline, for picking a sub-range of it
+#[derive(Debug, Clone, PartialEq, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DateRange {
+    /// when the earliest clip started recording
+    pub earliest: String,
+    /// when the last recording stopped
+    pub latest: String,
+    /// seconds of footage, which leaves out the gaps between clips
+    pub recorded_secs: f64,
+    /// days that a clip started recording on, in the timezone the clips were recorded in
+    pub days: usize,
+}
+
+/// options for which clips are collected into a `Timeline`
+#[derive(Debug, Default, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct TimelineOptions {
+    /// how many directories deep to look for clips, where 0 is only the input directory itself
+    pub max_depth: Option<usize>,
+    /// warn about clips dated further than this many seconds from both of their neighbors
+    pub out_of_sequence_secs: Option<u64>,
+    /// only include clips recorded within this window of the day
+    pub time_of_day: Option<TimeOfDayWindow>,
+    /// only include clips recorded around sunrise and sunset
+    pub golden_hour: Option<GoldenHourFilter>,
+    /// only include clips recorded on these days of the week, as names like `"mon"` or `"monday"`
+    #[serde(deserialize_with = "deserialize_weekdays")]
+    pub weekdays: Option<Vec<chrono::Weekday>>,
+    /// where the recording time is in clip filenames, for cameras with other naming schemes
+    pub filename_timestamp: FilenameTimestamp,
+    /// only probe the earliest this many clips by filename that aren't excluded, for quick tests
+    /// on a huge archive
+    pub max_clips: Option<usize>,
+    /// stop collecting clips once they add up to this many seconds
+    ///
+    /// clips are probed in parallel, so which clips make the cut is only roughly the earliest
+    pub max_duration_secs: Option<u64>,
+    /// clips to leave out, as exact paths or filename patterns like `2024_0501_12*`
+    #[serde(deserialize_with = "deserialize_exclude")]
+    pub exclude: Vec<glob::Pattern>,
+    /// what to do about clips that fail to probe
+    pub probe_failures: ProbeFailurePolicy,
+    /// read the timing of every packet while probing, to find clips whose timestamps jump
+    /// backwards and extract their frames without trusting them (slow on a big archive)
+    pub check_packet_timing: bool,
+}
+impl TimelineOptions {
+    /// whether the clip at `path` is explicitly excluded, which is checked before probing it
+    /// so that corrupt clips can be left out
+    fn excludes_path(&self, path: &Path) -> bool {
+        let filename = path.file_name().map(OsStr::to_string_lossy);
+        self.exclude.iter().any(|pattern| {
+            Path::new(pattern.as_str()) == path
+                || filename
+                    .as_deref()
+                    .is_some_and(|filename| pattern.matches(filename))
+        })
+    }
+    /// why a clip recorded at `creation_time` is excluded by the filters, if it is
+    fn exclusion(&self, creation_time: chrono::DateTime<chrono::Utc>) -> Option<&'static str> {
+        use chrono::Datelike;
+
+        let local = creation_time.with_timezone(&CLIP_TIMEZONE);
+        if let Some(window) = &self.time_of_day {
+            if !window.contains(local.time()) {
+                return Some("outside of the time-of-day window");
+            }
+        }
+        if let Some(weekdays) = &self.weekdays {
+            if !weekdays.contains(&local.weekday()) {
+                return Some("on an excluded weekday");
+            }
+        }
+        if let Some(golden_hour) = &self.golden_hour {
+            if !golden_hour.contains(local) {
+                return Some("outside of golden hour");
+            }
+        }
+        None
+    }
+    /// whether the clip at `path` is excluded, either explicitly or by the filters going by the
+    /// timestamp in its filename, which is all that's known about it before probing
+    fn excludes_path_by_filename(&self, path: &Path) -> bool {
+        self.excludes_path(path)
+            || TimelineClip::parse_timestamp_from_path(path, &self.filename_timestamp)
+                .is_ok_and(|creation_time| self.exclusion(creation_time).is_some())
+    }
+}
+/// how long to wait before probing a clip again
+const PROBE_RETRY_DELAY: Duration = Duration::from_secs(1);
+
+/// what to do when a clip can't be probed, which in large archives is usually a few corrupt clips
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ProbeFailurePolicy {
+    /// fail the whole timeline
+    #[default]
+    Abort,
+    /// probe the clip once more, then leave it out if it still fails
+    RetryThenSkip,
+    /// leave the clip out
+    Skip,
+}
+impl ProbeFailurePolicy {
+    /// run `probe` under the policy, where `Ok(Err(_))` is a failure the clip should be skipped for
+    ///
+    /// `wait` is called before retrying, and returns `false` if the job was cancelled meanwhile
+    fn run<T>(
+        self,
+        wait: impl FnOnce() -> bool,
+        mut probe: impl FnMut() -> anyhow::Result<T>,
+    ) -> anyhow::Result<Result<T, anyhow::Error>> {
+        let mut result = probe();
+        if self == Self::RetryThenSkip && result.as_ref().is_err_and(|e| !Cancelled::caused(e)) {
+            if !wait() {
+                return Err(Cancelled.into());
+            }
+            result = probe();
+        }
+        match result {
+            Ok(probed) => Ok(Ok(probed)),
+            Err(e) if self == Self::Abort || Cancelled::caused(&e) => Err(e),
+            Err(e) => Ok(Err(e)),
+        }
+    }
+}
+
+fn deserialize_exclude<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<glob::Pattern>, D::Error> {
+    let entries = <Vec<String> as serde::Deserialize>::deserialize(deserializer)?;
+    // a path with brackets in it is not a valid pattern, but should still match itself
+    Ok(entries
+        .iter()
+        .map(|entry| {
+            glob::Pattern::new(entry)
+                .or_else(|_| glob::Pattern::new(&glob::Pattern::escape(entry)))
+                .expect("escaped pattern is valid")
+        })
+        .collect())
+}
+fn deserialize_weekdays<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Vec<chrono::Weekday>>, D::Error> {
+    let names = <Option<Vec<String>> as serde::Deserialize>::deserialize(deserializer)?;
+    names
+        .map(|names| {
+            names
+                .iter()
+                .map(|name| name.parse().map_err(serde::de::Error::custom))
+                .collect()
+        })
+        .transpose()
+}
+
+/// how a local timestamp is read when a daylight saving change repeats it, when the clocks fall
+/// back, or skips over it, when the clocks spring forward
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum DstPolicy {
+    /// the earlier of the two instants it could be
+    #[default]
+    EarliestValid,
+    /// the later of the two instants it could be
+    LatestValid,
+    /// fail to parse the clip's timestamp
+    Error,
+}
+impl DstPolicy {
+    /// the instant that the local time `ndt` in the clip timezone is read as
+    fn resolve(self, ndt: chrono::NaiveDateTime) -> Option<chrono::DateTime<chrono::Utc>> {
+        use chrono::{LocalResult, Offset, TimeZone};
+
+        let (earliest, latest) = match CLIP_TIMEZONE.from_local_datetime(&ndt) {
+            LocalResult::Single(dt) => return Some(dt.to_utc()),
+            LocalResult::Ambiguous(a, b) => {
+                (a.to_utc().min(b.to_utc()), a.to_utc().max(b.to_utc()))
+            }
+            // the clocks skipped past `ndt`, so it could be in the offset from either side of the
+            // gap, like a camera that didn't change its clock yet would have stamped it
+            LocalResult::None => {
+                let around = ndt.and_utc();
+                let instants =
+                    [chrono::TimeDelta::days(-1), chrono::TimeDelta::days(1)].map(|delta| {
+                        let offset = CLIP_TIMEZONE.offset_from_utc_datetime(&(ndt + delta)).fix();
+                        around - chrono::TimeDelta::seconds(offset.local_minus_utc().into())
+                    });
+                (instants[0].min(instants[1]), instants[0].max(instants[1]))
+            }
+        };
+        match self {
+            Self::EarliestValid => Some(earliest),
+            Self::LatestValid => Some(latest),
+            Self::Error => None,
+        }
+    }
+}
+
+/// how the recording time is found in a clip's filename, and what format it is in
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(default)]
+pub struct FilenameTimestamp {
+    /// regex locating the timestamp, as its first capture group or otherwise the whole match
+    ///
+    /// without one, the timestamp is the first 16 characters of the filename
+    #[serde(deserialize_with = "deserialize_regex")]
+    pub pattern: Option<Regex>,
+    /// chrono format string the located timestamp is parsed with
+    pub format: String,
+    /// read milliseconds from 3 digits right after the timestamp, like `YYYY_MMDD_HHmmss_SSS`,
+    /// so clips recorded within the same second still sort in order
+    pub millis: bool,
+    /// how to read a timestamp that a daylight saving change makes ambiguous or skips over
+    pub dst: DstPolicy,
+}
+impl Default for FilenameTimestamp {
+    fn default() -> Self {
+        Self {
+            pattern: None,
+            format: "%Y_%m%d_%H%M%S".into(),
+            millis: false,
+            dst: DstPolicy::default(),
+        }
+    }
+}
+impl FilenameTimestamp {
+    /// the timestamp within `filename`, and everything after it
+    fn locate<'a>(&self, filename: &'a str) -> anyhow::Result<(&'a str, &'a str)> {
+        let Some(pattern) = &self.pattern else {
+            // the first 16 characters includes the date: YYYY_MMDD_HHmmss
+            return filename
+                .get(..16)
+                .map(|ts| (ts, &filename[16..]))
+                .ok_or_else(|| anyhow::anyhow!("{:?} is too short for a timestamp", filename));
+        };
+        let captures = pattern.captures(filename).ok_or_else(|| {
+            anyhow::anyhow!(
+                "{:?} does not match the timestamp pattern {}",
+                filename,
+                pattern
+            )
+        })?;
+        let found = captures
+            .get(1)
+            .or_else(|| captures.get(0))
+            .expect("a match always has group 0");
+        Ok((found.as_str(), &filename[found.end()..]))
+    }
+}
+
+/// milliseconds from the 3 digits at the start of `rest`, after an optional separator
+fn leading_millis(rest: &str) -> Option<u32> {
+    let rest = rest.strip_prefix(['_', '-', '.']).unwrap_or(rest);
+    let digits = rest.get(..3)?;
+    if !digits.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
     }
+    digits.parse().ok()
+}
+
+fn deserialize_regex<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<Regex>, D::Error> {
+    let pattern = <Option<String> as serde::Deserialize>::deserialize(deserializer)?;
+    pattern
+        .map(|pattern| Regex::new(&pattern).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+/// a window of minutes around sunrise and sunset, which are worked out for the date of each clip
+///
+/// the timeline is built before anything is scraped, so the location has to be given up-front
+#[derive(Debug, Clone, serde::Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct GoldenHourFilter {
+    /// minutes either side of sunrise and sunset to include
+    pub window_mins: u32,
+    /// where the clips were recorded
+    pub location: Option<LatLng>,
+    /// `HH:MM` clock time standing in for sunrise without a location, or during polar day or night
+    #[serde(deserialize_with = "deserialize_time_of_day")]
+    pub fallback_sunrise: chrono::NaiveTime,
+    /// `HH:MM` clock time standing in for sunset, like `fallback_sunrise`
+    #[serde(deserialize_with = "deserialize_time_of_day")]
+    pub fallback_sunset: chrono::NaiveTime,
+}
+impl Default for GoldenHourFilter {
+    fn default() -> Self {
+        Self {
+            window_mins: 60,
+            location: None,
+            fallback_sunrise: chrono::NaiveTime::from_hms_opt(6, 30, 0).expect("valid time"),
+            fallback_sunset: chrono::NaiveTime::from_hms_opt(19, 0, 0).expect("valid time"),
+        }
+    }
+}
+impl GoldenHourFilter {
+    fn contains(&self, local: chrono::DateTime<chrono_tz::Tz>) -> bool {
+        use chrono::TimeZone;
+
+        let date = local.date_naive();
+        let sun_times = self
+            .location
+            .as_ref()
+            .and_then(|loc| sun::sunrise_sunset(date, loc))
+            .map(|(sunrise, sunset)| (sunrise.naive_utc(), sunset.naive_utc()))
+            .or_else(|| {
+                let to_utc = |time| {
+                    CLIP_TIMEZONE
+                        .from_local_datetime(&date.and_time(time))
+                        .earliest()
+                        .map(|dt| dt.naive_utc())
+                };
+                Some((
+                    to_utc(self.fallback_sunrise)?,
+                    to_utc(self.fallback_sunset)?,
+                ))
+            });
+        let Some((sunrise, sunset)) = sun_times else {
+            return false;
+        };
+
+        let window = chrono::TimeDelta::minutes(self.window_mins.into());
+        let time = local.naive_utc();
+        (time - sunrise).abs() <= window || (time - sunset).abs() <= window
+    }
+}
+
+/// a window of the day as `HH:MM` times, which wraps past midnight when `end` is before `start`
+#[derive(Debug, Clone, serde::Deserialize)]
+pub struct TimeOfDayWindow {
+    #[serde(deserialize_with = "deserialize_time_of_day")]
+    pub start: chrono::NaiveTime,
+    #[serde(deserialize_with = "deserialize_time_of_day")]
+    pub end: chrono::NaiveTime,
+}
+impl TimeOfDayWindow {
+    fn contains(&self, time: chrono::NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            self.start <= time || time < self.end
+        }
+    }
+}
+fn deserialize_time_of_day<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<chrono::NaiveTime, D::Error> {
+    let s = <String as serde::Deserialize>::deserialize(deserializer)?;
+    chrono::NaiveTime::parse_from_str(&s, "%H:%M").map_err(serde::de::Error::custom)
+}
+
+/// indices of the (sorted) `times` that are more than `threshold` away from every neighbor
+///
+/// a clip with a mis-parsed timestamp ends up isolated at the wrong position in the timeline, far away
+/// from the clips on either side of it
+fn out_of_sequence_indices(
+    times: &[chrono::DateTime<chrono::Utc>],
+    threshold: chrono::TimeDelta,
+) -> Vec<usize> {
+    if times.len() < 2 {
+        return Vec::new();
+    }
+    (0..times.len())
+        .filter(|&i| {
+            let far_from_prev = i == 0 || times[i] - times[i - 1] > threshold;
+            let far_from_next = i + 1 == times.len() || times[i + 1] - times[i] > threshold;
+            far_from_prev && far_from_next
+        })
+        .collect()
+}
+
+/// glob every clip under `input_path`, up to `max_depth` directories deep
+fn glob_clip_paths(
+    input_path: &Path,
+    max_depth: Option<usize>,
+) -> anyhow::Result<impl Iterator<Item = Result<PathBuf, glob::GlobError>>> {
+    let patterns = match max_depth {
+        None => vec![input_path.join("**").join("*.mp4")],
+        Some(max_depth) => (0..=max_depth)
+            .map(|depth| {
+                let mut pattern = input_path.to_path_buf();
+                for _ in 0..depth {
+                    pattern.push("*");
+                }
+                pattern.join("*.mp4")
+            })
+            .collect(),
+    };
+
+    let mut paths = Vec::with_capacity(patterns.len());
+    for pattern in patterns {
+        paths.push(glob::glob_with(
+            &pattern.to_string_lossy(),
+            glob::MatchOptions {
+                case_sensitive: false,
+                ..Default::default()
+            },
+        )?);
+    }
+    Ok(paths.into_iter().flatten())
+}
+
+/// extensions of video files that are not timelined, but hint at why no clips were found
+const OTHER_VIDEO_EXTENSIONS: &[&str] = &["mov", "avi", "mkv", "m4v", "mts", "ts", "webm", "3gp"];
+
+/// the first `max_clips` paths ordered by filename, which starts with the date the clip was recorded
+fn earliest_clip_paths(mut paths: Vec<PathBuf>, max_clips: Option<usize>) -> Vec<PathBuf> {
+    paths.sort_unstable_by(|a, b| a.file_name().cmp(&b.file_name()));
+    if let Some(max_clips) = max_clips {
+        paths.truncate(max_clips);
+    }
+    paths
+}
+
+/// a hint at why globbing found no clips, from a scan of every file under `input_path`
+fn no_clips_hint(input_path: &Path, max_depth: Option<usize>) -> String {
+    let mut counts = BTreeMap::<String, usize>::new();
+    let all_files = glob::glob(&input_path.join("**").join("*").to_string_lossy())
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|path| path.is_file());
+    for path in all_files {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        *counts.entry(ext).or_default() += 1;
+    }
+
+    if let (Some(&mp4s), Some(max_depth)) = (counts.get("mp4"), max_depth) {
+        return format!("found {mp4s} .mp4 files nested deeper than the max depth of {max_depth}");
+    }
+    let others = OTHER_VIDEO_EXTENSIONS
+        .iter()
+        .filter_map(|&ext| counts.get(ext).map(|count| format!("{count} .{ext}")))
+        .collect::<Vec<_>>();
+    if !others.is_empty() {
+        format!(
+            "found {} files but only .mp4 clips are supported",
+            others.join(", ")
+        )
+    } else if counts.is_empty() {
+        "the input directory has no files".into()
+    } else {
+        "the input directory has no video files".into()
+    }
+}
+
+/// probe and parse the timestamp of the earliest clip under `input_path`, to catch a bad config
+/// before the whole timeline is built
+///
+/// clips that fail are handled by `opts.probe_failures` the way the timeline would, so a skipped
+/// clip moves the check on to the next earliest one
+///
+/// returns the path of the clip, or `None` if there are no clips to check
+pub fn check_first_clip(
+    info: &dyn JobContext,
+    input_path: &Path,
+    opts: &TimelineOptions,
+) -> anyhow::Result<Option<PathBuf>> {
+    let paths = glob_clip_paths(input_path, opts.max_depth)?
+        .filter(|path| !path.as_ref().is_ok_and(|path| opts.excludes_path(path)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut skipped = 0;
+    for path in earliest_clip_paths(paths, None) {
+        let wait = || {
+            info.set_progress(SetProgressInfo::warn(format!(
+                "failed to process first clip {:?}, retrying",
+                path
+            )));
+            info.interruptible_sleep(PROBE_RETRY_DELAY)
+        };
+        let processed = opts
+            .probe_failures
+            .run(wait, || {
+                TimelineClip::process(
+                    info,
+                    path.clone(),
+                    &opts.filename_timestamp,
+                    opts.check_packet_timing,
+                    false,
+                )
+            })
+            .with_context(|| format!("process first clip {:?}", path))?;
+        match processed {
+            Ok(_) => return Ok(Some(path)),
+            Err(e) => {
+                info.set_progress(SetProgressInfo::warn(format!(
+                    "skipped first clip {:?} that could not be processed: {:#}",
+                    path, e
+                )));
+                skipped += 1;
+            }
+        }
+    }
+    if skipped > 0 {
+        anyhow::bail!("none of the {} clips could be processed", skipped);
+    }
+    Ok(None)
+}
+
+/// how many failing filenames a `TimestampReport` keeps
+const TIMESTAMP_REPORT_SAMPLES: usize = 10;
+
+/// how many clips have a timestamp that parses from their filename
+#[derive(Debug, Default, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimestampReport {
+    pub parsed: usize,
+    pub failed: usize,
+    /// the first few filenames that failed to parse, and why
+    pub failed_samples: Vec<String>,
+}
+
+/// parse the timestamp of every clip under `input_path` that a timeline would include, without
+/// probing any of them, to check the filename options before a long job
+pub fn check_timestamps(
+    input_path: &Path,
+    opts: &TimelineOptions,
+) -> anyhow::Result<TimestampReport> {
+    let mut report = TimestampReport::default();
+    for path in glob_clip_paths(input_path, opts.max_depth)? {
+        let path = path?;
+        if opts.excludes_path(&path) {
+            continue;
+        }
+        match TimelineClip::parse_timestamp_from_path(&path, &opts.filename_timestamp) {
+            Ok(_) => report.parsed += 1,
+            Err(e) => {
+                report.failed += 1;
+                if report.failed_samples.len() < TIMESTAMP_REPORT_SAMPLES {
+                    let filename = path.file_name().unwrap_or_default().to_string_lossy();
+                    report.failed_samples.push(format!("{}: {:#}", filename, e));
+                }
+            }
+        }
+    }
+    Ok(report)
 }
 
 pub struct Timeline {
@@ -60,24 +692,111 @@ pub struct Timeline {
 }
 impl Timeline {
     pub fn new_from_path(
-        info: Arc<JobInfo>,
+        info: Arc<dyn JobContext>,
         pool: &WorkerPool,
         input_path: impl AsRef<Path>,
+        opts: &TimelineOptions,
     ) -> anyhow::Result<Self> {
-        let glob_pattern = input_path.as_ref().join("**").join("*.mp4");
-        let paths = glob::glob_with(
-            &glob_pattern.to_string_lossy(),
-            glob::MatchOptions {
-                case_sensitive: false,
-                ..Default::default()
-            },
-        )?;
-        Self::new(info, pool, paths)
+        let input_path = input_path.as_ref();
+        let mut paths = glob_clip_paths(input_path, opts.max_depth)?.peekable();
+        if paths.peek().is_none() {
+            return Err(EmptyTimeline(format!(
+                "no .mp4 clips found in {:?}: {}",
+                input_path,
+                no_clips_hint(input_path, opts.max_depth)
+            ))
+            .into());
+        }
+        if opts.max_clips.is_none() && opts.max_duration_secs.is_none() {
+            return Self::new(info, pool, paths, opts);
+        }
+
+        // a limited timeline should be the same subset every time, so the paths are sorted first
+        let mut paths = paths.collect::<Result<Vec<_>, _>>()?;
+        if opts.max_clips.is_some() {
+            // excluded clips would otherwise take up the limit and leave fewer clips than asked for
+            let total = paths.len();
+            paths.retain(|path| !opts.excludes_path_by_filename(path));
+            if paths.len() < total {
+                info.set_progress(SetProgressInfo::detail(format!(
+                    "excluded {} clips by their filenames before limiting the number of clips",
+                    total - paths.len()
+                )));
+            }
+        }
+        let paths = earliest_clip_paths(paths, opts.max_clips);
+        Self::new(
+            info,
+            pool,
+            paths.into_iter().map(Ok::<_, glob::GlobError>),
+            opts,
+        )
+    }
+    /// a timeline of just the clip at `clip_path`, like one long recording, without globbing
+    ///
+    /// a clip without a timestamp in its filename is dated by when it was last modified, which is
+    /// usually when its recording stopped
+    pub fn new_from_clip(
+        info: &dyn JobContext,
+        clip_path: &Path,
+        opts: &TimelineOptions,
+    ) -> anyhow::Result<Self> {
+        if clip_path.is_dir() {
+            anyhow::bail!(
+                "{:?} is a directory, which needs the full timeline instead",
+                clip_path
+            );
+        }
+        if !clip_path.is_file() {
+            anyhow::bail!("{:?} is not a file", clip_path);
+        }
+        let clip = TimelineClip::process(
+            info,
+            clip_path.to_owned(),
+            &opts.filename_timestamp,
+            opts.check_packet_timing,
+            true,
+        )
+        .with_context(|| format!("process TimelineClip {:?}", clip_path))?;
+        if let Some(reason) = opts.exclusion(clip.creation_time) {
+            anyhow::bail!(
+                "{:?} is excluded by the timeline filters {}",
+                clip_path,
+                reason
+            );
+        }
+
+        let timeline = Self::from_sorted_clips(vec![clip]);
+        info.set_progress(SetProgressInfo::detail(format!(
+            "timelined the single clip {:?}, which is {:.02}h long",
+            clip_path,
+            timeline.duration.as_secs_f64() / 60.0 / 60.0
+        )));
+        Ok(timeline)
     }
     fn new<E: Error + Send + Sync + 'static>(
-        info: Arc<JobInfo>,
+        info: Arc<dyn JobContext>,
+        pool: &WorkerPool,
+        paths: impl Iterator<Item = Result<PathBuf, E>>,
+        opts: &TimelineOptions,
+    ) -> anyhow::Result<Self> {
+        let filename_timestamp = opts.filename_timestamp.clone();
+        let check_packet_timing = opts.check_packet_timing;
+        Self::new_with_processor(info, pool, paths, opts, move |job, path| {
+            TimelineClip::process(job, path, &filename_timestamp, check_packet_timing, false)
+        })
+    }
+    /// like [`Timeline::new`], but with each path turned into a [`TimelineClip`] by
+    /// `process_clip`, so the clips don't have to be probed with ffprobe
+    fn new_with_processor<E: Error + Send + Sync + 'static>(
+        info: Arc<dyn JobContext>,
         pool: &WorkerPool,
         paths: impl Iterator<Item = Result<PathBuf, E>>,
+        opts: &TimelineOptions,
+        process_clip: impl Fn(&dyn JobContext, PathBuf) -> anyhow::Result<TimelineClip>
+            + Send
+            + Sync
+            + 'static,
     ) -> anyhow::Result<Self> {
         info.set_progress(crate::SetProgressInfo {
             progress: Some(0),
@@ -86,55 +805,654 @@ impl Timeline {
             ..Default::default()
         });
 
-        // create and run jobs to process the TimelineClip for each path specified
-        let clips_rx = pool.run_channel(paths.map(|path| {
-            let info_clone = info.clone();
-            move || {
-                let path = path?;
-                TimelineClip::process(&info_clone, path.clone())
-                    .with_context(|| format!("process TimelineClip {:?}", path))
-            }
-        }));
+        // create and run jobs to process the TimelineClip for each path specified, ahead of any
+        // bulk work already queued on the pool
+        let max_duration = opts.max_duration_secs.map(Duration::from_secs);
+        let limit_reached = Arc::new(AtomicBool::new(false));
+        let process_clip = Arc::new(process_clip);
+        let probe_failures = opts.probe_failures;
+        let mut excluded_paths = 0;
+        let paths: Vec<_> = paths
+            .filter(|path| {
+                let excluded = path.as_ref().is_ok_and(|path| opts.excludes_path(path));
+                excluded_paths += usize::from(excluded);
+                !excluded
+            })
+            .collect();
+        // the excluded paths are never probed, so they aren't part of the total. clips left out by
+        // the time filters still are, since their time isn't known until they've been processed
+        info.set_progress(SetProgressInfo {
+            total: Some(paths.len()),
+            ..Default::default()
+        });
+        // paths are only turned into tasks as their results are taken, so a huge archive doesn't
+        // have every task waiting in the queue at once
+        let clips_rx = pool.run_streaming_with(
+            Priority::High,
+            pool.streaming_depth(),
+            paths.into_iter().map(|path| {
+                let info_clone = info.clone();
+                let limit_reached = Arc::clone(&limit_reached);
+                let process_clip = Arc::clone(&process_clip);
+                move || -> anyhow::Result<_> {
+                    let process = || -> anyhow::Result<_> {
+                        // skip probing anything queued after the timeline is already long enough
+                        if limit_reached.load(Ordering::Relaxed) {
+                            return Ok(None);
+                        }
+                        let path = path?;
+                        let wait = || {
+                            info_clone.set_progress(SetProgressInfo::warn(format!(
+                                "failed to process TimelineClip {:?}, retrying",
+                                path
+                            )));
+                            info_clone.interruptible_sleep(PROBE_RETRY_DELAY)
+                        };
+                        let processed = probe_failures
+                            .run(wait, || process_clip(&*info_clone, path.clone()))
+                            .with_context(|| format!("process TimelineClip {:?}", path))?;
+                        Ok(Some(processed.map_err(|e| (path, e))))
+                    };
+                    let processed = process();
+                    // every path counts towards the total however it went, so the bar fills up
+                    info_clone.set_progress(SetProgressInfo {
+                        progress_inc: Some(1),
+                        ..Default::default()
+                    });
+                    processed
+                }
+            }),
+        );
 
         // collect all of the TimelineClips into a vector and sort by creation_time
         let mut timeline_clips = Vec::new();
+        let mut excluded = BTreeMap::<&str, usize>::new();
+        if excluded_paths > 0 {
+            excluded.insert("by the exclusion list", excluded_paths);
+        }
+        let mut skipped = Vec::new();
+        let mut collected_duration = Duration::ZERO;
         for clip in clips_rx {
-            timeline_clips.push(clip?);
+            let clip = match clip? {
+                Some(Ok(clip)) => clip,
+                Some(Err(failed)) => {
+                    skipped.push(failed);
+                    continue;
+                }
+                None => continue,
+            };
+            if limit_reached.load(Ordering::Relaxed) {
+                continue;
+            }
+            match opts.exclusion(clip.creation_time) {
+                Some(reason) => *excluded.entry(reason).or_default() += 1,
+                None => {
+                    collected_duration += clip.length;
+                    timeline_clips.push(clip);
+                }
+            }
+            if max_duration.is_some_and(|max| collected_duration >= max) {
+                limit_reached.store(true, Ordering::Relaxed);
+                info.set_progress(SetProgressInfo::detail(format!(
+                    "stopped collecting clips after {} clips reached the max duration",
+                    timeline_clips.len()
+                )));
+            }
+        }
+        for (reason, count) in excluded {
+            info.set_progress(SetProgressInfo::detail(format!(
+                "excluded {} clips {}",
+                count, reason
+            )));
+        }
+        if !skipped.is_empty() {
+            let report = skipped
+                .iter()
+                .map(|(path, e)| format!("{:?}: {:#}", path, e))
+                .collect::<Vec<_>>()
+                .join("\n");
+            info.set_progress(SetProgressInfo::warn(format!(
+                "skipped {} clips that could not be processed:\n{}",
+                skipped.len(),
+                report
+            )));
+        }
+        if timeline_clips.is_empty() && !skipped.is_empty() {
+            return Err(EmptyTimeline(
+                "every clip was either excluded or could not be processed".into(),
+            )
+            .into());
+        }
+        if timeline_clips.is_empty() {
+            return Err(
+                EmptyTimeline("every clip was excluded by the timeline filters".into()).into(),
+            );
         }
         timeline_clips.sort_unstable_by_key(|x| x.creation_time);
 
-        // finally, create a vec with a duration before the clip
-        let mut duration = Duration::ZERO;
-        let mut clips = Vec::new();
-        for clip in timeline_clips {
-            let len = clip.length;
-            clips.push((duration, clip));
-            duration += len;
+        // flag clips whose timestamps were probably parsed wrong, since they scramble the timelapse
+        if let Some(secs) = opts.out_of_sequence_secs {
+            let times: Vec<_> = timeline_clips.iter().map(|c| c.creation_time).collect();
+            let threshold = i64::try_from(secs)
+                .ok()
+                .and_then(chrono::TimeDelta::try_seconds)
+                .unwrap_or(chrono::TimeDelta::MAX);
+            for i in out_of_sequence_indices(&times, threshold) {
+                info.set_progress(SetProgressInfo::warn(format!(
+                    "clip {:?} is dated {}, which is out of sequence with its neighbors",
+                    timeline_clips[i].path, timeline_clips[i].creation_time
+                )));
+            }
         }
 
+        let timeline = Self::from_sorted_clips(timeline_clips);
         info.set_progress(SetProgressInfo::detail(format!(
             "total combined length of all clips is {:.02}h",
-            duration.as_secs_f64() / 60.0 / 60.0
+            timeline.duration.as_secs_f64() / 60.0 / 60.0
         )));
         info.set_progress(SetProgressInfo::detail("--- Finished clips timeline ---"));
-        Ok(Self { clips, duration })
+        Ok(timeline)
+    }
+    /// lay `clips` end to end, each starting where the one before it ends
+    pub(super) fn from_sorted_clips(sorted_clips: Vec<TimelineClip>) -> Self {
+        let mut duration = Duration::ZERO;
+        let mut clips = Vec::with_capacity(sorted_clips.len());
+        for clip in sorted_clips {
+            let len = clip.length;
+            clips.push((duration, clip));
+            duration += len;
+        }
+        Self { clips, duration }
     }
 
     pub fn get_at(&self, timestamp: Duration) -> (Duration, &TimelineClip) {
-        let idx = match self
-            .clips
-            .binary_search_by_key(&timestamp, |(clip_ts, _)| *clip_ts)
-        {
-            Ok(i) => i,
-            Err(i) => i - 1, // since this is where it should be "inserted", we need the previous one
-        };
+        let idx = self.clip_index_at(timestamp);
         (self.clips[idx].0, &self.clips[idx].1)
     }
+    /// index of the clip that `timestamp` falls within
+    ///
+    /// a timestamp on the boundary between two clips is the start of the later one. zero-length
+    /// clips share their offset with the clip after them, so they are never picked
+    pub fn clip_index_at(&self, timestamp: Duration) -> usize {
+        // the last clip starting at or before the timestamp
+        self.clips
+            .partition_point(|(clip_ts, _)| *clip_ts <= timestamp)
+            .saturating_sub(1)
+    }
     pub fn len(&self) -> Duration {
         self.duration
     }
+    /// number of clips in the timeline
+    pub fn clip_count(&self) -> usize {
+        self.clips.len()
+    }
 
     pub fn iter(&self) -> impl Iterator<Item = &TimelineClip> {
         self.clips.iter().map(|(_, clip)| clip)
     }
+    /// when the first clip started recording and when the last recording stopped
+    pub fn span(&self) -> Option<(chrono::DateTime<chrono::Utc>, chrono::DateTime<chrono::Utc>)> {
+        let earliest = self.iter().next()?.creation_time;
+        // clips are sorted by when they start, so a long one can end after the clips following it
+        let latest = self
+            .iter()
+            .map(|clip| {
+                clip.creation_time
+                    + chrono::TimeDelta::from_std(clip.length).unwrap_or(chrono::TimeDelta::zero())
+            })
+            .max()?;
+        Some((earliest, latest))
+    }
+    /// the offset of the footage recorded at `time`, and whether `time` falls in a gap between
+    /// clips instead
+    ///
+    /// a time in a gap maps to the end of the clip before it, which extracts that clip's last frame
+    pub fn offset_at_time(&self, time: chrono::DateTime<chrono::Utc>) -> (Duration, bool) {
+        // the last clip that started recording at or before `time`
+        let idx = self
+            .clips
+            .partition_point(|(_, clip)| clip.creation_time <= time)
+            .saturating_sub(1);
+        let Some((offset, clip)) = self.clips.get(idx) else {
+            return (Duration::ZERO, false);
+        };
+        let into_clip = (time - clip.creation_time).to_std().unwrap_or_default();
+        match into_clip < clip.length {
+            true => (*offset + into_clip, false),
+            // just before the end is still within the clip, where the frame is its last one
+            false => (
+                *offset + clip.length.saturating_sub(Duration::from_millis(1)),
+                true,
+            ),
+        }
+    }
+    /// when the footage starts and stops, and how much of it there is, or `None` without any clips
+    pub fn date_range(&self) -> Option<DateRange> {
+        let (earliest, latest) = self.span()?;
+        let days = self
+            .iter()
+            .map(|clip| {
+                clip.creation_time
+                    .with_timezone(&CLIP_TIMEZONE)
+                    .date_naive()
+            })
+            .collect::<std::collections::BTreeSet<_>>()
+            .len();
+        Some(DateRange {
+            earliest: earliest.to_rfc3339(),
+            latest: latest.to_rfc3339(),
+            recorded_secs: self.duration.as_secs_f64(),
+            days,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_timestamps, earliest_clip_paths, glob_clip_paths, no_clips_hint,
+        out_of_sequence_indices, DstPolicy, FilenameTimestamp, GoldenHourFilter,
+        ProbeFailurePolicy, TimeOfDayWindow, Timeline, TimelineClip, TimelineOptions,
+        CLIP_TIMEZONE,
+    };
+    use crate::compute::{test_util::ProgressBar, workers::WorkerPool};
+    use chrono::{NaiveTime, TimeDelta, TimeZone, Utc};
+    use std::{
+        path::{Path, PathBuf},
+        sync::Arc,
+        time::Duration,
+    };
+
+    fn count_clips(root: &Path, max_depth: Option<usize>) -> usize {
+        glob_clip_paths(root, max_depth)
+            .unwrap()
+            .map(Result::unwrap)
+            .count()
+    }
+
+    #[test]
+    fn date_range_spans_every_clip() {
+        assert_eq!(Timeline::from_sorted_clips(Vec::new()).date_range(), None);
+
+        let at = |d, h| {
+            CLIP_TIMEZONE
+                .with_ymd_and_hms(2024, 5, d, h, 0, 0)
+                .unwrap()
+                .to_utc()
+        };
+        let clip = |creation_time, mins: u64| TimelineClip {
+            creation_time,
+            length: Duration::from_secs(mins * 60),
+            path: PathBuf::new(),
+            unreliable_timestamps: false,
+        };
+        // the first clip is long enough to end after the second
+        let timeline = Timeline::from_sorted_clips(vec![
+            clip(at(1, 22), 180),
+            clip(at(1, 23), 10),
+            clip(at(4, 9), 5),
+        ]);
+        let range = timeline.date_range().unwrap();
+        assert_eq!(range.earliest, at(1, 22).to_rfc3339());
+        assert_eq!(
+            range.latest,
+            (at(4, 9) + TimeDelta::minutes(5)).to_rfc3339()
+        );
+        assert_eq!(range.recorded_secs, 195.0 * 60.0);
+        assert_eq!(range.days, 2);
+    }
+
+    #[test]
+    fn times_in_gaps_map_to_the_end_of_the_clip_before() {
+        let secs = Duration::from_secs;
+        let clip = |start, len| TimelineClip {
+            creation_time: Utc.timestamp_opt(start, 0).unwrap(),
+            length: secs(len),
+            path: PathBuf::new(),
+            unreliable_timestamps: false,
+        };
+        // recorded from 0s to 10s and 100s to 120s
+        let timeline = Timeline::from_sorted_clips(vec![clip(0, 10), clip(100, 20)]);
+        let at = |t| timeline.offset_at_time(Utc.timestamp_opt(t, 0).unwrap());
+        assert_eq!(at(5), (secs(5), false));
+        assert_eq!(at(50), (secs(10) - Duration::from_millis(1), true));
+        assert_eq!(at(105), (secs(15), false));
+        assert_eq!(at(200), (secs(30) - Duration::from_millis(1), true));
+    }
+
+    #[test]
+    fn clip_boundaries_belong_to_the_later_clip() {
+        let secs = Duration::from_secs;
+        let clips = [10, 0, 5, 20]
+            .into_iter()
+            .enumerate()
+            .map(|(i, len)| TimelineClip {
+                creation_time: Utc.timestamp_opt(i as i64 * 60, 0).unwrap(),
+                length: secs(len),
+                path: PathBuf::from(format!("{i}.mp4")),
+                unreliable_timestamps: false,
+            })
+            .collect();
+        let timeline = Timeline::from_sorted_clips(clips);
+        assert_eq!(timeline.len(), secs(35));
+
+        assert_eq!(timeline.clip_index_at(Duration::ZERO), 0);
+        assert_eq!(
+            timeline.clip_index_at(secs(10) - Duration::from_nanos(1)),
+            0
+        );
+        // the zero-length clip at 10s is skipped over
+        assert_eq!(timeline.clip_index_at(secs(10)), 2);
+        assert_eq!(timeline.clip_index_at(secs(15)), 3);
+        assert_eq!(timeline.get_at(secs(15)).0, secs(15));
+        assert_eq!(timeline.clip_index_at(secs(35)), 3);
+        assert_eq!(timeline.clip_index_at(secs(100)), 3);
+    }
+
+    #[test]
+    fn timestamps_are_checked_without_probing() {
+        let dir = tempfile::tempdir().unwrap();
+        for path in ["2024_0501_120000.mp4", "2024_0501_120100.mp4", "clip.mp4"] {
+            std::fs::write(dir.path().join(path), b"").unwrap();
+        }
+
+        let report = check_timestamps(dir.path(), &TimelineOptions::default()).unwrap();
+        assert_eq!((report.parsed, report.failed), (2, 1));
+        assert_eq!(report.failed_samples.len(), 1);
+        assert!(report.failed_samples[0].starts_with("clip.mp4: "));
+    }
+
+    #[test]
+    fn progress_reaches_the_total_of_unexcluded_clips() {
+        let paths =
+            (0..5).map(|i| Ok::<_, glob::GlobError>(format!("2024_0501_12000{i}_F.mp4").into()));
+        let opts: TimelineOptions = serde_json::from_str(
+            r#"{ "exclude": ["2024_0501_120000_F.mp4", "2024_0501_120004_*"], "probeFailures": "skip" }"#,
+        )
+        .unwrap();
+
+        // none of the clips can be processed, so they are all skipped, but still count as progress
+        let bar = Arc::new(ProgressBar::default());
+        let pool = WorkerPool::new(2);
+        let timeline = Timeline::new_with_processor(bar.clone(), &pool, paths, &opts, |_, path| {
+            anyhow::bail!("{:?} can't be probed", path)
+        });
+        assert!(timeline.is_err());
+        assert_eq!(*bar.0.lock().unwrap(), (3, 3));
+    }
+
+    #[test]
+    fn single_clip_timeline_needs_a_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let opts = TimelineOptions::default();
+        let bar = ProgressBar::default();
+        let err = Timeline::new_from_clip(&bar, dir.path(), &opts)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("is a directory"));
+        let err = Timeline::new_from_clip(&bar, &dir.path().join("missing.mp4"), &opts)
+            .err()
+            .unwrap();
+        assert!(err.to_string().contains("is not a file"));
+    }
+
+    #[test]
+    fn glob_respects_max_depth() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        for path in ["top.mp4", "a/one.MP4", "a/b/two.mp4", "a/b/ignored.txt"] {
+            std::fs::write(root.join(path), b"").unwrap();
+        }
+
+        assert_eq!(count_clips(root, Some(0)), 1);
+        assert_eq!(count_clips(root, Some(1)), 2);
+        assert_eq!(count_clips(root, Some(5)), 3);
+        assert_eq!(count_clips(root, None), 3);
+    }
+
+    #[test]
+    fn probe_failures_follow_the_policy() {
+        let flaky = || {
+            let mut attempts = 0;
+            move || {
+                attempts += 1;
+                match attempts {
+                    1 => anyhow::bail!("corrupt moov atom"),
+                    _ => Ok(attempts),
+                }
+            }
+        };
+        assert!(ProbeFailurePolicy::Abort.run(|| true, flaky()).is_err());
+        assert!(ProbeFailurePolicy::Skip
+            .run(|| true, flaky())
+            .unwrap()
+            .is_err());
+        let retried = ProbeFailurePolicy::RetryThenSkip.run(|| true, flaky());
+        assert_eq!(retried.unwrap().unwrap(), 2);
+        // cancelling during the wait fails instead of skipping
+        assert!(ProbeFailurePolicy::RetryThenSkip
+            .run(|| false, flaky())
+            .is_err());
+    }
+
+    #[test]
+    fn isolated_clip_is_out_of_sequence() {
+        let day = |d: u32| Utc.with_ymd_and_hms(2024, 5, d, 12, 0, 0).unwrap();
+        let mut times = vec![day(1), day(1) + TimeDelta::minutes(5), day(2), day(3)];
+        times.push(Utc.with_ymd_and_hms(2031, 1, 1, 0, 0, 0).unwrap());
+
+        assert_eq!(
+            out_of_sequence_indices(&times, TimeDelta::days(30)),
+            vec![4]
+        );
+        assert!(out_of_sequence_indices(&times[..4], TimeDelta::days(30)).is_empty());
+        assert!(out_of_sequence_indices(&times[..1], TimeDelta::zero()).is_empty());
+    }
+
+    #[test]
+    fn filename_timestamps_use_the_configured_pattern() {
+        let expected = Utc.with_ymd_and_hms(2024, 5, 1, 16, 30, 15).unwrap();
+        let default = FilenameTimestamp::default();
+        assert_eq!(
+            TimelineClip::parse_timestamp_from_path(
+                Path::new("clips/2024_0501_123015_F.MP4"),
+                &default
+            )
+            .unwrap(),
+            expected
+        );
+        assert!(TimelineClip::parse_timestamp_from_path(Path::new("a.mp4"), &default).is_err());
+
+        let custom: FilenameTimestamp = serde_json::from_str(
+            r#"{ "pattern": "^NORM(\\d{8}-\\d{6})", "format": "%Y%m%d-%H%M%S" }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            TimelineClip::parse_timestamp_from_path(
+                Path::new("NORM20240501-123015-000123F.mp4"),
+                &custom
+            )
+            .unwrap(),
+            expected
+        );
+        assert!(serde_json::from_str::<FilenameTimestamp>(r#"{ "pattern": "(" }"#).is_err());
+    }
+
+    #[test]
+    fn same_second_clips_are_ordered_by_millis() {
+        let opts = FilenameTimestamp {
+            millis: true,
+            ..Default::default()
+        };
+        let parse =
+            |name: &str| TimelineClip::parse_timestamp_from_path(Path::new(name), &opts).unwrap();
+        let earlier = parse("2024_0501_123015_250_F.mp4");
+        let later = parse("2024_0501_123015_750_F.mp4");
+
+        assert!(earlier < later);
+        assert_eq!(later - earlier, TimeDelta::milliseconds(500));
+        assert_eq!(
+            parse("2024_0501_123015_F.mp4"),
+            earlier - TimeDelta::milliseconds(250)
+        );
+    }
+
+    #[test]
+    fn dst_changes_resolve_by_policy() {
+        let parse = |name: &str, dst| {
+            let opts = FilenameTimestamp {
+                dst,
+                ..Default::default()
+            };
+            TimelineClip::parse_timestamp_from_path(Path::new(name), &opts).ok()
+        };
+        let utc = |d, h, m| Utc.with_ymd_and_hms(2024, 11, d, h, m, 0).unwrap();
+
+        // 1:30am happens twice when the clocks fall back on 2024-11-03, first in EDT then in EST
+        let repeated = "2024_1103_013000_F.mp4";
+        assert_eq!(
+            parse(repeated, DstPolicy::EarliestValid),
+            Some(utc(3, 5, 30))
+        );
+        assert_eq!(parse(repeated, DstPolicy::LatestValid), Some(utc(3, 6, 30)));
+        assert_eq!(parse(repeated, DstPolicy::Error), None);
+
+        // 2:30am never happens when the clocks spring forward on 2024-03-10
+        let skipped = "2024_0310_023000_F.mp4";
+        let utc = |h, m| Utc.with_ymd_and_hms(2024, 3, 10, h, m, 0).unwrap();
+        assert_eq!(parse(skipped, DstPolicy::EarliestValid), Some(utc(6, 30)));
+        assert_eq!(parse(skipped, DstPolicy::LatestValid), Some(utc(7, 30)));
+        assert_eq!(parse(skipped, DstPolicy::Error), None);
+
+        // times away from a change are unaffected
+        let plain = parse("2024_1104_013000_F.mp4", DstPolicy::Error);
+        assert_eq!(
+            plain,
+            Some(Utc.with_ymd_and_hms(2024, 11, 4, 6, 30, 0).unwrap())
+        );
+    }
+
+    #[test]
+    fn golden_hour_is_around_sunrise_and_sunset() {
+        let filter: GoldenHourFilter = serde_json::from_str(
+            r#"{ "windowMins": 45, "location": { "lat": 40.7128, "lng": -74.006 } }"#,
+        )
+        .unwrap();
+        // sunrise is around 5:25am and sunset around 8:31pm
+        let at = |h, m| {
+            CLIP_TIMEZONE
+                .with_ymd_and_hms(2024, 6, 21, h, m, 0)
+                .unwrap()
+        };
+        assert!(filter.contains(at(5, 0)));
+        assert!(filter.contains(at(20, 50)));
+        assert!(!filter.contains(at(12, 0)));
+        assert!(!filter.contains(at(19, 0)));
+
+        // without a location, the fallback clock times stand in
+        let fallback = GoldenHourFilter::default();
+        assert!(fallback.contains(at(19, 0)));
+        assert!(!fallback.contains(at(20, 50)));
+    }
+
+    #[test]
+    fn exclusion_list_matches_paths_and_filename_patterns() {
+        let opts: TimelineOptions = serde_json::from_str(
+            r#"{ "exclude": ["clips/a/2024_0501_120000_F.mp4", "2024_0502_*", "odd[1].mp4"] }"#,
+        )
+        .unwrap();
+
+        assert!(opts.excludes_path(Path::new("clips/a/2024_0501_120000_F.mp4")));
+        assert!(!opts.excludes_path(Path::new("clips/b/2024_0501_120000_F.mp4")));
+        assert!(opts.excludes_path(Path::new("clips/b/2024_0502_080000_F.mp4")));
+        assert!(opts.excludes_path(Path::new("odd[1].mp4")));
+        assert!(!opts.excludes_path(Path::new("clips/2024_0503_080000_F.mp4")));
+    }
+
+    #[test]
+    fn filename_exclusions_include_the_time_filters() {
+        // 2024-05-01 is a wednesday
+        let opts: TimelineOptions =
+            serde_json::from_str(r#"{ "exclude": ["2024_0502_*"], "weekdays": ["wed", "thu"] }"#)
+                .unwrap();
+
+        assert!(!opts.excludes_path_by_filename(Path::new("2024_0501_120000_F.mp4")));
+        assert!(opts.excludes_path_by_filename(Path::new("2024_0502_120000_F.mp4")));
+        assert!(opts.excludes_path_by_filename(Path::new("2024_0503_120000_F.mp4")));
+        // clips without a timestamp are kept, since they're dated by probing them
+        assert!(!opts.excludes_path_by_filename(Path::new("clip.mp4")));
+    }
+
+    #[test]
+    fn limited_paths_are_the_earliest_by_filename() {
+        let paths = [
+            "b/2024_0102_000000_F.mp4",
+            "a/2024_0103_000000_F.mp4",
+            "c/2024_0101_000000_F.mp4",
+        ]
+        .map(PathBuf::from)
+        .to_vec();
+
+        assert_eq!(
+            earliest_clip_paths(paths.clone(), Some(2)),
+            [
+                PathBuf::from("c/2024_0101_000000_F.mp4"),
+                PathBuf::from("b/2024_0102_000000_F.mp4"),
+            ]
+        );
+        assert_eq!(earliest_clip_paths(paths, None).len(), 3);
+    }
+
+    #[test]
+    fn no_clips_hint_mentions_other_extensions() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        std::fs::create_dir_all(root.join("a/b")).unwrap();
+        for path in ["one.MOV", "a/two.mov", "a/b/three.avi", "a/b/deep.mp4"] {
+            std::fs::write(root.join(path), b"").unwrap();
+        }
+
+        assert_eq!(
+            no_clips_hint(root, Some(1)),
+            "found 1 .mp4 files nested deeper than the max depth of 1"
+        );
+        std::fs::remove_file(root.join("a/b/deep.mp4")).unwrap();
+        assert_eq!(
+            no_clips_hint(root, None),
+            "found 2 .mov, 1 .avi files but only .mp4 clips are supported"
+        );
+    }
+
+    #[test]
+    fn time_of_day_window_wraps_past_midnight() {
+        let at = |h| NaiveTime::from_hms_opt(h, 0, 0).unwrap();
+        let day = TimeOfDayWindow {
+            start: at(6),
+            end: at(20),
+        };
+        assert!(day.contains(at(6)) && day.contains(at(12)));
+        assert!(!day.contains(at(20)) && !day.contains(at(2)));
+
+        let night = TimeOfDayWindow {
+            start: at(20),
+            end: at(6),
+        };
+        assert!(night.contains(at(23)) && night.contains(at(2)));
+        assert!(!night.contains(at(12)));
+    }
+
+    #[test]
+    fn weekday_filter_uses_clip_timezone() {
+        let opts: TimelineOptions =
+            serde_json::from_str(r#"{ "weekdays": ["mon", "Tuesday"] }"#).unwrap();
+        // late monday night in new york is already tuesday in utc
+        let monday_night = Utc.with_ymd_and_hms(2024, 5, 7, 3, 0, 0).unwrap();
+        let saturday = Utc.with_ymd_and_hms(2024, 5, 11, 12, 0, 0).unwrap();
+        assert_eq!(opts.exclusion(monday_night), None);
+        assert!(opts.exclusion(saturday).is_some());
+        assert!(
+            serde_json::from_str::<TimelineOptions>(r#"{ "weekdays": ["caturday"] }"#).is_err()
+        );
+    }
 }
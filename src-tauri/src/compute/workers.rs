@@ -1,7 +1,12 @@
 use std::{
     collections::{BTreeMap, VecDeque},
-    sync::{mpsc, Arc, Condvar, Mutex},
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Condvar, Mutex,
+    },
     thread,
+    time::Instant,
 };
 
 trait JobFn: Send {
@@ -19,9 +24,24 @@ where
 
 type Job = Box<dyn JobFn>;
 
+/// which queue a task waits in, where every high priority task runs before any normal one
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// latency-sensitive work, like building a timeline
+    High,
+    /// bulk work, like extracting timelapse frames
+    #[default]
+    Normal,
+}
+
 struct State {
+    high: VecDeque<Job>,
     queue: VecDeque<Job>,
     shutdown: bool,
+    /// number of jobs currently running
+    running: usize,
+    /// max number of jobs allowed to run at once, which is at most the number of threads
+    limit: usize,
 }
 
 struct Inner {
@@ -30,53 +50,99 @@ struct Inner {
 }
 
 impl Inner {
-    fn new() -> Self {
+    fn new(limit: usize) -> Self {
         Self {
             state: Mutex::new(State {
+                high: VecDeque::new(),
                 queue: VecDeque::new(),
                 shutdown: false,
+                running: 0,
+                limit,
             }),
             available: Condvar::new(),
         }
     }
 
-    fn push(&self, job: Job) {
+    fn push(&self, job: Job, priority: Priority) {
         let mut state = self.state.lock().unwrap();
         if state.shutdown {
             return;
         }
-        state.queue.push_back(job);
+        match priority {
+            Priority::High => state.high.push_back(job),
+            Priority::Normal => state.queue.push_back(job),
+        }
         self.available.notify_one();
     }
 
     fn next_job(&self) -> Option<Job> {
         let mut state = self.state.lock().unwrap();
         loop {
-            if let Some(job) = state.queue.pop_front() {
-                return Some(job);
+            if state.running < state.limit {
+                if let Some(job) = state.high.pop_front().or_else(|| state.queue.pop_front()) {
+                    state.running += 1;
+                    return Some(job);
+                }
             }
-            if state.shutdown {
+            if state.shutdown && state.high.is_empty() && state.queue.is_empty() {
                 return None;
             }
             state = self.available.wait(state).unwrap();
         }
     }
+
+    fn finish_job(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.running -= 1;
+        self.available.notify_one();
+    }
+
+    fn set_limit(&self, limit: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.limit = limit;
+        self.available.notify_all();
+    }
+
+    fn shutdown(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.shutdown = true;
+        self.available.notify_all();
+    }
 }
 
 fn worker_loop(inner: Arc<Inner>) {
     while let Some(job) = inner.next_job() {
-        job.call();
+        // a panicking task has already been reported by the panic hook, and shouldn't take the
+        // thread and its slot in the pool down with it
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| job.call()));
+        inner.finish_job();
     }
 }
 
+/// the result of a task, where a panic carries on in whoever is taking the results
+fn resume_panic<R>(result: thread::Result<R>) -> R {
+    result.unwrap_or_else(|payload| panic::resume_unwind(payload))
+}
+
+/// tasks in flight per thread at the `streaming_depth`
+pub const STREAMING_TASKS_PER_THREAD: usize = 4;
+
 pub struct WorkerPool {
     inner: Arc<Inner>,
+    threads: usize,
+}
+
+impl Drop for WorkerPool {
+    fn drop(&mut self) {
+        // workers finish whatever is already queued, then exit
+        self.inner.shutdown();
+    }
 }
 
 impl WorkerPool {
     pub fn new(threads: usize) -> Self {
         let thread_count = threads.max(1);
-        let inner = Arc::new(Inner::new());
+        let inner = Arc::new(Inner::new(thread_count));
         let mut handles = Vec::with_capacity(thread_count);
 
         for _ in 0..thread_count {
@@ -84,32 +150,55 @@ impl WorkerPool {
             handles.push(thread::spawn(move || worker_loop(inner_clone)));
         }
 
-        Self { inner }
+        Self {
+            inner,
+            threads: thread_count,
+        }
     }
 
-    fn enqueue_job(&self, job: Job) {
-        self.inner.push(job);
+    /// cap how many tasks run at once, between 1 and the number of threads
+    ///
+    /// returns the concurrency that was actually set
+    pub fn set_concurrency(&self, concurrency: usize) -> usize {
+        let concurrency = concurrency.clamp(1, self.threads);
+        self.inner.set_limit(concurrency);
+        concurrency
+    }
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+    /// a good `in_flight` for streaming tasks, which keeps every thread busy with a few tasks
+    /// queued up behind each
+    pub fn streaming_depth(&self) -> usize {
+        self.threads * STREAMING_TASKS_PER_THREAD
     }
 
-    pub fn run_ordered_channel<F, I, R>(&self, tasks: I) -> mpsc::Receiver<R>
+    fn enqueue_job(&self, job: Job, priority: Priority) {
+        self.inner.push(job, priority);
+    }
+
+    /// the results of `tasks` in submission order, with a panicking task panicking again when its
+    /// result is taken
+    pub fn run_ordered_channel<F, I, R>(&self, tasks: I) -> impl Iterator<Item = R>
     where
         I: IntoIterator<Item = F>,
         F: FnOnce() -> R + Send + 'static,
         R: Send + 'static,
     {
-        let unordered_rx = self.run_indexed_channel(tasks);
+        let unordered_rx = self.run_indexed_channel(Priority::Normal, tasks);
 
         // spawn another thread for organizing the jobs back in-order
         let (ordered_tx, ordered_rx) = mpsc::channel();
         thread::spawn(move || {
             let mut next_expected = 0usize;
-            let mut buffer: BTreeMap<usize, R> = BTreeMap::new();
+            let mut buffer: BTreeMap<usize, thread::Result<R>> = BTreeMap::new();
 
             for (idx, result) in unordered_rx {
                 buffer.insert(idx, result);
 
                 while let Some(result) = buffer.remove(&next_expected) {
                     if ordered_tx.send(result).is_err() {
+                        // the consumer is gone, and dropping `unordered_rx` makes the rest skip
                         return;
                     }
                     next_expected += 1;
@@ -119,7 +208,7 @@ impl WorkerPool {
             drop(ordered_tx);
         });
 
-        ordered_rx
+        ordered_rx.into_iter().map(resume_panic)
     }
 
     pub fn run_channel<F, I, R>(&self, tasks: I) -> impl Iterator<Item = R>
@@ -128,34 +217,269 @@ impl WorkerPool {
         F: FnOnce() -> R + Send + 'static,
         R: Send + 'static,
     {
-        self.run_indexed_channel(tasks).into_iter().map(|tup| tup.1)
+        self.run_channel_with(Priority::Normal, tasks)
+    }
+
+    /// like `run_channel`, but the tasks are queued with `priority`
+    pub fn run_channel_with<F, I, R>(&self, priority: Priority, tasks: I) -> impl Iterator<Item = R>
+    where
+        I: IntoIterator<Item = F>,
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        self.run_indexed_channel(priority, tasks)
+            .into_iter()
+            .map(|(_, result)| resume_panic(result))
+    }
+
+    /// like `run_channel_with`, but tasks are only taken from `tasks` and queued as results are
+    /// taken, keeping at most `in_flight` outstanding
+    pub fn run_streaming_with<F, I, R>(
+        &self,
+        priority: Priority,
+        in_flight: usize,
+        tasks: I,
+    ) -> Streaming<I::IntoIter, R>
+    where
+        I: IntoIterator<Item = F>,
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (tx, rx) = mpsc::channel();
+        Streaming {
+            inner: Arc::clone(&self.inner),
+            priority,
+            tasks: tasks.into_iter().enumerate(),
+            tx,
+            rx,
+            in_flight: in_flight.max(1),
+            outstanding: 0,
+            reorder: None,
+        }
+    }
+    /// like `run_ordered_channel`, but tasks are only taken from `tasks` and queued as results
+    /// are taken, keeping at most `in_flight` outstanding
+    ///
+    /// a slow task holds up the results after it, so `in_flight` should leave room for the rest
+    /// of the pool to work ahead of it
+    pub fn run_ordered_streaming<F, I, R>(
+        &self,
+        in_flight: usize,
+        tasks: I,
+    ) -> Streaming<I::IntoIter, R>
+    where
+        I: IntoIterator<Item = F>,
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        Streaming {
+            reorder: Some((0, BTreeMap::new())),
+            ..self.run_streaming_with(Priority::Normal, in_flight, tasks)
+        }
     }
 
-    fn run_indexed_channel<F, I, R>(&self, tasks: I) -> mpsc::Receiver<(usize, R)>
+    fn run_indexed_channel<F, I, R>(
+        &self,
+        priority: Priority,
+        tasks: I,
+    ) -> mpsc::Receiver<(usize, thread::Result<R>)>
     where
         I: IntoIterator<Item = F>,
         F: FnOnce() -> R + Send + 'static,
         R: Send + 'static,
     {
-        let (unordered_tx, unordered_rx) = mpsc::channel::<(usize, R)>();
+        // results are sent even when a task panics, since a missing one would hold up every result
+        // after it in the ordered channel
+        let (unordered_tx, unordered_rx) = mpsc::channel();
+        // set once the results stop being received, so the rest of the tasks are skipped instead
+        // of keeping the workers busy on results nobody will read
+        let abandoned = Arc::new(AtomicBool::new(false));
 
         // enqueue all jobs then close the sender
         for (idx, task) in tasks.into_iter().enumerate() {
             let ordered_tx = unordered_tx.clone();
+            let abandoned = Arc::clone(&abandoned);
             let job: Job = Box::new(move || {
-                let result = task();
-                let _ = ordered_tx.send((idx, result));
+                if abandoned.load(Ordering::Relaxed) {
+                    return;
+                }
+                let result = panic::catch_unwind(AssertUnwindSafe(task));
+                if ordered_tx.send((idx, result)).is_err() {
+                    abandoned.store(true, Ordering::Relaxed);
+                }
             });
-            self.enqueue_job(job);
+            self.enqueue_job(job, priority);
         }
         unordered_rx
     }
 }
 
+/// the results of tasks that are only queued on the pool as earlier results are taken, so that at
+/// most `in_flight` of them are queued, running, or waiting to be taken at once
+///
+/// unlike the channels, which queue every task up-front, the state captured by later tasks isn't
+/// created until they're queued, and a slow consumer can't fall arbitrarily far behind
+pub struct Streaming<I: Iterator, R> {
+    inner: Arc<Inner>,
+    priority: Priority,
+    tasks: std::iter::Enumerate<I>,
+    /// results are sent even when a task panics, since `tx` keeps the channel open and a
+    /// missing one would be waited for forever
+    tx: mpsc::Sender<(usize, thread::Result<R>)>,
+    rx: mpsc::Receiver<(usize, thread::Result<R>)>,
+    in_flight: usize,
+    /// tasks queued that haven't been taken yet
+    outstanding: usize,
+    /// the index of the next result, and the results that came back ahead of it, when the results
+    /// are taken in submission order
+    reorder: Option<(usize, BTreeMap<usize, R>)>,
+}
+impl<I, F, R> Streaming<I, R>
+where
+    I: Iterator<Item = F>,
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    /// queue tasks until `in_flight` are outstanding or there are none left
+    fn fill(&mut self) {
+        while self.outstanding < self.in_flight {
+            let Some((idx, task)) = self.tasks.next() else {
+                return;
+            };
+            let tx = self.tx.clone();
+            let job: Job = Box::new(move || {
+                let result = panic::catch_unwind(AssertUnwindSafe(task));
+                // the send only fails once the results are dropped, and then nobody wants it
+                let _ = tx.send((idx, result));
+            });
+            self.inner.push(job, self.priority);
+            self.outstanding += 1;
+        }
+    }
+}
+impl<I, F, R> Iterator for Streaming<I, R>
+where
+    I: Iterator<Item = F>,
+    F: FnOnce() -> R + Send + 'static,
+    R: Send + 'static,
+{
+    type Item = R;
+
+    fn next(&mut self) -> Option<R> {
+        self.fill();
+        loop {
+            if let Some((next, buffer)) = &mut self.reorder {
+                if let Some(result) = buffer.remove(next) {
+                    *next += 1;
+                    self.outstanding -= 1;
+                    return Some(result);
+                }
+            }
+            if self.outstanding == 0 {
+                return None;
+            }
+            let (idx, result) = self.rx.recv().ok()?;
+            let result = resume_panic(result);
+            match &mut self.reorder {
+                Some((_, buffer)) => {
+                    buffer.insert(idx, result);
+                }
+                None => {
+                    self.outstanding -= 1;
+                    return Some(result);
+                }
+            }
+        }
+    }
+}
+
+/// tunes a pool's concurrency by hill climbing on the rate that tasks are finished at
+///
+/// subprocesses like ffmpeg contend for disk and cpu, so past some point running more of them at
+/// once makes each one slower without finishing any more per second. the pool gets every thread
+/// back once this is dropped, however the job using it ends
+pub struct AdaptiveConcurrency<'a> {
+    pool: &'a WorkerPool,
+    concurrency: usize,
+    /// whether the last adjustment was up or down
+    increasing: bool,
+    window_start: Instant,
+    window_tasks: usize,
+    last_rate: Option<f64>,
+}
+impl<'a> AdaptiveConcurrency<'a> {
+    /// how many tasks each measurement covers, per concurrent task
+    const TASKS_PER_WINDOW: usize = 4;
+    /// how much the rate has to change by before it counts as better or worse
+    const TOLERANCE: f64 = 0.05;
+
+    /// start out at half of the pool's threads, leaving room to go either way
+    pub fn new(pool: &'a WorkerPool) -> Self {
+        Self {
+            pool,
+            concurrency: pool.set_concurrency(pool.threads().div_ceil(2)),
+            increasing: true,
+            window_start: Instant::now(),
+            window_tasks: 0,
+            last_rate: None,
+        }
+    }
+
+    /// record that a task finished, returning the concurrency and task rate if it was changed
+    pub fn task_finished(&mut self) -> Option<(usize, f64)> {
+        self.window_tasks += 1;
+        if self.window_tasks < self.concurrency * Self::TASKS_PER_WINDOW {
+            return None;
+        }
+        let rate = self.window_tasks as f64 / self.window_start.elapsed().as_secs_f64();
+        self.window_start = Instant::now();
+        self.window_tasks = 0;
+
+        match self.last_rate {
+            // keep going the same way while it helps, and turn around once it hurts
+            Some(last) if rate < last * (1.0 - Self::TOLERANCE) => {
+                self.increasing = !self.increasing
+            }
+            Some(last) if rate <= last * (1.0 + Self::TOLERANCE) => {
+                self.last_rate = Some(rate);
+                return None;
+            }
+            _ => {}
+        }
+        self.last_rate = Some(rate);
+
+        let next = match self.increasing {
+            true => self.concurrency + 1,
+            false => self.concurrency.saturating_sub(1),
+        };
+        let next = self.pool.set_concurrency(next);
+        if next == self.concurrency {
+            // pinned at either end, so the only way to go is back
+            self.increasing = !self.increasing;
+            return None;
+        }
+        self.concurrency = next;
+        Some((next, rate))
+    }
+}
+impl Drop for AdaptiveConcurrency<'_> {
+    fn drop(&mut self) {
+        // the pool is shared with the rest of the job, which expects every thread back
+        self.pool.set_concurrency(self.pool.threads());
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use super::WorkerPool;
-    use std::{thread, time::Duration};
+    use super::{AdaptiveConcurrency, Priority, WorkerPool};
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            mpsc, Arc, Mutex,
+        },
+        thread,
+        time::Duration,
+    };
 
     #[test]
     fn returns_results_in_submission_order() {
@@ -168,7 +492,7 @@ mod tests {
             }
         }));
 
-        let collected: Vec<u64> = receiver.into_iter().collect();
+        let collected: Vec<u64> = receiver.collect();
         assert_eq!(collected, vec![30, 5, 15]);
     }
 
@@ -176,7 +500,7 @@ mod tests {
     fn handles_empty_task_list() {
         let pool = WorkerPool::new(4);
         let receiver = pool.run_ordered_channel(std::iter::empty::<fn() -> u8>());
-        let results: Vec<u8> = receiver.into_iter().collect();
+        let results: Vec<u8> = receiver.collect();
         assert!(results.is_empty());
     }
 
@@ -190,11 +514,174 @@ mod tests {
                 move || value
             }));
 
-            let collected: Vec<i32> = receiver.into_iter().collect();
+            let collected: Vec<i32> = receiver.collect();
             assert_eq!(
                 collected,
                 (0..5).map(|n| round * 10 + n).collect::<Vec<_>>()
             );
         }
     }
+
+    #[test]
+    fn dropping_the_receiver_skips_remaining_tasks() {
+        let pool = WorkerPool::new(1);
+        let ran = Arc::new(AtomicUsize::new(0));
+        // each task waits to be let through, so it's known which have run when the receiver is
+        // dropped
+        let (release, gate) = mpsc::sync_channel(0);
+        let gate = Arc::new(Mutex::new(gate));
+
+        let mut receiver = pool.run_channel((0..100).map(|n| {
+            let ran = Arc::clone(&ran);
+            let gate = Arc::clone(&gate);
+            move || {
+                ran.fetch_add(1, Ordering::Relaxed);
+                gate.lock().unwrap().recv().unwrap();
+                n
+            }
+        }));
+        release.send(()).unwrap();
+        assert_eq!(receiver.next(), Some(0));
+        drop(receiver);
+        // the second task finds nobody listening, so every task after it is skipped
+        release.send(()).unwrap();
+
+        let mut next = pool.run_channel([|| "next"]);
+        assert_eq!(next.next(), Some("next"));
+        assert_eq!(ran.load(Ordering::Relaxed), 2);
+    }
+
+    #[test]
+    fn streaming_keeps_tasks_in_flight_bounded() {
+        let pool = WorkerPool::new(4);
+        let created = Arc::new(AtomicUsize::new(0));
+        let taken = Arc::new(AtomicUsize::new(0));
+
+        let tasks = (0..20u64).map(|n| {
+            // every task is created on the consumer's thread, so this sees the last take
+            let ahead = created.fetch_add(1, Ordering::SeqCst) - taken.load(Ordering::SeqCst);
+            assert!(ahead < 3, "{ahead} tasks were outstanding");
+            move || {
+                thread::sleep(Duration::from_millis(5 * (n % 3)));
+                n
+            }
+        });
+        let mut results = Vec::new();
+        for n in pool.run_ordered_streaming(3, tasks) {
+            taken.fetch_add(1, Ordering::SeqCst);
+            results.push(n);
+        }
+        assert_eq!(results, (0..20).collect::<Vec<_>>());
+
+        let mut unordered: Vec<_> = pool
+            .run_streaming_with(Priority::High, 2, (0..10).map(|n| move || n))
+            .collect();
+        unordered.sort_unstable();
+        assert_eq!(unordered, (0..10).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn panicking_tasks_reach_the_consumer() {
+        let pool = WorkerPool::new(1);
+        let tasks = (0..3).map(|n| {
+            move || {
+                assert_ne!(n, 1, "task panicked");
+                n
+            }
+        });
+        let mut results = pool.run_ordered_streaming(2, tasks);
+        assert_eq!(results.next(), Some(0));
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| results.next()));
+        assert!(panicked.is_err());
+
+        // the worker survived the panic
+        let mut next = pool.run_ordered_channel([|| "next"]);
+        assert_eq!(next.next(), Some("next"));
+    }
+
+    #[test]
+    fn panicking_tasks_in_an_ordered_run_reach_the_consumer() {
+        let pool = WorkerPool::new(2);
+        let tasks = (0..4).map(|n| {
+            move || {
+                assert_ne!(n, 1, "task panicked");
+                n
+            }
+        });
+        let mut results = pool.run_ordered_channel(tasks);
+        assert_eq!(results.next(), Some(0));
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| results.next()));
+        assert!(panicked.is_err());
+        // the results after the panicking task aren't lost
+        assert_eq!(results.collect::<Vec<_>>(), [2, 3]);
+
+        let unordered = pool.run_channel((0..2).map(|n| {
+            move || {
+                assert_ne!(n, 1, "task panicked");
+                n
+            }
+        }));
+        let taken = std::panic::catch_unwind(|| unordered.collect::<Vec<_>>());
+        assert!(taken.is_err());
+    }
+
+    #[test]
+    fn adaptive_concurrency_gives_every_thread_back_when_dropped() {
+        let pool = WorkerPool::new(4);
+        let limit = || pool.inner.state.lock().unwrap().limit;
+        let adaptive = AdaptiveConcurrency::new(&pool);
+        assert_eq!(limit(), 2);
+        drop(adaptive);
+        assert_eq!(limit(), 4);
+    }
+
+    #[test]
+    fn concurrency_limits_tasks_running_at_once() {
+        let pool = WorkerPool::new(4);
+        assert_eq!(pool.set_concurrency(0), 1);
+        assert_eq!(pool.set_concurrency(2), 2);
+
+        let running = Arc::new(AtomicUsize::new(0));
+        let max_running = Arc::new(AtomicUsize::new(0));
+        let results = pool.run_channel((0..8).map(|_| {
+            let running = Arc::clone(&running);
+            let max_running = Arc::clone(&max_running);
+            move || {
+                let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+                max_running.fetch_max(now, Ordering::SeqCst);
+                thread::sleep(Duration::from_millis(20));
+                running.fetch_sub(1, Ordering::SeqCst);
+            }
+        }));
+        assert_eq!(results.count(), 8);
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn high_priority_tasks_run_first() {
+        let pool = WorkerPool::new(1);
+        let order = Arc::new(Mutex::new(Vec::new()));
+
+        // occupy the only worker so everything after this is queued up
+        let (release_tx, release_rx) = mpsc::channel::<()>();
+        let blocker = pool.run_channel([move || {
+            release_rx.recv().unwrap();
+        }]);
+
+        let record = |label: &'static str| {
+            let order = Arc::clone(&order);
+            move || order.lock().unwrap().push(label)
+        };
+        let normal = pool.run_channel([record("normal 1"), record("normal 2")]);
+        let high = pool.run_channel_with(Priority::High, [record("high 1"), record("high 2")]);
+
+        release_tx.send(()).unwrap();
+        blocker.for_each(drop);
+        normal.for_each(drop);
+        high.for_each(drop);
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec!["high 1", "high 2", "normal 1", "normal 2"]
+        );
+    }
 }
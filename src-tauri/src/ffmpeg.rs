@@ -1,15 +1,22 @@
 use std::{
+    cell::RefCell,
+    ffi::OsStr,
     fs,
-    io::{Read, Write},
+    io::{self, Read, Write},
     path::{Path, PathBuf},
-    process::{Child, Command, Stdio},
-    sync::OnceLock,
+    process::{Child, Command, Output, Stdio},
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Mutex, OnceLock,
+    },
     time::Duration,
 };
 
 use anyhow::{anyhow, Context};
+#[cfg(feature = "app")]
 use tauri::{path::BaseDirectory, AppHandle, Manager};
 
+#[cfg(feature = "app")]
 cfg_if::cfg_if! {
     if #[cfg(target_os = "macos")] {
         const FFMPEG_RELATIVE_PATH: &str = "resources/bin/mac/ffmpeg";
@@ -34,15 +41,22 @@ fn binaries() -> &'static Binaries {
     BINARIES.get().expect("binaries set by lib.rs")
 }
 
+/// use the ffmpeg and ffprobe binaries bundled with the app
+#[cfg(feature = "app")]
 pub fn set_paths(app: &AppHandle) -> anyhow::Result<()> {
+    set_binary_paths(
+        resolve_resource(app, FFMPEG_RELATIVE_PATH)?,
+        resolve_resource(app, FFPROBE_RELATIVE_PATH)?,
+    )
+}
+/// use the ffmpeg and ffprobe binaries at these paths, for running without the app's resources
+pub fn set_binary_paths(ffmpeg: PathBuf, ffprobe: PathBuf) -> anyhow::Result<()> {
     BINARIES
-        .set(Binaries {
-            ffmpeg: resolve_resource(app, FFMPEG_RELATIVE_PATH)?,
-            ffprobe: resolve_resource(app, FFPROBE_RELATIVE_PATH)?,
-        })
+        .set(Binaries { ffmpeg, ffprobe })
         .map_err(|_| anyhow::anyhow!("ffmpeg::BINARIES is already set"))?;
     Ok(())
 }
+#[cfg(feature = "app")]
 fn resolve_resource(app: &AppHandle, relative: &str) -> anyhow::Result<PathBuf> {
     match app.path().resolve(relative, BaseDirectory::Resource) {
         Ok(path) => Ok(path),
@@ -69,6 +83,64 @@ fn command_for(path: &Path) -> Command {
     cmd
 }
 
+static VERBOSE: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// warnings from the ffmpeg runs on this thread, until taken by `take_diagnostics`
+    static DIAGNOSTICS: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+}
+
+/// run ffmpeg and ffprobe with `-v warning` and keep what they print, to diagnose odd clips
+///
+/// the video encoder always stays at `-v error`, since its output is only read once it finishes
+#[cfg(feature = "app")]
+pub fn set_verbose(verbose: bool) {
+    VERBOSE.store(verbose, Ordering::Relaxed);
+}
+fn log_level() -> &'static str {
+    if VERBOSE.load(Ordering::Relaxed) {
+        "warning"
+    } else {
+        "error"
+    }
+}
+/// keep the output of a successful run, which only has anything in it in verbose mode
+fn record_diagnostics(stderr: &[u8]) {
+    let stderr = String::from_utf8_lossy(stderr);
+    let stderr = stderr.trim();
+    if !stderr.is_empty() {
+        DIAGNOSTICS.with_borrow_mut(|d| d.push(stderr.to_string()));
+    }
+}
+/// take the output of every successful ffmpeg run on this thread since the last call
+pub fn take_diagnostics() -> Vec<String> {
+    DIAGNOSTICS.take()
+}
+
+/// default for how far before the end of a clip `extract_last_frame` starts decoding
+pub const DEFAULT_LAST_FRAME_SEEK: Duration = Duration::from_secs(3);
+static LAST_FRAME_SEEK_MILLIS: AtomicU64 =
+    AtomicU64::new(DEFAULT_LAST_FRAME_SEEK.as_millis() as u64);
+
+/// how far before the end of a clip to start decoding when looking for its last frame
+///
+/// further back is slower, but finds the last frame of clips with a long undecodable tail
+#[cfg(feature = "app")]
+pub fn set_last_frame_seek(seek: Duration) {
+    LAST_FRAME_SEEK_MILLIS.store(seek.as_millis() as u64, Ordering::Relaxed);
+}
+/// the `-sseof` offset for a clip of `duration`, which never seeks past its start
+///
+/// `None` means the whole clip is decoded, when it is shorter than a frame or its length is unknown
+fn last_frame_seek(duration: Option<Duration>) -> Option<Duration> {
+    let seek = Duration::from_millis(LAST_FRAME_SEEK_MILLIS.load(Ordering::Relaxed));
+    let seek = match duration {
+        Some(duration) => seek.min(duration),
+        None => seek,
+    };
+    (seek >= Duration::from_millis(1)).then_some(seek)
+}
+
 #[derive(Debug, serde::Deserialize)]
 struct ProbeDurOutput {
     format: FFProbeFormat,
@@ -88,7 +160,7 @@ pub fn probe(path: &Path) -> anyhow::Result<ProbeInfo> {
     #[rustfmt::skip]
     let result = command_for(&bins.ffprobe)
         .args([
-            "-v", "error",
+            "-v", log_level(),
             "-select_streams", "v:0",
             "-probesize", "32k",
             "-show_entries", "format",
@@ -106,6 +178,8 @@ pub fn probe(path: &Path) -> anyhow::Result<ProbeInfo> {
         )
     }
 
+    record_diagnostics(&result.stderr);
+
     // parse the json output from ffprobe for the duration
     let output =
         serde_json::from_slice::<ProbeDurOutput>(&result.stdout).context("parse ProbeDurOutput")?;
@@ -121,21 +195,395 @@ pub fn probe(path: &Path) -> anyhow::Result<ProbeInfo> {
     })
 }
 
-pub fn extract_frame(input: &Path, at: Duration) -> anyhow::Result<Vec<u8>> {
+/// how many times the decode timestamps of the video packets in `path` jump backwards or repeat,
+/// which makes seeking by timestamp land on the wrong frames
+///
+/// every packet of the clip is read, but none are decoded
+pub fn timestamp_discontinuities(path: &Path) -> anyhow::Result<usize> {
+    #[rustfmt::skip]
+    let result = command_for(&binaries().ffprobe)
+        .args([
+            "-v", log_level(),
+            "-select_streams", "v:0",
+            "-show_entries", "packet=dts",
+            "-of", "csv=p=0",
+        ])
+        .arg(path)
+        .output()
+        .context("execute packet probe")?;
+    if !result.status.success() {
+        anyhow::bail!(
+            "ffprobe for packet timing failed: {}",
+            String::from_utf8_lossy(&result.stderr)
+        )
+    }
+
+    record_diagnostics(&result.stderr);
+    Ok(count_dts_resets(&String::from_utf8_lossy(&result.stdout)))
+}
+/// count the packets in ffprobe's csv of `dts` values that aren't after the packet before them,
+/// skipping packets without one
+fn count_dts_resets(csv: &str) -> usize {
+    let mut prev = None;
+    let mut resets = 0;
+    for dts in csv
+        .lines()
+        .filter_map(|line| line.trim().trim_end_matches(',').parse::<i64>().ok())
+    {
+        if prev.is_some_and(|prev| dts <= prev) {
+            resets += 1;
+        }
+        prev = Some(dts);
+    }
+    resets
+}
+
+/// sections that can be requested from `probe_raw`
+#[cfg(feature = "app")]
+const PROBE_SECTIONS: &[&str] = &[
+    "format",
+    "format_tags",
+    "stream",
+    "stream_tags",
+    "stream_disposition",
+    "streams",
+    "chapters",
+    "programs",
+];
+#[cfg(feature = "app")]
+const DEFAULT_PROBE_ENTRIES: &str = "format:stream";
+
+/// check that `entries` is only a `-show_entries` list of known sections, like `format:stream=codec_name,width`
+#[cfg(feature = "app")]
+fn validate_show_entries(entries: &str) -> anyhow::Result<()> {
+    for section_entries in entries.split(':') {
+        let (section, names) = section_entries
+            .split_once('=')
+            .unwrap_or((section_entries, ""));
+        if !PROBE_SECTIONS.contains(&section) {
+            anyhow::bail!("unknown ffprobe section {:?}", section);
+        }
+        let valid_name = |name: &str| {
+            !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        };
+        if !names.is_empty() && !names.split(',').all(valid_name) {
+            anyhow::bail!("invalid ffprobe entries {:?}", names);
+        }
+    }
+    Ok(())
+}
+
+/// run ffprobe and return its json output verbatim, with `entries` defaulting to the format and streams
+#[cfg(feature = "app")]
+pub fn probe_raw(path: &Path, entries: Option<&str>) -> anyhow::Result<serde_json::Value> {
     let bins = binaries();
 
+    let entries = entries.unwrap_or(DEFAULT_PROBE_ENTRIES);
+    validate_show_entries(entries)?;
+
     #[rustfmt::skip]
-    let result = command_for(&bins.ffmpeg)
-        .arg("-v").arg("error")
-        .arg("-ss").arg(&at.as_secs_f64().to_string())
-        .arg("-i").arg(input)
-        .arg("-frames:v").arg("1")
-        .arg("-f").arg("image2")
-        .arg("-vcodec").arg("mjpeg")
-        .arg("-q:v").arg("2")
-        .arg("-")
+    let result = command_for(&bins.ffprobe)
+        .args([
+            "-v", log_level(),
+            "-show_entries", entries,
+            "-of", "json",
+        ])
+        .arg(path)
         .output()
-        .context("execute ffmpeg to extract frame")?;
+        .context("execute probe")?;
+
+    if !result.status.success() {
+        anyhow::bail!(
+            "ffprobe failed: {}",
+            String::from_utf8_lossy(&result.stderr)
+        )
+    }
+
+    record_diagnostics(&result.stderr);
+    serde_json::from_slice(&result.stdout).context("parse ffprobe output")
+}
+
+/// the image format frames are extracted as before they are fed to the video encoder
+///
+/// every generation of mjpeg loses some quality, which png avoids at the cost of speed and size
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(tag = "codec", rename_all = "camelCase")]
+pub enum Intermediate {
+    /// jpgs at ffmpeg's `-q:v` scale, from 2 (best) to 31 (worst)
+    Mjpeg {
+        #[serde(default = "Intermediate::default_mjpeg_quality")]
+        quality: u8,
+    },
+    /// lossless pngs
+    Png,
+}
+impl Default for Intermediate {
+    fn default() -> Self {
+        Self::Mjpeg {
+            quality: Self::default_mjpeg_quality(),
+        }
+    }
+}
+impl Intermediate {
+    fn default_mjpeg_quality() -> u8 {
+        2
+    }
+    /// ffmpeg output args for writing a single frame in this format
+    fn output_args(self) -> Vec<String> {
+        match self {
+            Self::Mjpeg { quality } => vec![
+                "-vcodec".into(),
+                "mjpeg".into(),
+                "-q:v".into(),
+                quality.clamp(2, 31).to_string(),
+            ],
+            Self::Png => ["-vcodec", "png"].map(String::from).to_vec(),
+        }
+    }
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Mjpeg { .. } => "jpg",
+            Self::Png => "png",
+        }
+    }
+    /// the encoder input that reads frames in this format, so the two always match
+    pub fn frame_input(self) -> FrameInput {
+        match self {
+            Self::Mjpeg { .. } => FrameInput::Mjpeg,
+            Self::Png => FrameInput::Png,
+        }
+    }
+}
+
+/// the highest denoise strength, past which frames are mostly smeared
+pub const MAX_DENOISE_STRENGTH: f32 = 10.0;
+
+/// the pixels that frames are extracted as
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PixelFormat {
+    #[default]
+    Color,
+    /// a single luma channel, for computer vision pipelines that don't want color
+    Gray,
+}
+
+/// how a frame is extracted from a clip
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct ExtractOptions {
+    pub intermediate: Intermediate,
+    /// strength of the denoise filter run on the frame, or `None` to leave the noise in
+    pub denoise: Option<f32>,
+    pub pixel_format: PixelFormat,
+}
+impl ExtractOptions {
+    fn filter_args(self) -> Vec<String> {
+        self.filter_args_after(Vec::new())
+    }
+    /// the `-vf` running `filters` and then the ones for these options, if there are any
+    fn filter_args_after(self, mut filters: Vec<String>) -> Vec<String> {
+        if let Some(strength) = self.denoise {
+            filters.push(denoise_filter(strength));
+        }
+        if self.pixel_format == PixelFormat::Gray {
+            filters.push("format=gray".to_string());
+        }
+        match filters.is_empty() {
+            true => Vec::new(),
+            false => vec!["-vf".into(), filters.join(",")],
+        }
+    }
+}
+/// a hardware decoder for extracting frames, passed to ffmpeg as `-hwaccel`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HwAccel {
+    /// whichever decoder ffmpeg finds first
+    Auto,
+    Videotoolbox,
+    Cuda,
+    Qsv,
+    D3d11va,
+    Vaapi,
+}
+impl HwAccel {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Auto => "auto",
+            Self::Videotoolbox => "videotoolbox",
+            Self::Cuda => "cuda",
+            Self::Qsv => "qsv",
+            Self::D3d11va => "d3d11va",
+            Self::Vaapi => "vaapi",
+        }
+    }
+}
+
+static HWACCEL: Mutex<Option<HwAccel>> = Mutex::new(None);
+/// set once the hardware decoder fails on a clip that decodes fine in software
+static HWACCEL_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// decode clips with `hwaccel` when extracting frames, or in software with `None`
+///
+/// this also turns the decoder back on if it had failed
+pub fn set_hwaccel(hwaccel: Option<HwAccel>) {
+    *HWACCEL.lock().unwrap() = hwaccel;
+    HWACCEL_FAILED.store(false, Ordering::Relaxed);
+}
+fn active_hwaccel() -> Option<HwAccel> {
+    if HWACCEL_FAILED.load(Ordering::Relaxed) {
+        return None;
+    }
+    *HWACCEL.lock().unwrap()
+}
+/// how clips are being decoded, for the job log
+pub fn decode_path() -> String {
+    match *HWACCEL.lock().unwrap() {
+        None => "software".into(),
+        Some(hwaccel) if HWACCEL_FAILED.load(Ordering::Relaxed) => format!(
+            "software, since hardware decoding with {} failed",
+            hwaccel.name()
+        ),
+        Some(hwaccel) => format!("hardware ({})", hwaccel.name()),
+    }
+}
+/// run a decoding ffmpeg command, where `build` puts `input_args` right before its `-i`
+///
+/// with a hardware decoder set, this decodes with it first and retries in software if that
+/// fails. if the retry works, the decoder is to blame, so it stays off from then on
+fn decode_output(build: impl Fn(&[&str]) -> Command) -> io::Result<Output> {
+    let Some(hwaccel) = active_hwaccel() else {
+        return build(&[]).output();
+    };
+    let result = build(&["-hwaccel", hwaccel.name()]).output()?;
+    if result.status.success() {
+        return Ok(result);
+    }
+
+    let fallback = build(&[]).output()?;
+    if fallback.status.success() {
+        HWACCEL_FAILED.store(true, Ordering::Relaxed);
+        DIAGNOSTICS.with_borrow_mut(|d| {
+            d.push(format!(
+                "hardware decoding with {} failed, so clips are decoded in software from now on: {}",
+                hwaccel.name(),
+                String::from_utf8_lossy(&result.stderr).trim()
+            ))
+        });
+    }
+    Ok(fallback)
+}
+
+/// a spatial `hqdn3d`, where strength 1 is its default amount
+///
+/// only one frame is extracted at a time, so there is nothing for its temporal half to work with
+fn denoise_filter(strength: f32) -> String {
+    let strength = strength.clamp(0.0, MAX_DENOISE_STRENGTH);
+    format!("hqdn3d={:.2}:{:.2}:0:0", 4.0 * strength, 3.0 * strength)
+}
+
+/// how `extract_frame_with` gets to the frame at a timestamp
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Seek {
+    /// jump to the keyframe before the timestamp and decode from there up to it, which picks the
+    /// same frame that `extract_frames` does
+    Fast,
+    /// decode from the nearest keyframe up to the timestamp
+    Accurate,
+    /// decode from the start of the clip, timing frames by their count instead of their timestamps
+    Reindexed,
+}
+
+/// a frame extracted from a clip
+#[derive(Debug)]
+pub struct ExtractedFrame {
+    pub data: Vec<u8>,
+    /// where the frame is in the clip, when the time it was extracted at is past the last frame
+    /// and this is the last frame instead
+    pub last_frame_at: Option<Duration>,
+}
+
+pub fn extract_frame(input: &Path, at: Duration) -> anyhow::Result<Vec<u8>> {
+    extract_frame_with(input, at, Seek::Fast, ExtractOptions::default()).map(|frame| frame.data)
+}
+/// like `extract_frame`, but with the format and filters of `opts` instead of as a plain jpg
+pub fn extract_frame_as(
+    input: &Path,
+    at: Duration,
+    opts: ExtractOptions,
+) -> anyhow::Result<ExtractedFrame> {
+    extract_frame_with(input, at, Seek::Fast, opts)
+}
+/// like `extract_frame_as`, but seeks by decoding up to `at` instead of jumping to the nearest
+/// keyframe
+///
+/// this is slower, but avoids the garbage frames some clips produce when fast-seeking
+pub fn extract_frame_accurate(
+    input: &Path,
+    at: Duration,
+    opts: ExtractOptions,
+) -> anyhow::Result<ExtractedFrame> {
+    extract_frame_with(input, at, Seek::Accurate, opts)
+}
+/// like `extract_frame_as`, but for clips whose timestamps jump backwards, which `-ss` can't seek
+/// through reliably
+///
+/// the clip is decoded from its start and each frame is retimed by its position, so `at` lands
+/// where it would if the clip had steady timestamps. this is much slower for late timestamps
+pub fn extract_frame_reindexed(
+    input: &Path,
+    at: Duration,
+    opts: ExtractOptions,
+) -> anyhow::Result<ExtractedFrame> {
+    extract_frame_with(input, at, Seek::Reindexed, opts)
+}
+fn extract_frame_with(
+    input: &Path,
+    at: Duration,
+    seek: Seek,
+    opts: ExtractOptions,
+) -> anyhow::Result<ExtractedFrame> {
+    let bins = binaries();
+
+    let ss = at.as_secs_f64().to_string();
+    let result = decode_output(|input_args| {
+        let mut cmd = command_for(&bins.ffmpeg);
+        cmd.arg("-v").arg(log_level()).args(input_args);
+        // `-ss` before `-i` is a fast input seek, after `-i` it is an accurate output seek
+        let filter_args = match seek {
+            Seek::Fast => {
+                // the frames between the keyframe and `at` are dropped, which is ffmpeg's default
+                // but is what batched extraction relies on to get the same frames
+                #[rustfmt::skip]
+                cmd
+                    .arg("-accurate_seek")
+                    .arg("-ss").arg(&ss)
+                    .arg("-i").arg(input);
+                opts.filter_args()
+            }
+            Seek::Accurate => {
+                cmd.arg("-i").arg(input).arg("-ss").arg(&ss);
+                opts.filter_args()
+            }
+            Seek::Reindexed => {
+                cmd.arg("-fflags").arg("+genpts").arg("-i").arg(input);
+                opts.filter_args_after(vec![
+                    "setpts=N/FRAME_RATE/TB".into(),
+                    format!("trim=start={ss}"),
+                ])
+            }
+        };
+
+        #[rustfmt::skip]
+        cmd
+            .arg("-frames:v").arg("1")
+            .args(filter_args)
+            .arg("-f").arg("image2")
+            .args(opts.intermediate.output_args())
+            .arg("-");
+        cmd
+    })
+    .context("execute ffmpeg to extract frame")?;
 
     if !result.status.success() {
         anyhow::bail!(
@@ -144,13 +592,26 @@ pub fn extract_frame(input: &Path, at: Duration) -> anyhow::Result<Vec<u8>> {
         );
     }
 
+    record_diagnostics(&result.stderr);
     if result.stdout.is_empty() {
-        extract_last_frame(input).context("extract_frame failed -> using extract_last_frame")
+        let (data, last_frame_at) = extract_last_frame(input, opts)
+            .context("extract_frame failed -> using extract_last_frame")?;
+        Ok(ExtractedFrame {
+            data,
+            last_frame_at,
+        })
     } else {
-        Ok(result.stdout)
+        Ok(ExtractedFrame {
+            data: result.stdout,
+            last_frame_at: None,
+        })
     }
 }
-fn extract_last_frame(input: &Path) -> anyhow::Result<Vec<u8>> {
+/// the last frame of `input`, and where it is in the clip if that can be told
+fn extract_last_frame(
+    input: &Path,
+    opts: ExtractOptions,
+) -> anyhow::Result<(Vec<u8>, Option<Duration>)> {
     let bins = binaries();
 
     // create a temporary file for the last frame
@@ -161,25 +622,39 @@ fn extract_last_frame(input: &Path) -> anyhow::Result<Vec<u8>> {
                 .file_stem()
                 .expect("file stem of extract_last_frame input"),
         )
-        .suffix(".jpg")
+        .suffix(&format!(".{}", opts.intermediate.extension()))
         .tempfile()
         .context("create temp file for ffmpeg last frame output")?
         .into_temp_path();
 
-    #[rustfmt::skip]
-    let result = command_for(&bins.ffmpeg)
-        .arg("-y")
-        .arg("-v").arg("error")
-        .arg("-sseof").arg("-3")
-        .arg("-i").arg(input)
-        .arg("-f").arg("image2")
-        .arg("-vsync").arg("0")
-        .arg("-update").arg("1")
-        .arg("-vcodec").arg("mjpeg")
-        .arg("-q:v").arg("2")
-        .arg(&temp_path)
-        .output()
-        .context("execute ffmpeg to extract frame")?;
+    // seeking further back than the clip is long puts ffmpeg before its start
+    let duration = probe(input).ok().map(|info| info.duration);
+    let seek = last_frame_seek(duration);
+    // showinfo reports the time of each frame, where the last one is the frame that's kept
+    let result = decode_output(|input_args| {
+        let mut cmd = command_for(&bins.ffmpeg);
+        #[rustfmt::skip]
+        cmd
+            .arg("-y")
+            .arg("-hide_banner")
+            .arg("-v").arg("level+info")
+            .args(input_args);
+        if let Some(seek) = seek {
+            cmd.arg("-sseof").arg(format!("-{}", seek.as_secs_f64()));
+        }
+
+        #[rustfmt::skip]
+        cmd
+            .arg("-i").arg(input)
+            .arg("-f").arg("image2")
+            .arg("-vsync").arg("0")
+            .arg("-update").arg("1")
+            .args(opts.filter_args_after(vec!["showinfo".into()]))
+            .args(opts.intermediate.output_args())
+            .arg(&temp_path);
+        cmd
+    })
+    .context("execute ffmpeg to extract frame")?;
 
     if !result.status.success() {
         anyhow::bail!(
@@ -188,6 +663,16 @@ fn extract_last_frame(input: &Path) -> anyhow::Result<Vec<u8>> {
         );
     }
 
+    let frame_times = showinfo_frame_times(&result.stderr);
+    // the times start over from wherever `-sseof` seeked to
+    let seeked_to = match seek {
+        Some(seek) => duration.map(|duration| duration.saturating_sub(seek)),
+        None => Some(Duration::ZERO),
+    };
+    let last_frame_at = frame_times
+        .last()
+        .zip(seeked_to)
+        .map(|(&t, seeked_to)| seeked_to + Duration::from_secs_f64(t.max(0.0)));
     let frame =
         fs::read(&temp_path).with_context(|| format!("read last frame from {temp_path:?}"))?;
     temp_path
@@ -197,75 +682,1252 @@ fn extract_last_frame(input: &Path) -> anyhow::Result<Vec<u8>> {
         anyhow::bail!("ffmpeg did not produce frame data");
     }
 
-    Ok(frame)
+    Ok((frame, last_frame_at))
 }
 
-pub struct Mp4FrameEncoder {
-    child: Child,
+/// extract the frames at each of `timestamps` from one clip as jpgs, in a single ffmpeg run
+///
+/// each frame is the first one at or after its timestamp, like an accurate seek. timestamps past
+/// the last frame fall back to `extract_frame`, which finds the last frame instead
+pub fn extract_frames(input: &Path, timestamps: &[Duration]) -> anyhow::Result<Vec<Vec<u8>>> {
+    let frames = extract_frames_as(input, timestamps, ExtractOptions::default())?;
+    Ok(frames.into_iter().map(|frame| frame.data).collect())
 }
-impl Mp4FrameEncoder {
-    pub fn new(output: &Path, fps: u32) -> anyhow::Result<Self> {
-        let bins = binaries();
+/// like `extract_frames`, but with the format and filters of `opts` instead of as plain jpgs
+pub fn extract_frames_as(
+    input: &Path,
+    timestamps: &[Duration],
+    opts: ExtractOptions,
+) -> anyhow::Result<Vec<ExtractedFrame>> {
+    let Some(&start) = timestamps.iter().min() else {
+        return Ok(Vec::new());
+    };
+    // seek to the earliest timestamp so only the span being sampled is decoded, which also
+    // restarts `t` from 0
+    let offsets = timestamps
+        .iter()
+        .map(|&ts| (ts - start).as_secs_f64())
+        .collect::<Vec<_>>();
+    // a frame is selected when it is the first at or after any of the offsets
+    let select = offsets
+        .iter()
+        .map(|offset| format!("gte(t,{offset:.6})*(isnan(prev_t)+lt(prev_t,{offset:.6}))"))
+        .collect::<Vec<_>>()
+        .join("+");
 
+    // showinfo reports the time of each selected frame at the info level, and the level
+    // prefix tells its lines apart from warnings
+    let result = decode_output(|input_args| {
+        let mut cmd = command_for(&binaries().ffmpeg);
         #[rustfmt::skip]
-        let child = command_for(&bins.ffmpeg)
+        cmd
+            .arg("-hide_banner")
+            .arg("-v").arg("level+info")
+            .args(input_args)
+            .arg("-accurate_seek")
+            .arg("-ss").arg(start.as_secs_f64().to_string())
+            .arg("-i").arg(input)
+            .args(opts.filter_args_after(vec![format!("select='{select}'"), "showinfo".into()]))
+            .arg("-vsync").arg("passthrough")
+            .arg("-f").arg("image2pipe")
+            .args(opts.intermediate.output_args())
+            .arg("-");
+        cmd
+    })
+    .context("execute ffmpeg to extract frames")?;
+    if !result.status.success() {
+        anyhow::bail!(
+            "ffmpeg frames extraction failed: {}",
+            String::from_utf8_lossy(&result.stderr)
+        );
+    }
+
+    let frame_times = showinfo_frame_times(&result.stderr);
+    let frames =
+        split_images(&result.stdout, opts.intermediate).context("split extracted frames")?;
+    if frames.len() != frame_times.len() {
+        anyhow::bail!(
+            "ffmpeg wrote {} frames but reported {}",
+            frames.len(),
+            frame_times.len()
+        );
+    }
+
+    timestamps
+        .iter()
+        .zip(offsets)
+        .map(|(&ts, offset)| {
+            // the selected frames are in order, so the first at or after the offset is its frame.
+            // the tolerance covers the rounding of the times that showinfo prints
+            match frame_times.iter().position(|&t| t >= offset - 0.001) {
+                Some(i) => Ok(ExtractedFrame {
+                    data: frames[i].clone(),
+                    last_frame_at: None,
+                }),
+                None => extract_frame_as(input, ts, opts),
+            }
+        })
+        .collect()
+}
+/// the time of each frame that the showinfo filter logged to `stderr`, where the level prefix of
+/// each line tells them apart from the warnings, which are kept in verbose mode
+fn showinfo_frame_times(stderr: &[u8]) -> Vec<f64> {
+    let stderr = String::from_utf8_lossy(stderr);
+    let mut frame_times = Vec::new();
+    let mut warnings = Vec::new();
+    for line in stderr.lines() {
+        if line.contains("Parsed_showinfo") {
+            frame_times.extend(showinfo_pts_time(line));
+        } else if line.contains("[warning]") || line.contains("[error]") {
+            warnings.push(line);
+        }
+    }
+    if VERBOSE.load(Ordering::Relaxed) {
+        record_diagnostics(warnings.join("\n").as_bytes());
+    }
+    frame_times
+}
+/// the `pts_time` of a frame, from a line that the showinfo filter logged
+fn showinfo_pts_time(line: &str) -> Option<f64> {
+    line.split("pts_time:")
+        .nth(1)?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+/// split concatenated images, like `image2pipe` writes them, into each image
+fn split_images(mut data: &[u8], format: Intermediate) -> anyhow::Result<Vec<Vec<u8>>> {
+    let image_len = match format {
+        Intermediate::Mjpeg { .. } => jpeg_len,
+        Intermediate::Png => png_len,
+    };
+    let mut images = Vec::new();
+    while !data.is_empty() {
+        let len = image_len(data)?;
+        images.push(data[..len].to_vec());
+        data = &data[len..];
+    }
+    Ok(images)
+}
+/// the length of the png at the start of `data`, found by walking its chunks to the end of image
+fn png_len(data: &[u8]) -> anyhow::Result<usize> {
+    if !data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        anyhow::bail!("png does not start with a signature");
+    }
+    let mut i = 8;
+    loop {
+        let (Some(len), Some(typ)) = (data.get(i..i + 4), data.get(i + 4..i + 8)) else {
+            anyhow::bail!("truncated png chunk at byte {i}");
+        };
+        // the length, type and crc around the chunk's data
+        i += u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize + 12;
+        if i > data.len() {
+            anyhow::bail!("truncated png chunk at byte {i}");
+        }
+        if typ == b"IEND" {
+            return Ok(i);
+        }
+    }
+}
+/// the length of the jpg at the start of `data`, found by walking its markers to the end of image
+fn jpeg_len(data: &[u8]) -> anyhow::Result<usize> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        anyhow::bail!("jpg does not start with a start of image marker");
+    }
+    let mut i = 2;
+    loop {
+        // markers can be padded with any number of 0xff bytes
+        let marker = loop {
+            match data.get(i..i + 2) {
+                Some([0xFF, 0xFF]) => i += 1,
+                Some([0xFF, marker]) => break *marker,
+                _ => anyhow::bail!("malformed jpg marker at byte {i}"),
+            }
+        };
+        i += 2;
+        match marker {
+            0xD9 => return Ok(i),
+            // these stand alone, without a length
+            0x01 | 0xD0..=0xD7 => continue,
+            _ => {}
+        }
+        let len = data
+            .get(i..i + 2)
+            .map(|len| u16::from_be_bytes([len[0], len[1]]) as usize)
+            .ok_or_else(|| anyhow!("truncated jpg segment at byte {i}"))?;
+        i += len;
+        if marker == 0xDA {
+            // the entropy-coded scan runs until the next marker, with 0xff escaped as 0xff00 and
+            // restart markers in between
+            loop {
+                match data.get(i..i + 2) {
+                    Some([0xFF, 0x00 | 0xD0..=0xD7]) => i += 2,
+                    Some([0xFF, _]) => break,
+                    Some(_) => i += 1,
+                    None => anyhow::bail!("truncated jpg scan"),
+                }
+            }
+        }
+    }
+}
+
+/// generate a synthetic `testsrc` clip of `length`, for checking the pipeline without real footage
+#[cfg(feature = "app")]
+pub fn generate_test_clip(output: &Path, length: Duration) -> anyhow::Result<()> {
+    let bins = binaries();
+
+    #[rustfmt::skip]
+    let result = command_for(&bins.ffmpeg)
+        .arg("-y")
+        .arg("-v").arg(log_level())
+        .arg("-f").arg("lavfi")
+        .arg("-i").arg(format!("testsrc=duration={}:size=320x240:rate=30", length.as_secs_f64()))
+        .arg("-c:v").arg("libx264")
+        .arg("-pix_fmt").arg("yuv420p")
+        .arg(output)
+        .output()
+        .context("execute ffmpeg to generate test clip")?;
+
+    if !result.status.success() {
+        anyhow::bail!(
+            "ffmpeg test clip generation failed: {}",
+            String::from_utf8_lossy(&result.stderr)
+        );
+    }
+    record_diagnostics(&result.stderr);
+    Ok(())
+}
+
+/// container format for encoded video
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, serde::Deserialize, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Container {
+    #[default]
+    Mp4,
+    /// matroska, which stays playable if the encode is interrupted
+    Mkv,
+}
+impl Container {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Mkv => "mkv",
+        }
+    }
+    fn from_path(path: &Path) -> Option<Self> {
+        let ext = path.extension()?.to_str()?.to_ascii_lowercase();
+        match ext.as_str() {
+            "mp4" => Some(Self::Mp4),
+            "mkv" => Some(Self::Mkv),
+            _ => None,
+        }
+    }
+    #[cfg(feature = "app")]
+    const ALL: [Self; 2] = [Self::Mp4, Self::Mkv];
+    /// name of the ffmpeg muxer for the container
+    #[cfg(feature = "app")]
+    fn muxer(self) -> &'static str {
+        match self {
+            Self::Mp4 => "mp4",
+            Self::Mkv => "matroska",
+        }
+    }
+    fn muxer_args(self) -> &'static [&'static str] {
+        match self {
+            // faststart is mp4-specific, other muxers reject it
+            Self::Mp4 => &["-f", "mp4", "-movflags", "+faststart"],
+            Self::Mkv => &["-f", "matroska"],
+        }
+    }
+}
+
+/// format of an animated image output, which is smaller than a video for short timelapses and
+/// plays in an `<img>`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AnimatedFormat {
+    Webp,
+    Avif,
+}
+impl AnimatedFormat {
+    /// the widest an animated output is encoded, since these formats grow quickly with size
+    pub const MAX_WIDTH: u32 = 1280;
+    #[cfg(feature = "app")]
+    const ALL: [Self; 2] = [Self::Webp, Self::Avif];
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Webp => "webp",
+            Self::Avif => "avif",
+        }
+    }
+    fn encoder(self) -> &'static str {
+        match self {
+            Self::Webp => "libwebp_anim",
+            Self::Avif => "libaom-av1",
+        }
+    }
+    fn codec_args(self) -> Vec<String> {
+        let mut args = vec![
+            "-c:v".into(),
+            self.encoder().into(),
+            "-pix_fmt".into(),
+            "yuv420p".into(),
+            // yuv420p needs an even height
+            "-vf".into(),
+            format!("scale='min({},iw)':-2", Self::MAX_WIDTH),
+        ];
+        if self == Self::Avif {
+            // the default speed takes minutes per frame
+            args.extend(["-crf", "32", "-cpu-used", "6"].map(String::from));
+        }
+        args
+    }
+    /// the muxer, which shares its name with the extension
+    fn muxer_args(self) -> [&'static str; 4] {
+        ["-f", self.extension(), "-loop", "0"]
+    }
+}
+
+/// what an encode writes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Video(Container),
+    Animated(AnimatedFormat),
+}
+impl OutputFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Video(container) => container.extension(),
+            Self::Animated(format) => format.extension(),
+        }
+    }
+}
+
+/// what the bundled ffmpeg can actually encode and mux, out of what crimelapse knows how to use
+#[cfg(feature = "app")]
+#[derive(Debug, Clone, serde::Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Capabilities {
+    /// whether libx264 is available, which every video output is encoded with
+    pub h264: bool,
+    /// whether frames can be extracted as jpgs, which every output starts from
+    pub mjpeg: bool,
+    pub containers: Vec<Container>,
+    /// animated image formats that can be both encoded and muxed
+    pub animated: Vec<AnimatedFormat>,
+    /// `codec` values of the intermediate formats that can be used
+    pub intermediates: Vec<&'static str>,
+}
+
+/// query the bundled ffmpeg for its encoders and muxers
+#[cfg(feature = "app")]
+pub fn capabilities() -> anyhow::Result<Capabilities> {
+    let encoders = list_components("-encoders")?;
+    let muxers = list_components("-muxers")?;
+    let has_encoder = |name: &str| encoders.iter().any(|e| e == name);
+    Ok(Capabilities {
+        h264: has_encoder("libx264"),
+        mjpeg: has_encoder("mjpeg"),
+        containers: Container::ALL
+            .into_iter()
+            .filter(|c| muxers.iter().any(|m| m == c.muxer()))
+            .collect(),
+        animated: AnimatedFormat::ALL
+            .into_iter()
+            .filter(|f| has_encoder(f.encoder()) && muxers.iter().any(|m| m == f.extension()))
+            .collect(),
+        intermediates: [("mjpeg", "mjpeg"), ("png", "png")]
+            .into_iter()
+            .filter(|(_, encoder)| has_encoder(encoder))
+            .map(|(codec, _)| codec)
+            .collect(),
+    })
+}
+/// the names listed by `ffmpeg -encoders`, `-muxers`, and the like
+#[cfg(feature = "app")]
+fn list_components(flag: &str) -> anyhow::Result<Vec<String>> {
+    let result = command_for(&binaries().ffmpeg)
+        .arg("-hide_banner")
+        .arg(flag)
+        .output()
+        .with_context(|| format!("execute ffmpeg {flag}"))?;
+    if !result.status.success() {
+        anyhow::bail!(
+            "ffmpeg {flag} failed: {}",
+            String::from_utf8_lossy(&result.stderr)
+        );
+    }
+    Ok(parse_component_list(&String::from_utf8_lossy(
+        &result.stdout,
+    )))
+}
+/// the names in a component list, which follow a legend that ends with a line of dashes
+#[cfg(feature = "app")]
+fn parse_component_list(output: &str) -> Vec<String> {
+    output
+        .lines()
+        .skip_while(|line| {
+            let line = line.trim();
+            line.is_empty() || !line.chars().all(|c| c == '-')
+        })
+        .skip(1)
+        .filter_map(|line| line.split_whitespace().nth(1))
+        .flat_map(|names| names.split(','))
+        .map(String::from)
+        .collect()
+}
+
+/// the format of the frames written to the encoder's stdin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameInput {
+    /// concatenated jpg images
+    Mjpeg,
+    /// concatenated png images
+    Png,
+    /// packed rgb24 pixels of frames that are all the same size
+    Rgb24 { width: u32, height: u32 },
+}
+impl FrameInput {
+    fn args(self) -> Vec<String> {
+        match self {
+            Self::Mjpeg => ["-f", "image2pipe", "-vcodec", "mjpeg"]
+                .map(String::from)
+                .to_vec(),
+            Self::Png => ["-f", "image2pipe", "-vcodec", "png"]
+                .map(String::from)
+                .to_vec(),
+            Self::Rgb24 { width, height } => vec![
+                "-f".into(),
+                "rawvideo".into(),
+                "-pix_fmt".into(),
+                "rgb24".into(),
+                "-s".into(),
+                format!("{width}x{height}"),
+            ],
+        }
+    }
+}
+
+/// how the video encoder picks a bitrate
+#[derive(Debug, Clone, Copy, Default, PartialEq, serde::Deserialize)]
+#[serde(
+    tag = "mode",
+    rename_all = "camelCase",
+    rename_all_fields = "camelCase"
+)]
+pub enum RateControl {
+    /// libx264's default constant quality, which makes the size unpredictable
+    #[default]
+    Crf,
+    /// two passes at the average bitrate that makes the video about `target_mb` in size
+    TargetSize { target_mb: f64 },
+}
+
+/// how frames are encoded into a video, other than where they come from and where they go
+#[derive(Debug, Clone, PartialEq)]
+pub struct VideoOptions {
+    pub fps: u32,
+    pub container: Container,
+    pub rate: RateControl,
+    /// passed to ffmpeg after the encoder's own options, like `-preset slow`
+    pub extra_args: Vec<String>,
+}
+
+/// a named section of the output video, which players can jump between
+#[derive(Debug, Clone, PartialEq)]
+pub struct Chapter {
+    pub start: Duration,
+    pub end: Duration,
+    pub title: String,
+}
+
+/// `chapters` as an ffmetadata file, which ffmpeg reads as an extra input
+fn ffmetadata_chapters(chapters: &[Chapter]) -> String {
+    // these characters are special in ffmetadata values
+    let escape = |s: &str| {
+        s.chars()
+            .flat_map(|c| match c {
+                '=' | ';' | '#' | '\\' | '\n' => vec!['\\', c],
+                c => vec![c],
+            })
+            .collect::<String>()
+    };
+    let mut out = String::from(";FFMETADATA1\n");
+    for chapter in chapters {
+        out.push_str(&format!(
+            "[CHAPTER]\nTIMEBASE=1/1000\nSTART={}\nEND={}\ntitle={}\n",
+            chapter.start.as_millis(),
+            chapter.end.as_millis(),
+            escape(&chapter.title)
+        ));
+    }
+    out
+}
+
+/// options that decide the inputs, outputs, or passes of an encode, which the encoder has to set
+const RESERVED_ARGS: [&str; 11] = [
+    "-i",
+    "-f",
+    "-y",
+    "-n",
+    "-map",
+    "-map_chapters",
+    "-map_metadata",
+    "-pass",
+    "-passlogfile",
+    // stderr is only read once ffmpeg exits, so more logging could fill the pipe and stall it
+    "-v",
+    "-loglevel",
+];
+
+/// reject extra encoder options that would fight with how the encoder feeds and writes ffmpeg
+fn check_extra_args(extra_args: &[String]) -> anyhow::Result<()> {
+    if let Some(arg) = extra_args
+        .iter()
+        .find(|arg| RESERVED_ARGS.contains(&arg.as_str()))
+    {
+        anyhow::bail!("extra ffmpeg argument {} is set by the encoder itself", arg);
+    }
+    Ok(())
+}
+
+/// `chapters` written to a temporary ffmetadata file, or `None` if there aren't any
+fn chapters_file(chapters: &[Chapter]) -> anyhow::Result<Option<tempfile::TempPath>> {
+    if chapters.is_empty() {
+        return Ok(None);
+    }
+    let mut file = tempfile::Builder::new()
+        .suffix(".ffmetadata")
+        .tempfile()
+        .context("create chapters file")?;
+    file.write_all(ffmetadata_chapters(chapters).as_bytes())
+        .context("write chapters file")?;
+    Ok(Some(file.into_temp_path()))
+}
+
+/// everything about an encode other than where its frames come from and where it goes
+struct EncodeArgs {
+    fps: u32,
+    format: OutputFormat,
+    input: FrameInput,
+    metadata: Vec<(String, String)>,
+    /// ffmetadata file with the chapters of the output, removed once the encoder is dropped
+    chapters: Option<tempfile::TempPath>,
+    /// options from the user that go after ours, so they override any they repeat
+    extra_args: Vec<String>,
+}
+impl EncodeArgs {
+    /// the args of a video encode, reading frames in the format of `input`
+    fn video(
+        input: FrameInput,
+        metadata: &[(&str, String)],
+        chapters: &[Chapter],
+        opts: &VideoOptions,
+    ) -> anyhow::Result<Self> {
+        Ok(Self {
+            fps: opts.fps,
+            format: OutputFormat::Video(opts.container),
+            input,
+            metadata: metadata
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.clone()))
+                .collect(),
+            chapters: chapters_file(chapters)?,
+            extra_args: opts.extra_args.clone(),
+        })
+    }
+    /// the whole ffmpeg command reading frames from stdin and writing them to `output`
+    fn piped_command(&self, output: &Path) -> Command {
+        let mut cmd = self.command(OsStr::new("-"));
+        self.output_args(&mut cmd);
+        cmd.arg(output);
+        cmd
+    }
+    /// the ffmpeg command reading frames from `input`, up until the output options
+    fn command(&self, input: &OsStr) -> Command {
+        let mut cmd = command_for(&binaries().ffmpeg);
+        #[rustfmt::skip]
+        cmd
             .arg("-y")
             .arg("-v").arg("error")
-            .arg("-f").arg("image2pipe")
-            .arg("-vcodec").arg("mjpeg")
-            .arg("-r").arg(fps.to_string())
-            .arg("-i").arg("-")
-            .arg("-c:v").arg("libx264")
-            .arg("-pix_fmt").arg("yuv420p")
-            .arg("-movflags").arg("+faststart")
-            .arg(output)
-            .stdin(Stdio::piped())
-            .stdout(Stdio::null())
-            .stderr(Stdio::piped())
-            .spawn()
-            .context("spawn ffmpeg mp4 encoder")?;
+            .args(self.input.args())
+            .arg("-r").arg(self.fps.to_string())
+            .arg("-i").arg(input);
+        if let Some(chapters) = &self.chapters {
+            cmd.arg("-i").arg(chapters).arg("-map").arg("0:v");
+        }
+        match self.format {
+            #[rustfmt::skip]
+            OutputFormat::Video(_) => {
+                cmd
+                    .arg("-c:v").arg("libx264")
+                    .arg("-pix_fmt").arg("yuv420p");
+            }
+            OutputFormat::Animated(format) => {
+                cmd.args(format.codec_args());
+            }
+        }
+        cmd.args(&self.extra_args);
+        cmd
+    }
+    /// muxer and metadata args for writing the final output
+    fn output_args(&self, cmd: &mut Command) {
+        match self.format {
+            OutputFormat::Video(container) => cmd.args(container.muxer_args()),
+            OutputFormat::Animated(format) => cmd.args(format.muxer_args()),
+        };
+        if self.chapters.is_some() {
+            cmd.arg("-map_chapters").arg("1");
+        }
+        for (key, value) in &self.metadata {
+            cmd.arg("-metadata").arg(format!("{key}={value}"));
+        }
+    }
+}
+
+enum EncoderSink {
+    /// frames are piped straight into ffmpeg
+    Pipe(Child),
+    /// frames are buffered into a file until `finish`, since both passes need every frame
+    TwoPass(TwoPassBuffer),
+}
 
-        Ok(Self { child })
+struct TwoPassBuffer {
+    /// holds the buffered frames and the pass log, and is removed with them on drop
+    dir: tempfile::TempDir,
+    frames: io::BufWriter<fs::File>,
+    frame_count: usize,
+    target_bytes: f64,
+    args: EncodeArgs,
+}
+impl TwoPassBuffer {
+    const FRAMES_FILE: &str = "frames";
+    const PASS_LOG_PREFIX: &str = "passlog";
+
+    fn new(target_mb: f64, args: EncodeArgs) -> anyhow::Result<Self> {
+        if !target_mb.is_finite() || target_mb <= 0.0 {
+            anyhow::bail!("target size of {target_mb}MB must be above 0");
+        }
+        let dir = tempfile::tempdir().context("create temp dir for two-pass encode")?;
+        let frames = fs::File::create(dir.path().join(Self::FRAMES_FILE))
+            .context("create two-pass frame buffer")?;
+        Ok(Self {
+            dir,
+            frames: io::BufWriter::new(frames),
+            frame_count: 0,
+            target_bytes: target_mb * 1024.0 * 1024.0,
+            args,
+        })
     }
 
-    pub fn encode_frame(&mut self, jpeg: &[u8]) -> anyhow::Result<()> {
-        let stdin = self
-            .child
-            .stdin
-            .as_mut()
-            .ok_or_else(|| anyhow!("ffmpeg stdin already closed"))?;
-        stdin
-            .write_all(jpeg)
-            .context("write frame to ffmpeg stdin")?;
-        stdin.flush().context("flush ffmpeg stdin after frame")?;
-        Ok(())
+    /// the average bitrate that spreads the target size over the whole video, in kbit/s
+    fn bitrate_kbps(&self) -> u64 {
+        let secs = self.frame_count as f64 / self.args.fps.max(1) as f64;
+        (self.target_bytes * 8.0 / secs / 1000.0).max(1.0) as u64
     }
 
-    pub fn finish(&mut self) -> anyhow::Result<()> {
-        if let Some(mut stdin) = self.child.stdin.take() {
-            stdin.flush().context("flush ffmpeg stdin before finish")?;
+    fn encode(&mut self, partial_output: &Path) -> anyhow::Result<()> {
+        if self.frame_count == 0 {
+            anyhow::bail!("no frames were encoded");
         }
+        self.frames.flush().context("flush two-pass frame buffer")?;
+        let frames = self.dir.path().join(Self::FRAMES_FILE);
+        let pass_log = self.dir.path().join(Self::PASS_LOG_PREFIX);
+        let bitrate = format!("{}k", self.bitrate_kbps());
 
-        let mut stderr_handle = self.child.stderr.take();
-        let status = self
-            .child
-            .wait()
-            .context("wait for ffmpeg encoder to finish")?;
+        for pass in ["1", "2"] {
+            let mut cmd = self.args.command(frames.as_os_str());
+            #[rustfmt::skip]
+            cmd
+                .arg("-b:v").arg(&bitrate)
+                .arg("-pass").arg(pass)
+                .arg("-passlogfile").arg(&pass_log);
+            if pass == "1" {
+                // the first pass only gathers statistics, so its output is thrown away
+                cmd.arg("-f").arg("null").arg("-");
+            } else {
+                self.args.output_args(&mut cmd);
+                cmd.arg(partial_output);
+            }
+            let result = cmd
+                .stdin(Stdio::null())
+                .output()
+                .with_context(|| format!("execute ffmpeg encode pass {pass}"))?;
+            if !result.status.success() {
+                anyhow::bail!(
+                    "ffmpeg encode pass {pass} failed: {}",
+                    String::from_utf8_lossy(&result.stderr)
+                );
+            }
+        }
+        Ok(())
+    }
+}
 
-        let mut stderr_buf = Vec::new();
-        if let Some(mut stderr) = stderr_handle.take() {
-            stderr
-                .read_to_end(&mut stderr_buf)
-                .context("read ffmpeg stderr")?;
+/// where `output` is written to until it is finished
+fn partial_path(output: &Path) -> PathBuf {
+    let mut partial_output = output.as_os_str().to_owned();
+    partial_output.push(".part");
+    PathBuf::from(partial_output)
+}
+
+pub struct Mp4FrameEncoder {
+    sink: EncoderSink,
+    /// what is being encoded, for naming it in errors
+    format: OutputFormat,
+    /// whether the encode has been completed or failed by `finish`
+    finished: bool,
+    /// the final output path, which only exists once encoding succeeds
+    output: PathBuf,
+    /// where ffmpeg writes to while encoding
+    partial_output: PathBuf,
+    /// the ffmpeg command encoding the frames, less the bitrate and pass of a two-pass encode
+    command_line: String,
+}
+impl Mp4FrameEncoder {
+    /// `metadata` is a list of container-level `-metadata key=value` tags to write into the output
+    pub fn new(
+        output: &Path,
+        input: FrameInput,
+        metadata: &[(&str, String)],
+        chapters: &[Chapter],
+        opts: &VideoOptions,
+    ) -> anyhow::Result<Self> {
+        if Container::from_path(output) != Some(opts.container) {
+            anyhow::bail!(
+                "output {:?} does not have the .{} extension",
+                output,
+                opts.container.extension()
+            );
         }
+        check_extra_args(&opts.extra_args)?;
+
+        let args = EncodeArgs::video(input, metadata, chapters, opts)?;
+        Self::start(output, args, opts.rate)
+    }
 
-        if !status.success() {
+    /// the ffmpeg command that `new` would encode frames into `output` with, without starting it
+    pub fn command_line_for(
+        output: &Path,
+        input: FrameInput,
+        opts: &VideoOptions,
+    ) -> anyhow::Result<String> {
+        let args = EncodeArgs::video(input, &[], &[], opts)?;
+        Ok(format!("{:?}", args.piped_command(&partial_path(output))))
+    }
+
+    /// like `new`, but encoding an animated image that loops forever instead of a video
+    pub fn animated(
+        output: &Path,
+        fps: u32,
+        format: AnimatedFormat,
+        input: FrameInput,
+    ) -> anyhow::Result<Self> {
+        if !output
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case(format.extension()))
+        {
             anyhow::bail!(
-                "ffmpeg mp4 encoder failed: {}",
-                String::from_utf8_lossy(&stderr_buf)
+                "output {:?} does not have the .{} extension",
+                output,
+                format.extension()
             );
         }
+        let args = EncodeArgs {
+            fps,
+            format: OutputFormat::Animated(format),
+            input,
+            metadata: Vec::new(),
+            chapters: None,
+            extra_args: Vec::new(),
+        };
+        Self::start(output, args, RateControl::Crf)
+    }
+
+    fn start(output: &Path, args: EncodeArgs, rate: RateControl) -> anyhow::Result<Self> {
+        // write to a partial file so a crash never leaves something that looks finished
+        let partial_output = partial_path(output);
+        let mut cmd = args.piped_command(&partial_output);
+        let command_line = format!("{:?}", cmd);
+        let format = args.format;
+        let sink = match rate {
+            RateControl::Crf => {
+                let child = cmd
+                    .stdin(Stdio::piped())
+                    .stdout(Stdio::null())
+                    .stderr(Stdio::piped())
+                    .spawn()
+                    .with_context(|| format!("spawn ffmpeg {} encoder", args.format.extension()))?;
+                EncoderSink::Pipe(child)
+            }
+            RateControl::TargetSize { target_mb } => {
+                EncoderSink::TwoPass(TwoPassBuffer::new(target_mb, args)?)
+            }
+        };
 
+        Ok(Self {
+            sink,
+            format,
+            finished: false,
+            output: output.to_path_buf(),
+            partial_output,
+            command_line,
+        })
+    }
+
+    /// the ffmpeg command that frames are encoded with, for the job log
+    pub fn command_line(&self) -> &str {
+        &self.command_line
+    }
+
+    /// write one frame, in the `FrameInput` format the encoder was created with
+    pub fn encode_frame(&mut self, frame: &[u8]) -> anyhow::Result<()> {
+        match &mut self.sink {
+            EncoderSink::Pipe(child) => {
+                let stdin = child
+                    .stdin
+                    .as_mut()
+                    .ok_or_else(|| anyhow!("ffmpeg stdin already closed"))?;
+                stdin
+                    .write_all(frame)
+                    .context("write frame to ffmpeg stdin")?;
+                stdin.flush().context("flush ffmpeg stdin after frame")?;
+            }
+            EncoderSink::TwoPass(buffer) => {
+                buffer
+                    .frames
+                    .write_all(frame)
+                    .context("write frame to two-pass buffer")?;
+                buffer.frame_count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn finish(&mut self) -> anyhow::Result<()> {
+        let result = match &mut self.sink {
+            EncoderSink::Pipe(child) => {
+                if let Some(mut stdin) = child.stdin.take() {
+                    stdin.flush().context("flush ffmpeg stdin before finish")?;
+                }
+
+                let mut stderr_handle = child.stderr.take();
+                let status = child.wait().context("wait for ffmpeg encoder to finish")?;
+                self.finished = true;
+
+                let mut stderr_buf = Vec::new();
+                if let Some(mut stderr) = stderr_handle.take() {
+                    stderr
+                        .read_to_end(&mut stderr_buf)
+                        .context("read ffmpeg stderr")?;
+                }
+                match status.success() {
+                    true => Ok(()),
+                    false => Err(anyhow!(
+                        "ffmpeg {} encoder failed: {}",
+                        self.format.extension(),
+                        String::from_utf8_lossy(&stderr_buf)
+                    )),
+                }
+            }
+            EncoderSink::TwoPass(buffer) => {
+                self.finished = true;
+                buffer.encode(&self.partial_output)
+            }
+        };
+
+        if let Err(e) = result {
+            let _ = fs::remove_file(&self.partial_output);
+            return Err(e);
+        }
+
+        fs::rename(&self.partial_output, &self.output)
+            .with_context(|| format!("rename {:?} to {:?}", self.partial_output, self.output))?;
         Ok(())
     }
 }
+impl Drop for Mp4FrameEncoder {
+    fn drop(&mut self) {
+        // an error occurred before `finish`, so kill ffmpeg and reap it instead of leaving a zombie
+        if !self.finished {
+            if let EncoderSink::Pipe(child) = &mut self.sink {
+                let _ = child.kill();
+                let _ = child.wait();
+            }
+            let _ = fs::remove_file(&self.partial_output);
+        }
+    }
+}
+
+/// join `segments` into `output` with the concat demuxer, copying their streams instead of
+/// encoding them again, and writing the container-level `metadata` and `chapters` there
+///
+/// the segments must all be in one directory and encoded with the same settings
+pub fn concat_segments(
+    segments: &[PathBuf],
+    output: &Path,
+    container: Container,
+    metadata: &[(&str, String)],
+    chapters: &[Chapter],
+) -> anyhow::Result<()> {
+    if Container::from_path(output) != Some(container) {
+        anyhow::bail!(
+            "output {:?} does not have the .{} extension",
+            output,
+            container.extension()
+        );
+    }
+    let Some(dir) = segments.first().and_then(|first| first.parent()) else {
+        anyhow::bail!("no segments to join into {:?}", output);
+    };
+
+    // names in the list are relative to the list itself, so it is written next to the segments
+    let mut list = tempfile::Builder::new()
+        .suffix(".txt")
+        .tempfile_in(dir)
+        .context("create segment list")?;
+    for segment in segments {
+        let name = segment
+            .file_name()
+            .ok_or_else(|| anyhow!("segment {:?} has no file name", segment))?;
+        // quotes can't be escaped inside a quoted string, so they close it and are escaped outside
+        let name = name.to_string_lossy().replace('\'', "'\\''");
+        writeln!(list, "file '{}'", name).context("write segment list")?;
+    }
+    let list = list.into_temp_path();
+    let chapters = chapters_file(chapters)?;
+
+    let partial_output = partial_path(output);
+    let mut cmd = command_for(&binaries().ffmpeg);
+    #[rustfmt::skip]
+    cmd
+        .arg("-y")
+        .arg("-v").arg("error")
+        .arg("-f").arg("concat")
+        .arg("-safe").arg("0")
+        .arg("-i").arg(&list);
+    if let Some(chapters) = &chapters {
+        #[rustfmt::skip]
+        cmd
+            .arg("-i").arg(chapters)
+            .arg("-map").arg("0")
+            .arg("-map_chapters").arg("1");
+    }
+    cmd.arg("-c").arg("copy").args(container.muxer_args());
+    for (key, value) in metadata {
+        cmd.arg("-metadata").arg(format!("{key}={value}"));
+    }
+    let result = cmd
+        .arg(&partial_output)
+        .output()
+        .context("execute ffmpeg to join segments")?;
+    if !result.status.success() {
+        let _ = fs::remove_file(&partial_output);
+        anyhow::bail!(
+            "ffmpeg failed to join {} segments: {}",
+            segments.len(),
+            String::from_utf8_lossy(&result.stderr)
+        );
+    }
+    fs::rename(&partial_output, output)
+        .with_context(|| format!("rename {:?} to {:?}", partial_output, output))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        check_extra_args, count_dts_resets, denoise_filter, ffmetadata_chapters, last_frame_seek,
+        showinfo_pts_time, split_images, AnimatedFormat, Chapter, Container, EncodeArgs,
+        EncoderSink, ExtractOptions, FrameInput, HwAccel, Intermediate, Mp4FrameEncoder,
+        OutputFormat, PixelFormat, TwoPassBuffer,
+    };
+    use std::{
+        path::Path,
+        process::{Command, Stdio},
+        time::Duration,
+    };
+
+    #[test]
+    fn container_from_extension() {
+        assert_eq!(
+            Container::from_path(Path::new("out/output.mp4")),
+            Some(Container::Mp4)
+        );
+        assert_eq!(
+            Container::from_path(Path::new("out/output.MKV")),
+            Some(Container::Mkv)
+        );
+        assert_eq!(Container::from_path(Path::new("out/output.avi")), None);
+        assert_eq!(Container::from_path(Path::new("out/output")), None);
+    }
+
+    #[cfg(feature = "app")]
+    #[test]
+    fn show_entries_only_allows_known_sections() {
+        use super::validate_show_entries;
+        assert!(validate_show_entries("format:stream").is_ok());
+        assert!(validate_show_entries("stream=codec_name,width:format_tags").is_ok());
+        assert!(validate_show_entries("packet").is_err());
+        assert!(validate_show_entries("format=-i").is_err());
+        assert!(validate_show_entries("stream=width,").is_err());
+        assert!(validate_show_entries("").is_err());
+    }
+
+    #[test]
+    fn raw_input_declares_frame_size() {
+        let args = FrameInput::Rgb24 {
+            width: 1920,
+            height: 1080,
+        }
+        .args();
+        assert!(args.windows(2).any(|w| w == ["-pix_fmt", "rgb24"]));
+        assert!(args.windows(2).any(|w| w == ["-s", "1920x1080"]));
+        assert!(!FrameInput::Mjpeg.args().contains(&"-s".to_string()));
+    }
+
+    #[test]
+    fn target_size_bitrate_spreads_over_the_video() {
+        let args = EncodeArgs {
+            fps: 30,
+            format: OutputFormat::Video(Container::Mp4),
+            input: FrameInput::Mjpeg,
+            metadata: Vec::new(),
+            chapters: None,
+            extra_args: Vec::new(),
+        };
+        let mut buffer = TwoPassBuffer::new(10.0, args).unwrap();
+        buffer.frame_count = 300;
+        // 10MiB over 10 seconds
+        assert_eq!(buffer.bitrate_kbps(), 8388);
+
+        let args = EncodeArgs {
+            fps: 30,
+            format: OutputFormat::Video(Container::Mp4),
+            input: FrameInput::Mjpeg,
+            metadata: Vec::new(),
+            chapters: None,
+            extra_args: Vec::new(),
+        };
+        assert!(TwoPassBuffer::new(0.0, args).is_err());
+    }
+
+    #[test]
+    fn last_frame_seek_is_clamped_to_short_clips() {
+        // the default seek is 3 seconds
+        assert_eq!(
+            last_frame_seek(Some(Duration::from_millis(1500))),
+            Some(Duration::from_millis(1500))
+        );
+        assert_eq!(
+            last_frame_seek(Some(Duration::from_secs(60))),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(last_frame_seek(None), Some(Duration::from_secs(3)));
+        assert_eq!(last_frame_seek(Some(Duration::ZERO)), None);
+    }
+
+    #[test]
+    fn chapters_are_written_as_escaped_ffmetadata() {
+        let chapters = [Chapter {
+            start: Duration::from_millis(1500),
+            end: Duration::from_secs(3),
+            title: "a=b;c".into(),
+        }];
+        assert_eq!(
+            ffmetadata_chapters(&chapters),
+            ";FFMETADATA1\n[CHAPTER]\nTIMEBASE=1/1000\nSTART=1500\nEND=3000\ntitle=a\\=b\\;c\n"
+        );
+    }
+
+    #[test]
+    fn intermediate_matches_encoder_input() {
+        let png = serde_json::from_str::<Intermediate>(r#"{"codec":"png"}"#).unwrap();
+        assert_eq!(png.frame_input(), FrameInput::Png);
+        assert!(png.frame_input().args().contains(&"png".to_string()));
+
+        let mjpeg = serde_json::from_str::<Intermediate>(r#"{"codec":"mjpeg"}"#).unwrap();
+        assert_eq!(mjpeg, Intermediate::default());
+        assert_eq!(mjpeg.frame_input(), FrameInput::Mjpeg);
+        let worst = Intermediate::Mjpeg { quality: 90 }.output_args();
+        assert_eq!(worst.last().unwrap(), "31");
+    }
+
+    #[cfg(feature = "app")]
+    #[test]
+    fn component_lists_are_read_after_the_legend() {
+        use super::parse_component_list;
+        let encoders = "Encoders:
+ V..... = Video
+ A..... = Audio
+ ------
+ V....D libx264              libx264 H.264 / AVC / MPEG-4 AVC (codec h264)
+ V....D mjpeg                MJPEG (Motion JPEG)
+";
+        assert_eq!(parse_component_list(encoders), ["libx264", "mjpeg"]);
+
+        let muxers = "File formats:
+ D. = Demuxing supported
+ .E = Muxing supported
+ --
+  E matroska        Matroska
+  E mp4             MP4 (MPEG-4 Part 14)
+";
+        assert_eq!(parse_component_list(muxers), ["matroska", "mp4"]);
+    }
+
+    #[test]
+    fn denoise_is_only_filtered_when_enabled() {
+        assert!(ExtractOptions::default().filter_args().is_empty());
+        let opts = ExtractOptions {
+            denoise: Some(2.0),
+            ..Default::default()
+        };
+        assert_eq!(opts.filter_args(), ["-vf", "hqdn3d=8.00:6.00:0:0"]);
+        assert_eq!(denoise_filter(100.0), "hqdn3d=40.00:30.00:0:0");
+    }
+
+    #[test]
+    fn hwaccel_names_match_their_options() {
+        for name in ["auto", "videotoolbox", "cuda", "qsv", "d3d11va", "vaapi"] {
+            let hwaccel: HwAccel = serde_json::from_value(name.into()).unwrap();
+            assert_eq!(hwaccel.name(), name);
+        }
+    }
+
+    #[test]
+    fn gray_frames_are_converted_after_denoising() {
+        let opts = ExtractOptions {
+            pixel_format: PixelFormat::Gray,
+            ..Default::default()
+        };
+        assert_eq!(opts.filter_args(), ["-vf", "format=gray"]);
+        let opts = ExtractOptions {
+            denoise: Some(1.0),
+            ..opts
+        };
+        assert_eq!(
+            opts.filter_args(),
+            ["-vf", "hqdn3d=4.00:3.00:0:0,format=gray"]
+        );
+    }
+
+    #[test]
+    fn concatenated_images_are_split_apart() {
+        let encode = |shade| {
+            let img =
+                image::RgbImage::from_fn(16, 16, |x, y| image::Rgb([shade, x as u8 * 16, y as u8]));
+            let mut jpg = Vec::new();
+            image::codecs::jpeg::JpegEncoder::new(&mut jpg)
+                .encode_image(&img)
+                .unwrap();
+            jpg
+        };
+        let (a, b) = (encode(0), encode(255));
+        let jpg = Intermediate::default();
+        assert_eq!(
+            split_images(&[a.clone(), b.clone()].concat(), jpg).unwrap(),
+            [a.clone(), b]
+        );
+        assert!(split_images(&a[..a.len() - 2], jpg).is_err());
+
+        let mut png = Vec::new();
+        image::RgbImage::new(4, 4)
+            .write_to(&mut std::io::Cursor::new(&mut png), image::ImageFormat::Png)
+            .unwrap();
+        assert_eq!(
+            split_images(&[png.clone(), png.clone()].concat(), Intermediate::Png).unwrap(),
+            [png.clone(), png.clone()]
+        );
+        assert!(split_images(&png[..png.len() - 4], Intermediate::Png).is_err());
+    }
+
+    #[test]
+    fn dts_that_go_backwards_or_repeat_are_counted() {
+        let csv = "0,\n1001,\nN/A,\n2002,\n1001,\n1001\n3003,\n";
+        assert_eq!(count_dts_resets(csv), 2);
+        assert_eq!(count_dts_resets("0,\n1001,\n2002,\n"), 0);
+    }
+
+    #[test]
+    fn showinfo_lines_give_frame_times() {
+        let line = "[Parsed_showinfo_1 @ 0x600] [info] n:   1 pts:  45045 pts_time:1.5015  \
+                    duration:   1501 fmt:yuvj420p";
+        assert_eq!(showinfo_pts_time(line), Some(1.5015));
+        assert_eq!(
+            showinfo_pts_time("[Parsed_showinfo_1 @ 0x600] [info] config in"),
+            None
+        );
+    }
+
+    #[test]
+    fn extra_args_cannot_replace_inputs_or_outputs() {
+        let args = |args: &[&str]| args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>();
+        assert!(
+            check_extra_args(&args(&["-preset", "slow", "-tune", "film", "-g", "250"])).is_ok()
+        );
+        assert!(check_extra_args(&args(&["-preset", "slow", "-f", "mpegts"])).is_err());
+        assert!(check_extra_args(&args(&["-pass", "1"])).is_err());
+        assert!(check_extra_args(&args(&["-loglevel", "debug"])).is_err());
+    }
+
+    #[test]
+    fn faststart_only_for_mp4() {
+        assert!(Container::Mp4.muxer_args().contains(&"+faststart"));
+        assert!(!Container::Mkv.muxer_args().contains(&"+faststart"));
+    }
+
+    #[test]
+    fn animated_outputs_loop_and_are_scaled_down() {
+        for format in [AnimatedFormat::Webp, AnimatedFormat::Avif] {
+            assert_eq!(
+                format.muxer_args(),
+                ["-f", format.extension(), "-loop", "0"]
+            );
+            let args = format.codec_args();
+            assert!(args.contains(&format.encoder().to_string()));
+            assert!(args.contains(&"scale='min(1280,iw)':-2".to_string()));
+        }
+        let output = std::path::Path::new("output.mp4");
+        assert!(
+            Mp4FrameEncoder::animated(output, 30, AnimatedFormat::Webp, FrameInput::Mjpeg).is_err()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dropping_unfinished_encoder_reaps_child_and_removes_partial() {
+        // stands in for ffmpeg: a process that runs until its stdin is closed
+        let child = Command::new("cat")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let pid = child.id();
+        let dir = tempfile::tempdir().unwrap();
+        let partial_output = dir.path().join("output.mp4.part");
+        std::fs::write(&partial_output, b"partial").unwrap();
+        drop(Mp4FrameEncoder {
+            sink: EncoderSink::Pipe(child),
+            format: OutputFormat::Video(Container::Mp4),
+            finished: false,
+            output: dir.path().join("output.mp4"),
+            partial_output: partial_output.clone(),
+            command_line: String::new(),
+        });
+        assert!(!partial_output.exists());
+
+        // signal 0 only checks whether the process exists, which fails once it has been reaped
+        let alive = Command::new("kill")
+            .arg("-0")
+            .arg(pid.to_string())
+            .stderr(Stdio::null())
+            .status()
+            .unwrap()
+            .success();
+        assert!(!alive);
+    }
+}
@@ -1,224 +1,260 @@
+pub mod api;
+#[cfg(feature = "app")]
+mod app;
 mod compute;
 mod ffmpeg;
 
 use std::{
-    collections::HashMap,
-    fs,
-    io::Write,
     path::{Path, PathBuf},
-    sync::{
-        atomic::{AtomicBool, AtomicUsize},
-        Arc, Mutex,
-    },
     time::Duration,
 };
 
-use tauri::{path::BaseDirectory, AppHandle, Emitter, Manager, State};
+#[cfg(feature = "app")]
+pub use app::run;
 
 // job info and state //
 
+/// how serious a detail message is, so the frontend can color and filter them
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+impl Severity {
+    /// prefix for the message in the job log
+    #[cfg(feature = "app")]
+    fn log_prefix(self) -> &'static str {
+        match self {
+            Self::Info => "",
+            Self::Warn => "WARN: ",
+            Self::Error => "ERROR: ",
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, serde::Serialize)]
 #[serde(rename_all = "camelCase")]
-struct SetProgressInfo {
-    progress: Option<usize>,
-    progress_inc: Option<usize>,
-    total: Option<usize>,
-    detail: Option<String>,
+pub struct SetProgressInfo {
+    pub progress: Option<usize>,
+    pub progress_inc: Option<usize>,
+    pub total: Option<usize>,
+    pub detail: Option<String>,
+    /// severity of `detail`
+    pub severity: Severity,
 }
 impl SetProgressInfo {
-    fn detail<S: Into<String>>(s: S) -> Self {
+    pub fn detail<S: Into<String>>(s: S) -> Self {
         Self {
             detail: Some(s.into()),
             ..Default::default()
         }
     }
-}
-struct JobInfo {
-    id: usize,
-    is_cancelled: AtomicBool,
-    app: AppHandle,
-    logfile_path: PathBuf,
-}
-impl JobInfo {
-    pub(crate) fn set_progress(&self, info: SetProgressInfo) {
-        if let Some(detail) = &info.detail {
-            let line = format!(
-                "[{}] {detail}\n",
-                chrono::Local::now().format("%Y-%m-%d %H:%M:%S%.3f")
-            );
-            let mut file = fs::OpenOptions::new()
-                .create(true)
-                .append(true)
-                .open(&self.logfile_path)
-                .expect("open logfile");
-            file.write_all(line.as_bytes()).expect("write to logfile");
+    pub fn warn<S: Into<String>>(s: S) -> Self {
+        Self {
+            severity: Severity::Warn,
+            ..Self::detail(s)
         }
-
-        self.app
-            .emit(&format!("progress:{}", self.id), info)
-            .expect("emit progress");
-    }
-    pub fn cancelled(&self) -> bool {
-        self.is_cancelled.load(std::sync::atomic::Ordering::Relaxed)
     }
-    pub fn cancel_result(&self) -> anyhow::Result<()> {
-        if self.cancelled() {
-            anyhow::bail!("job is cancelled")
+    pub fn error<S: Into<String>>(s: S) -> Self {
+        Self {
+            severity: Severity::Error,
+            ..Self::detail(s)
         }
-        Ok(())
     }
-    pub fn resolve_resource<P: AsRef<Path>>(&self, path: P) -> PathBuf {
-        self.app
-            .path()
-            .resolve(path, BaseDirectory::Resource)
-            .expect("resolve resource path")
+}
+/// the error from `cancel_result`, so a cancelled job can be told apart from a failed one
+#[derive(Debug)]
+pub struct Cancelled;
+impl std::fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("job is cancelled")
     }
 }
-struct Jobs {
-    id_inc: AtomicUsize,
-    active: Mutex<HashMap<usize, Arc<JobInfo>>>,
+impl std::error::Error for Cancelled {}
+impl Cancelled {
+    /// whether `err` was caused by the job being cancelled, under any amount of context
+    pub fn caused(err: &anyhow::Error) -> bool {
+        err.chain().any(|cause| cause.is::<Cancelled>())
+    }
 }
 
-// job options //
+/// what the compute pipeline reports progress to and checks for cancellation, which `JobInfo`
+/// implements for the app, and which can be implemented to run the pipeline without tauri
+pub trait JobContext: Send + Sync {
+    fn set_progress(&self, info: SetProgressInfo);
+    fn cancelled(&self) -> bool;
+    /// the absolute path of a bundled resource, like the glyph config
+    fn resolve_resource(&self, path: &str) -> PathBuf;
+    /// count `bytes` more of output towards the job's summary
+    fn add_bytes_written(&self, _bytes: u64) {}
 
-#[derive(Debug, serde::Deserialize, PartialEq, Eq)]
-#[serde(rename_all = "lowercase")]
-enum TimelapseType {
-    None,
-    Jpg,
-    Mp4,
-}
-#[derive(Debug, serde::Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct TimelapseOptions {
-    typ: TimelapseType,
-    length: u64,
-    fps: u32,
-    skip: Option<u32>,
-}
-
-#[derive(Debug, serde::Deserialize)]
-struct ExportOptions {
-    enabled: bool,
-    location: bool,
+    fn cancel_result(&self) -> anyhow::Result<()> {
+        if self.cancelled() {
+            return Err(Cancelled.into());
+        }
+        Ok(())
+    }
+    /// sleep for `dur`, returning early with `false` if the job is cancelled in the meantime
+    fn interruptible_sleep(&self, dur: Duration) -> bool {
+        interruptible_sleep(|| self.cancelled(), dur)
+    }
+    /// block while the volume holding `dir` has fewer than `min_free` bytes available, so a long
+    /// job pauses instead of failing partway through writing its output
+    fn wait_for_disk_space(&self, dir: &Path, min_free: u64) -> anyhow::Result<()> {
+        wait_for_space(
+            |dur| self.interruptible_sleep(dur),
+            &|info| self.set_progress(info),
+            || {
+                fs4::available_space(dir)
+                    .map_err(|e| anyhow::anyhow!("read available space for {:?}: {}", dir, e))
+            },
+            min_free,
+            DISK_SPACE_POLL_INTERVAL,
+        )
+    }
+    /// log anything ffmpeg printed in verbose mode while working on `subject` on this thread
+    fn log_ffmpeg_diagnostics(&self, subject: &Path) {
+        for output in ffmpeg::take_diagnostics() {
+            self.set_progress(SetProgressInfo::warn(format!(
+                "ffmpeg output for {:?}:\n{}",
+                subject, output
+            )));
+        }
+    }
 }
 
-// job commands //
-
-#[tauri::command]
-fn start_job(
-    app: AppHandle,
-    jobs: State<Jobs>,
-    threads: usize,
-    input_path: String,
-    output_path: String,
-    timelapse: TimelapseOptions,
-    export: ExportOptions,
-) -> usize {
-    // create the output directory
-    std::fs::create_dir_all(&output_path).expect("create output directory");
-
-    // create the JobInfo struct for this job
-    let id = jobs
-        .id_inc
-        .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
-    let info = Arc::new(JobInfo {
-        id,
-        is_cancelled: AtomicBool::new(false),
-        app,
-        logfile_path: Into::<PathBuf>::into(&output_path).join("output.log"),
-    });
-    // add the JobInfo struct to the list of currently active jobs
-    {
-        let mut job_map = jobs.active.lock().unwrap();
-        job_map.insert(info.id, info.clone());
-    }
-
-    let info_clone = info.clone();
-    let run_job = move || -> anyhow::Result<()> {
-        let job = compute::ProcessClipsJob::new(threads, Arc::clone(&info_clone), &input_path)?;
-        if timelapse.typ != TimelapseType::None {
-            let typ = match timelapse.typ {
-                TimelapseType::Jpg => compute::TimelapseType::Jpg,
-                TimelapseType::Mp4 => compute::TimelapseType::Mp4,
-                _ => unreachable!(),
-            };
-            let length = Duration::from_secs(timelapse.length);
-            job.create_timelapse(
-                Arc::clone(&info_clone),
-                typ,
-                length,
-                timelapse.fps,
-                timelapse.skip,
-                &output_path,
-            )?;
+/// how often a job paused by `wait_for_disk_space` checks the free space again
+const DISK_SPACE_POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// poll `available` every `interval` until it is at least `min_free` bytes, reporting when the
+/// job pauses and resumes
+fn wait_for_space(
+    mut sleep: impl FnMut(Duration) -> bool,
+    report: &dyn Fn(SetProgressInfo),
+    mut available: impl FnMut() -> anyhow::Result<u64>,
+    min_free: u64,
+    interval: Duration,
+) -> anyhow::Result<()> {
+    let mb = |bytes: u64| bytes as f64 / 1024.0 / 1024.0;
+    let mut paused = false;
+    loop {
+        let free = available()?;
+        if free >= min_free {
+            if paused {
+                report(SetProgressInfo::detail(format!(
+                    "resuming with {:.0}MB free on the output volume",
+                    mb(free)
+                )));
+            }
+            return Ok(());
         }
-        if export.enabled {
-            job.export_data(info_clone, export.location, &output_path)?;
+        if !paused {
+            report(SetProgressInfo::warn(format!(
+                "pausing until {:.0}MB is free on the output volume, which only has {:.0}MB left",
+                mb(min_free),
+                mb(free)
+            )));
+            paused = true;
         }
-        Ok(())
-    };
-
-    tauri::async_runtime::spawn_blocking(move || {
-        if let Err(e) = run_job() {
-            let panic_msg = format!("----- PANIC -----\n{:?}\n", e);
-            info.set_progress(SetProgressInfo::detail(panic_msg.clone()));
-            eprintln!("{}", panic_msg);
+        if !sleep(interval) {
+            return Err(Cancelled.into());
         }
-        info.is_cancelled
-            .store(true, std::sync::atomic::Ordering::Relaxed);
-    });
-    id
+    }
 }
+/// how often `interruptible_sleep` checks for cancellation
+const SLEEP_POLL_INTERVAL: Duration = Duration::from_millis(50);
 
-#[tauri::command]
-fn cancel_job(job_id: usize, jobs: State<Jobs>) -> bool {
-    let mut job_map = jobs.active.lock().unwrap();
-    let info = job_map.remove(&job_id);
-    if let Some(ji) = &info {
-        ji.is_cancelled
-            .store(true, std::sync::atomic::Ordering::Relaxed);
+fn interruptible_sleep(cancelled: impl Fn() -> bool, dur: Duration) -> bool {
+    let deadline = std::time::Instant::now() + dur;
+    loop {
+        if cancelled() {
+            return false;
+        }
+        let now = std::time::Instant::now();
+        if now >= deadline {
+            return true;
+        }
+        std::thread::sleep(SLEEP_POLL_INTERVAL.min(deadline - now));
     }
-    info.is_some()
 }
 
-// other commands //
+#[cfg(test)]
+mod tests {
+    use super::{interruptible_sleep, wait_for_space, Cancelled, Severity};
+    use anyhow::Context;
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::{Duration, Instant},
+    };
 
-#[tauri::command]
-fn get_parallelism() -> usize {
-    std::thread::available_parallelism()
-        .map(|x| x.get())
-        .unwrap_or(1)
-}
+    #[test]
+    fn sleep_runs_to_completion() {
+        let cancelled = AtomicBool::new(false);
+        let start = Instant::now();
+        assert!(interruptible_sleep(
+            || cancelled.load(Ordering::Relaxed),
+            Duration::from_millis(120)
+        ));
+        assert!(start.elapsed() >= Duration::from_millis(120));
+    }
 
-#[tauri::command]
-fn read_file(filepath: &Path) -> String {
-    std::fs::read_to_string(filepath).expect("read file from filepath")
-}
+    #[test]
+    fn sleep_returns_early_when_cancelled() {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let cancelled_clone = Arc::clone(&cancelled);
+        let canceller = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            cancelled_clone.store(true, Ordering::Relaxed);
+        });
 
-// init //
+        let start = Instant::now();
+        assert!(!interruptible_sleep(
+            || cancelled.load(Ordering::Relaxed),
+            Duration::from_secs(10)
+        ));
+        assert!(start.elapsed() < Duration::from_secs(5));
+        canceller.join().unwrap();
+    }
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    let jobs_state = Jobs {
-        id_inc: AtomicUsize::new(1),
-        active: Mutex::new(HashMap::new()),
-    };
+    #[test]
+    fn low_space_pauses_until_enough_is_free() {
+        let reports = Mutex::new(Vec::new());
+        let report = |info: super::SetProgressInfo| reports.lock().unwrap().push(info.severity);
+        let mut free = [10, 50, 200].into_iter();
+        let mut sleeps = 0;
+        wait_for_space(
+            |_| {
+                sleeps += 1;
+                true
+            },
+            &report,
+            || Ok(free.next().unwrap()),
+            100,
+            Duration::ZERO,
+        )
+        .unwrap();
+        assert_eq!(sleeps, 2);
+        // one warning when pausing and one note when resuming, however long it waits
+        assert_eq!(*reports.lock().unwrap(), [Severity::Warn, Severity::Info]);
 
-    tauri::Builder::default()
-        .plugin(tauri_plugin_dialog::init())
-        .plugin(tauri_plugin_opener::init())
-        .setup(|app| {
-            ffmpeg::set_paths(app.handle())?;
-            Ok(())
-        })
-        .manage(jobs_state)
-        .invoke_handler(tauri::generate_handler![
-            start_job,
-            cancel_job,
-            get_parallelism,
-            read_file,
-        ])
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+        let cancelled = wait_for_space(|_| false, &report, || Ok(0), 100, Duration::ZERO);
+        assert!(Cancelled::caused(&cancelled.unwrap_err()));
+    }
+
+    #[test]
+    fn cancellation_is_recognized_under_context() {
+        let cancelled: anyhow::Result<()> = Err(Cancelled.into());
+        let err = cancelled
+            .context("extract frame 3")
+            .context("create timelapse")
+            .unwrap_err();
+        assert!(Cancelled::caused(&err));
+        assert!(!Cancelled::caused(&anyhow::anyhow!("job is cancelled")));
+    }
 }